@@ -102,9 +102,9 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
         let selection = spi.select(&mut chip);
         let tx_buf = [1, 2, 3, 4].as_ref();
         let mut rx_buf = [0;4];
-        spi.write(tx_buf).root_wait();
-        spi.read(&mut rx_buf).root_wait();
-        spi.xfer(tx_buf, &mut rx_buf).root_wait();
+        spi.write(tx_buf).root_wait().unwrap();
+        spi.read(&mut rx_buf).root_wait().unwrap();
+        spi.xfer(tx_buf, &mut rx_buf).root_wait().unwrap();
         drop(selection); // drop() deselects chip.
     }
 
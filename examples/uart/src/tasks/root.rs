@@ -98,7 +98,7 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
 
     {
         let mut tx = tx_drv.start();
-        tx.write(b"Write a lowercase word:\n").root_wait();
+        tx.write(b"Write a lowercase word:\n").root_wait().unwrap();
         tx.flush().root_wait();
     }
 
@@ -134,9 +134,9 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
         let mut tx = tx_drv.start();
 
         dbg1.set();
-        tx.write(upper.into_bytes().as_ref()).root_wait();
+        tx.write(upper.into_bytes().as_ref()).root_wait().unwrap();
         dbg1.clear();
-        tx.write(b"\n").root_wait();
+        tx.write(b"\n").root_wait().unwrap();
         dbg1.set();
         tx.flush().root_wait(); // Wait for the actual uart transmission to complete
         dbg1.clear();
@@ -0,0 +1,410 @@
+use crate::{diverged::DmaChDiverged, DmaChCfg, DmaChNum, DmaError, DmaItem, DmaStChToken};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use displaydoc::Display;
+use drone_core::{fib::FiberStreamRing, token::Token};
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::dma::ch::{traits::*, DmaChMap};
+use futures::prelude::*;
+
+/// Double buffered receiver or transmitter error.
+#[derive(Display, Debug, Copy, Clone)]
+pub enum DoubleBufError {
+    /// The dma controller swapped buffers before the previous buffer was released.
+    Overrun,
+    /// The dma stream failed: {0}
+    Dma(DmaError),
+    /// The stream was aborted.
+    Aborted,
+}
+
+/// Double buffered peripheral-to-memory dma receiver.
+///
+/// The dma controller fills one buffer while the other is processed,
+/// and the buffers are swapped by hardware when a buffer is full, see RM0090 §10.3.10.
+pub struct DmaDoubleBufRx<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem>(DoubleBuf<DmaCh, DmaInt, T>);
+
+/// Double buffered memory-to-peripheral dma transmitter.
+///
+/// The dma controller transmits one buffer while the other is filled,
+/// and the buffers are swapped by hardware when a buffer is transmitted, see RM0090 §10.3.10.
+pub struct DmaDoubleBufTx<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem>(DoubleBuf<DmaCh, DmaInt, T>);
+
+/// A double buffer mode stream, in either direction.
+struct DoubleBuf<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> {
+    dma: DmaChDiverged<DmaCh>,
+    dma_int: DmaInt,
+    bufs: [Box<[T]>; 2],
+    ndt: usize,
+    /// The index of each completed buffer, captured when its transfer complete interrupt is handled.
+    done_stream: FiberStreamRing<Result<usize, DmaError>>,
+    /// Set to complete the fiber feeding `done_stream` when the stream is disabled.
+    done_stop: Arc<AtomicBool>,
+    /// Any buffer completed event or error that occurred before the last buffer was released.
+    pending: Option<Result<usize, DmaError>>,
+    running: bool,
+}
+
+/// A full buffer in a double buffered receiver.
+/// The buffer is handed back to the dma controller when dropped.
+pub struct DmaBufFull<'a, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> {
+    rx: &'a mut DoubleBuf<DmaCh, DmaInt, T>,
+    /// The index of the full buffer, i.e. 0 or 1.
+    pub index: usize,
+}
+
+/// A transmitted buffer in a double buffered transmitter, to be filled with the next data.
+/// The buffer is handed back to the dma controller when dropped.
+pub struct DmaBufFree<'a, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> {
+    tx: &'a mut DoubleBuf<DmaCh, DmaInt, T>,
+    /// The index of the transmitted buffer, i.e. 0 or 1.
+    pub index: usize,
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> DmaDoubleBufRx<DmaCh, DmaInt, T> {
+    /// Start reception into `bufs` from the peripheral data register at `per_addr`.
+    /// The two buffers must have the same length, not exceeding 65535 peripheral data items.
+    ///
+    /// The memory data size is given by the buffer item type.
+    /// The peripheral data size, fifo and bursts are taken from the channel transfer configuration if in fifo mode,
+    /// and otherwise the peripheral data size is equal to the memory data size.
+    ///
+    /// The stream is enabled when this function returns,
+    /// and the caller must enable the dma request in the peripheral afterwards, see AN4031 §4.3.
+    ///
+    /// # Safety
+    ///
+    /// `per_addr` must be the address of the data register for the peripheral
+    /// that is routed to the dma stream channel `DmaStCh`.
+    pub unsafe fn start<DmaStCh: DmaStChToken>(
+        cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        per_addr: u32,
+        bufs: [Box<[T]>; 2],
+    ) -> Self
+    where
+        DmaCh: DmaChNum,
+    {
+        Self(DoubleBuf::start(cfg, per_addr, bufs, 0b00)) // peripheral-to-memory
+    }
+
+    /// Wait for the next buffer to become full.
+    ///
+    /// The returned buffer must be dropped before the dma controller completes the other buffer,
+    /// otherwise the dma controller has started to overwrite the buffer while it was processed,
+    /// and the next call returns [`DoubleBufError::Overrun`].
+    ///
+    /// The stream is restarted from the first buffer if it has failed,
+    /// and [`DoubleBufError::Aborted`] is returned once the stream is aborted.
+    pub async fn next(&mut self) -> Result<DmaBufFull<'_, DmaCh, DmaInt, T>, DoubleBufError> {
+        let index = self.0.next().await?;
+        Ok(DmaBufFull { rx: &mut self.0, index })
+    }
+
+    /// Stop the reception and return the buffers.
+    ///
+    /// The caller must disable the dma request in the peripheral afterwards, see AN4031 §4.1.
+    pub fn stop(self) -> [Box<[T]>; 2] {
+        self.0.stop()
+    }
+
+    /// Disable the stream if it is running.
+    pub fn abort(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> DmaDoubleBufTx<DmaCh, DmaInt, T> {
+    /// Start transmission from `bufs` to the peripheral data register at `per_addr`,
+    /// where both buffers must be filled with the first data to transmit.
+    /// The two buffers must have the same length, not exceeding 65535 peripheral data items.
+    ///
+    /// The memory data size is given by the buffer item type.
+    /// The peripheral data size, fifo and bursts are taken from the channel transfer configuration if in fifo mode,
+    /// and otherwise the peripheral data size is equal to the memory data size.
+    ///
+    /// The stream is enabled when this function returns,
+    /// and the caller must enable the dma request in the peripheral afterwards, see AN4031 §4.3.
+    ///
+    /// # Safety
+    ///
+    /// `per_addr` must be the address of the data register for the peripheral
+    /// that is routed to the dma stream channel `DmaStCh`.
+    pub unsafe fn start<DmaStCh: DmaStChToken>(
+        cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        per_addr: u32,
+        bufs: [Box<[T]>; 2],
+    ) -> Self
+    where
+        DmaCh: DmaChNum,
+    {
+        Self(DoubleBuf::start(cfg, per_addr, bufs, 0b01)) // memory-to-peripheral
+    }
+
+    /// Wait for the next buffer to be transmitted, so that it can be filled with the next data.
+    ///
+    /// The returned buffer must be dropped before the dma controller completes the other buffer,
+    /// otherwise the dma controller has started to transmit the buffer while it was filled,
+    /// and the next call returns [`DoubleBufError::Overrun`].
+    ///
+    /// The stream is restarted from the first buffer if it has failed,
+    /// and [`DoubleBufError::Aborted`] is returned once the stream is aborted.
+    pub async fn next(&mut self) -> Result<DmaBufFree<'_, DmaCh, DmaInt, T>, DoubleBufError> {
+        let index = self.0.next().await?;
+        Ok(DmaBufFree { tx: &mut self.0, index })
+    }
+
+    /// Stop the transmission and return the buffers.
+    ///
+    /// The caller must disable the dma request in the peripheral afterwards, see AN4031 §4.1.
+    pub fn stop(self) -> [Box<[T]>; 2] {
+        self.0.stop()
+    }
+
+    /// Disable the stream if it is running.
+    pub fn abort(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> DoubleBuf<DmaCh, DmaInt, T> {
+    /// Start the stream in the direction `dir`, i.e. the DIR value in CR.
+    unsafe fn start<DmaStCh: DmaStChToken>(
+        cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        per_addr: u32,
+        bufs: [Box<[T]>; 2],
+        dir: u32,
+    ) -> Self
+    where
+        DmaCh: DmaChNum,
+    {
+        let len = bufs[0].len();
+        assert_eq!(len, bufs[1].len(), "The buffers must have equal length");
+        assert!(len > 0, "The buffers must not be empty");
+
+        let DmaChCfg {
+            dma_ch,
+            dma_int,
+            dma_pl,
+            dma_transfer,
+            ..
+        } = cfg;
+        let dma: DmaChDiverged<DmaCh> = dma_ch.into();
+        let psize = match dma_transfer.fifo_threshold() {
+            Some(_) => dma_transfer.psize(),
+            None => T::SIZE,
+        };
+        // The number of data items is given in peripheral data size units.
+        let ndt = len * T::SIZE.bytes() / psize.bytes();
+        assert!(ndt <= 0xFFFF, "The buffers are too large");
+
+        dma.dma_cpar.store_reg(|r, v| {
+            r.pa().write(v, per_addr); // peripheral address
+        });
+        dma.dma_ccr.store_reg(|r, v| {
+            r.chsel().write(v, DmaStCh::NUM); // channel selection
+            r.pl().write(v, dma_pl); // priority level
+            r.msize().write(v, T::SIZE.bits()); // memory data size
+            r.psize().write(v, psize.bits()); // peripheral data size
+            r.mburst().write(v, dma_transfer.mburst().bits()); // memory burst
+            r.pburst().write(v, dma_transfer.pburst().bits()); // peripheral burst
+            r.minc().set(v); // memory address pointer is incremented after each data transfer
+            r.pinc().clear(v); // peripheral address pointer is fixed
+            r.dir().write(v, dir); // data transfer direction
+            r.tcie().set(v); // transfer complete interrupt enable
+            r.teie().set(v); // transfer error interrupt enable
+
+            r.circ().set(v); // circular mode (required in double buffer mode)
+            r.dbm().set(v); // double buffer mode
+        });
+        dma.dma_cfcr.store_reg(|r, v| {
+            if let Some(threshold) = dma_transfer.fifo_threshold() {
+                r.dmdis().set(v); // direct mode disabled
+                r.fth().write(v, threshold.bits()); // fifo threshold
+            }
+        });
+        dma.dma_cm0ar.store_reg(|r, v| {
+            r.m0a().write(v, bufs[0].as_ptr() as u32);
+        });
+        dma.dma_cm1ar.store_reg(|r, v| {
+            r.m1a().write(v, bufs[1].as_ptr() as u32);
+        });
+
+        let (done_stream, done_stop) = Self::enable(&dma, dma_int, ndt);
+
+        Self {
+            dma,
+            dma_int,
+            bufs,
+            ndt,
+            done_stream,
+            done_stop,
+            pending: None,
+            running: true,
+        }
+    }
+
+    /// Wait for the next buffer to be completed, and get its index.
+    async fn next(&mut self) -> Result<usize, DoubleBufError> {
+        let done = match self.pending.take() {
+            // The other buffer was completed before the last buffer was released.
+            Some(Ok(_)) => return Err(DoubleBufError::Overrun),
+            Some(Err(err)) => Err(err),
+            None => self.done_stream.next().await.ok_or(DoubleBufError::Aborted)?,
+        };
+        match done {
+            Ok(index) => match self.done_stream.next().now_or_never() {
+                // More than one buffer was completed since the last call.
+                Some(Some(Ok(_))) => Err(DoubleBufError::Overrun),
+                Some(Some(Err(err))) => {
+                    self.pending = Some(Err(err));
+                    Err(DoubleBufError::Overrun)
+                }
+                _ => Ok(index),
+            },
+            Err(err) => {
+                // Reset and restart the stream so that the transfer can proceed.
+                // The fiber has completed with the error.
+                self.dma.reset();
+                let (done_stream, done_stop) = Self::enable(&self.dma, self.dma_int, self.ndt);
+                self.done_stream = done_stream;
+                self.done_stop = done_stop;
+                Err(DoubleBufError::Dma(err))
+            }
+        }
+    }
+
+    fn stop(mut self) -> [Box<[T]>; 2] {
+        self.abort();
+        [mem::take(&mut self.bufs[0]), mem::take(&mut self.bufs[1])]
+    }
+
+    /// Disable the stream if it is running.
+    fn abort(&mut self) {
+        if self.running {
+            // Disabling the stream sets the transfer complete flag, which lets the fiber see the stop request.
+            self.done_stop.store(true, Ordering::Release);
+            self.dma.abort();
+            self.running = false;
+        }
+    }
+
+    /// Enable the stream starting from the first buffer.
+    fn enable(
+        dma: &DmaChDiverged<DmaCh>,
+        dma_int: DmaInt,
+        ndt: usize,
+    ) -> (FiberStreamRing<Result<usize, DmaError>>, Arc<AtomicBool>) {
+        dma.dma_ccr.modify_reg(|r, v| {
+            r.ct().clear(v); // the first transfer is into memory 0
+        });
+        dma.dma_cndtr.store_reg(|r, v| {
+            r.ndt().write(v, ndt as u32);
+        });
+
+        // A transfer complete interrupt is generated every time a buffer is completed.
+        let dma_isr_tcif = dma.dma_isr_tcif;
+        let dma_ifcr_ctcif = dma.dma_ifcr_ctcif;
+        let flags = dma.err_flags();
+        let done_stop = Arc::new(AtomicBool::new(false));
+        let stop = done_stop.clone();
+        // The fiber only reads the current target (CT) bit.
+        let dma_ccr = unsafe { DmaCh::SDmaCcr::take() };
+        let done_stream = dma_int.add_saturating_stream(
+            2,
+            fib::new_fn(move || {
+                if stop.load(Ordering::Acquire) {
+                    return fib::Complete(None);
+                }
+                // Load _entire_ interrupt status register.
+                // The value is not masked to TCIF.
+                let val = dma_isr_tcif.load_val();
+                if let Some(err) = flags.take(&val) {
+                    fib::Complete(Some(Err(err)))
+                } else if dma_isr_tcif.read(&val) {
+                    // Clear transfer completed interrupt flag.
+                    dma_ifcr_ctcif.set_bit();
+                    // The hardware has swapped to the other buffer when the transfer complete interrupt is generated,
+                    // so the current target (CT) is the buffer _not_ completed.
+                    fib::Yielded(Some(Ok(if dma_ccr.ct().read_bit() { 0 } else { 1 })))
+                } else {
+                    fib::Yielded(None)
+                }
+            }),
+        );
+
+        // Clear transfer completed interrupt flag.
+        dma.dma_ifcr_ctcif.set_bit();
+
+        // Clear any outstanding fifo error interrupt flag by settings its clear register.
+        dma.dma_ifcr_cfeif.set_bit();
+
+        // Enable stream.
+        dma.dma_ccr.modify_reg(|r, v| r.en().set(v));
+
+        (done_stream, done_stop)
+    }
+
+    fn release(&mut self) {
+        // The buffer was processed too slow if the other buffer is already completed.
+        if let Some(Some(res)) = self.done_stream.next().now_or_never() {
+            self.pending = Some(res);
+        }
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> Drop for DoubleBuf<DmaCh, DmaInt, T> {
+    /// Stop the stream.
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> Deref for DmaBufFull<'_, DmaCh, DmaInt, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.rx.bufs[self.index]
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> DerefMut
+    for DmaBufFull<'_, DmaCh, DmaInt, T>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.rx.bufs[self.index]
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> Drop for DmaBufFull<'_, DmaCh, DmaInt, T> {
+    /// Hand the buffer back to the dma controller.
+    fn drop(&mut self) {
+        self.rx.release();
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> Deref for DmaBufFree<'_, DmaCh, DmaInt, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx.bufs[self.index]
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> DerefMut
+    for DmaBufFree<'_, DmaCh, DmaInt, T>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx.bufs[self.index]
+    }
+}
+
+impl<DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> Drop for DmaBufFree<'_, DmaCh, DmaInt, T> {
+    /// Hand the buffer back to the dma controller.
+    fn drop(&mut self) {
+        self.tx.release();
+    }
+}
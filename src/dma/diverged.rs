@@ -0,0 +1,315 @@
+use crate::{config::DmaTransfer, DmaChNum, DmaError, DmaErrorKind};
+use core::mem::size_of_val;
+use drone_core::fib::FiberFuture;
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::dma::ch::{
+    CDmaCndtr, DmaChMap, DmaChPeriph, SDmaCcr, SDmaCfcr, SDmaCm0Ar, SDmaCpar,
+};
+
+/// The registers of a dma stream, diverged for use by peripheral drivers.
+///
+/// The interrupt flags and the data counter are copy tokens so that they can be moved into fibers.
+#[allow(missing_docs)]
+pub struct DmaChDiverged<DmaCh: DmaChMap> {
+    pub dma_ccr: DmaCh::SDmaCcr,
+    pub dma_cfcr: DmaCh::SDmaCfcr,
+    pub dma_cm0ar: DmaCh::SDmaCm0Ar,
+    pub dma_cm1ar: DmaCh::SDmaCm1Ar,
+    pub dma_cndtr: DmaCh::CDmaCndtr,
+    pub dma_cpar: DmaCh::SDmaCpar,
+    pub dma_ifcr_cdmeif: DmaCh::CDmaIfcrCdmeif,
+    pub dma_ifcr_cfeif: DmaCh::CDmaIfcrCfeif,
+    pub dma_ifcr_chtif: DmaCh::CDmaIfcrChtif,
+    pub dma_ifcr_ctcif: DmaCh::CDmaIfcrCtcif,
+    pub dma_ifcr_cteif: DmaCh::CDmaIfcrCteif,
+    pub dma_isr_dmeif: DmaCh::CDmaIsrDmeif,
+    pub dma_isr_feif: DmaCh::CDmaIsrFeif,
+    pub dma_isr_htif: DmaCh::CDmaIsrHtif,
+    pub dma_isr_tcif: DmaCh::CDmaIsrTcif,
+    pub dma_isr_teif: DmaCh::CDmaIsrTeif,
+    /// The dma controller number, i.e. 1 or 2.
+    dma: u32,
+    /// The stream number within the dma controller.
+    stream: u32,
+}
+
+/// The dummy item written when reading, aligned for any memory data size.
+static DUMMY: u32 = 0;
+
+impl<DmaCh: DmaChMap> DmaChDiverged<DmaCh> {
+    /// Initialize the stream for peripheral-to-memory transfers from the data register `per_dr`.
+    pub fn init_dma_rx(&self, per_dr: u32, chsel: u32, priority: u32, transfer: DmaTransfer) {
+        self.dma_cpar.store_reg(|r, v| {
+            r.pa().write(v, per_dr); // peripheral address
+        });
+        self.dma_ccr.store_reg(|r, v| {
+            r.chsel().write(v, chsel); // channel selection
+            r.pl().write(v, priority); // priority level
+            r.msize().write(v, transfer.msize().bits()); // memory data size
+            r.psize().write(v, transfer.psize().bits()); // peripheral data size
+            r.mburst().write(v, transfer.mburst().bits()); // memory burst
+            r.pburst().write(v, transfer.pburst().bits()); // peripheral burst
+            r.pinc().clear(v); // peripheral address pointer is fixed
+            r.circ().clear(v); // normal mode
+            r.dir().write(v, 0b00); // peripheral-to-memory
+            r.tcie().set(v); // transfer complete interrupt enable
+            r.teie().set(v); // transfer error interrupt enable
+        });
+        self.init_fifo(transfer);
+    }
+
+    /// Initialize the stream for memory-to-peripheral transfers to the data register `per_dr`.
+    pub fn init_dma_tx(&self, per_dr: u32, chsel: u32, priority: u32, transfer: DmaTransfer) {
+        self.dma_cpar.store_reg(|r, v| {
+            r.pa().write(v, per_dr); // peripheral address
+        });
+        self.dma_ccr.store_reg(|r, v| {
+            r.chsel().write(v, chsel); // channel selection
+            r.pl().write(v, priority); // priority level
+            r.msize().write(v, transfer.msize().bits()); // memory data size
+            r.psize().write(v, transfer.psize().bits()); // peripheral data size
+            r.mburst().write(v, transfer.mburst().bits()); // memory burst
+            r.pburst().write(v, transfer.pburst().bits()); // peripheral burst
+            r.pinc().clear(v); // peripheral address pointer is fixed
+            r.circ().clear(v); // normal mode
+            r.dir().write(v, 0b01); // memory-to-peripheral
+            r.tcie().set(v); // transfer complete interrupt enable
+            r.teie().set(v); // transfer error interrupt enable
+        });
+        self.init_fifo(transfer);
+    }
+
+    fn init_fifo(&self, transfer: DmaTransfer) {
+        self.dma_cfcr.store_reg(|r, v| {
+            if let Some(threshold) = transfer.fifo_threshold() {
+                r.dmdis().set(v); // direct mode disabled
+                r.fth().write(v, threshold.bits()); // fifo threshold
+            }
+        });
+    }
+
+    /// The number of bytes in a single peripheral data item.
+    pub fn psize_bytes(&self) -> usize {
+        1 << self.dma_ccr.psize().read_bits()
+    }
+
+    /// Setup and enable a transfer from/to `buf`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must outlive the transfer.
+    pub unsafe fn setup_stream<Word>(&self, buf: &[Word]) {
+        // Memory address pointer is incremented after each data transfer
+        self.dma_ccr.modify_reg(|r, v| {
+            r.minc().set(v);
+        });
+
+        // Set buffer memory addres.
+        self.dma_cm0ar.store_reg(|r, v| {
+            r.m0a().write(v, buf.as_ptr() as u32);
+        });
+
+        // Set number of peripheral data items to transfer.
+        self.dma_cndtr.store_reg(|r, v| {
+            r.ndt().write(v, (size_of_val(buf) / self.psize_bytes()) as u32);
+        });
+
+        // Clear transfer completed interrupt flag.
+        self.dma_ifcr_ctcif.set_bit();
+
+        // Enable stream.
+        self.dma_ccr.modify_reg(|r, v| r.en().set(v));
+    }
+
+    /// Setup and enable a transfer of `len` bytes from/to a dummy item.
+    ///
+    /// # Safety
+    ///
+    /// The stream must be initialized for the peripheral that consumes/produces the dummy items.
+    pub unsafe fn setup_dummy_stream(&self, len: usize) {
+        // Memory address pointer is fixed
+        self.dma_ccr.modify_reg(|r, v| {
+            r.minc().clear(v);
+        });
+
+        // Set buffer memory addres.
+        self.dma_cm0ar.store_reg(|r, v| {
+            r.m0a().write(v, &DUMMY as *const u32 as u32);
+        });
+
+        // Set number of peripheral data items to transfer.
+        self.dma_cndtr.store_reg(|r, v| {
+            r.ndt().write(v, (len / self.psize_bytes()) as u32);
+        });
+
+        // Clear transfer completed interrupt flag.
+        self.dma_ifcr_ctcif.set_bit();
+
+        // Enable stream.
+        self.dma_ccr.modify_reg(|r, v| r.en().set(v));
+    }
+
+    /// Create a future that completes when the transfer is completed or has failed.
+    pub fn transfer_future<DmaInt: IntToken>(
+        &self,
+        dma_int: DmaInt,
+    ) -> FiberFuture<Result<(), DmaError>> {
+        let dma_isr_tcif = self.dma_isr_tcif;
+        let dma_ifcr_ctcif = self.dma_ifcr_ctcif;
+        let flags = self.err_flags();
+        dma_int.add_future(fib::new_fn(move || {
+            // Load _entire_ interrupt status register.
+            // The value is not masked to TCIF.
+            let val = dma_isr_tcif.load_val();
+            if let Some(err) = flags.take(&val) {
+                fib::Complete(Err(err))
+            } else if dma_isr_tcif.read(&val) {
+                // Clear transfer completed interrupt flag.
+                dma_ifcr_ctcif.set_bit();
+                fib::Complete(Ok(()))
+            } else {
+                fib::Yielded(())
+            }
+        }))
+    }
+
+    /// Get the error flags for use within a fiber.
+    pub fn err_flags(&self) -> DmaErrFlags<DmaCh> {
+        DmaErrFlags {
+            dma: self.dma,
+            stream: self.stream,
+            fifo: self.dma_cfcr.dmdis().read_bit(),
+            dma_cndtr: self.dma_cndtr,
+            dma_ifcr_cdmeif: self.dma_ifcr_cdmeif,
+            dma_ifcr_cfeif: self.dma_ifcr_cfeif,
+            dma_ifcr_cteif: self.dma_ifcr_cteif,
+            dma_isr_dmeif: self.dma_isr_dmeif,
+            dma_isr_feif: self.dma_isr_feif,
+            dma_isr_teif: self.dma_isr_teif,
+        }
+    }
+
+    /// Reset the stream after an error so that a new transfer can be started.
+    ///
+    /// This clears the transfer complete flag, so it must only be called
+    /// when no fiber is waiting for the stream, see [`Self::abort`].
+    pub fn reset(&self) {
+        self.abort();
+        self.clear_flags();
+    }
+
+    /// Clear all interrupt flags of the stream.
+    pub fn clear_flags(&self) {
+        self.dma_ifcr_ctcif.set_bit();
+        self.dma_ifcr_chtif.set_bit();
+        self.dma_ifcr_cteif.set_bit();
+        self.dma_ifcr_cdmeif.set_bit();
+        self.dma_ifcr_cfeif.set_bit();
+    }
+
+    /// Disable the stream and wait for any ongoing transfer to be aborted.
+    ///
+    /// A stream disabled by software during a transfer sets its transfer complete flag,
+    /// so a fiber created by [`Self::transfer_future`] completes afterwards.
+    pub fn abort(&self) {
+        // Disable stream.
+        self.dma_ccr.modify_reg(|r, v| r.en().clear(v));
+
+        // Wait until the EN bit in DMA_SxCR register is reset.
+        while self.dma_ccr.en().read_bit() {}
+    }
+}
+
+impl<DmaCh: DmaChNum> From<DmaChPeriph<DmaCh>> for DmaChDiverged<DmaCh> {
+    fn from(periph: DmaChPeriph<DmaCh>) -> Self {
+        let DmaChPeriph {
+            dma_ccr,
+            dma_cfcr,
+            dma_cm0ar,
+            dma_cm1ar,
+            dma_cndtr,
+            dma_cpar,
+            dma_ifcr_cdmeif,
+            dma_ifcr_cfeif,
+            dma_ifcr_chtif,
+            dma_ifcr_ctcif,
+            dma_ifcr_cteif,
+            dma_isr_dmeif,
+            dma_isr_feif,
+            dma_isr_htif,
+            dma_isr_tcif,
+            dma_isr_teif,
+        } = periph;
+        Self {
+            dma_ccr,
+            dma_cfcr,
+            dma_cm0ar,
+            dma_cm1ar,
+            dma_cndtr: dma_cndtr.into_copy(),
+            dma_cpar,
+            dma_ifcr_cdmeif: dma_ifcr_cdmeif.into_copy(),
+            dma_ifcr_cfeif: dma_ifcr_cfeif.into_copy(),
+            dma_ifcr_chtif: dma_ifcr_chtif.into_copy(),
+            dma_ifcr_ctcif: dma_ifcr_ctcif.into_copy(),
+            dma_ifcr_cteif: dma_ifcr_cteif.into_copy(),
+            dma_isr_dmeif: dma_isr_dmeif.into_copy(),
+            dma_isr_feif: dma_isr_feif.into_copy(),
+            dma_isr_htif: dma_isr_htif.into_copy(),
+            dma_isr_tcif: dma_isr_tcif.into_copy(),
+            dma_isr_teif: dma_isr_teif.into_copy(),
+            dma: DmaCh::DMA,
+            stream: DmaCh::STREAM,
+        }
+    }
+}
+
+/// The error flags of a stream.
+pub struct DmaErrFlags<DmaCh: DmaChMap> {
+    dma: u32,
+    stream: u32,
+    fifo: bool,
+    dma_cndtr: DmaCh::CDmaCndtr,
+    dma_ifcr_cdmeif: DmaCh::CDmaIfcrCdmeif,
+    dma_ifcr_cfeif: DmaCh::CDmaIfcrCfeif,
+    dma_ifcr_cteif: DmaCh::CDmaIfcrCteif,
+    dma_isr_dmeif: DmaCh::CDmaIsrDmeif,
+    dma_isr_feif: DmaCh::CDmaIsrFeif,
+    dma_isr_teif: DmaCh::CDmaIsrTeif,
+}
+
+impl<DmaCh: DmaChMap> DmaErrFlags<DmaCh> {
+    /// Take any error flagged in the interrupt status register value `val`, clearing its flag.
+    pub fn take(&self, val: &DmaCh::DmaIsrVal) -> Option<DmaError> {
+        let kind = if self.dma_isr_teif.read(val) {
+            self.dma_ifcr_cteif.set_bit();
+            DmaErrorKind::Transfer
+        } else if self.dma_isr_dmeif.read(val) {
+            self.dma_ifcr_cdmeif.set_bit();
+            DmaErrorKind::DirectMode
+        } else if self.dma_isr_feif.read(val) {
+            self.dma_ifcr_cfeif.set_bit();
+            if !self.fifo {
+                // The fifo error flag is not an error in direct mode.
+                return None;
+            }
+            DmaErrorKind::Fifo
+        } else {
+            return None;
+        };
+        let remaining = self.dma_cndtr.ndt().read_bits() as usize;
+        Some(DmaError {
+            dma: self.dma,
+            stream: self.stream,
+            kind,
+            remaining,
+        })
+    }
+}
+
+impl<DmaCh: DmaChMap> Clone for DmaErrFlags<DmaCh> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+
+impl<DmaCh: DmaChMap> Copy for DmaErrFlags<DmaCh> {}
@@ -37,6 +37,61 @@ stch_token!(DmaStCh5, 5);
 stch_token!(DmaStCh6, 6);
 stch_token!(DmaStCh7, 7);
 
+/// A dma stream identified by its controller and stream number.
+pub trait DmaChNum: DmaChMap {
+    /// The dma controller number, i.e. 1 or 2.
+    const DMA: u32;
+    /// The stream number within the dma controller.
+    const STREAM: u32;
+}
+
+macro_rules! ch_num {
+    ($ch:ident, $dma:expr, $stream:expr) => {
+        impl DmaChNum for drone_stm32_map::periph::dma::ch::$ch {
+            const DMA: u32 = $dma;
+            const STREAM: u32 = $stream;
+        }
+    };
+}
+
+ch_num!(Dma1Ch0, 1, 0);
+ch_num!(Dma1Ch1, 1, 1);
+ch_num!(Dma1Ch2, 1, 2);
+ch_num!(Dma1Ch3, 1, 3);
+ch_num!(Dma1Ch4, 1, 4);
+ch_num!(Dma1Ch5, 1, 5);
+ch_num!(Dma1Ch6, 1, 6);
+ch_num!(Dma1Ch7, 1, 7);
+ch_num!(Dma2Ch0, 2, 0);
+ch_num!(Dma2Ch1, 2, 1);
+ch_num!(Dma2Ch2, 2, 2);
+ch_num!(Dma2Ch3, 2, 3);
+ch_num!(Dma2Ch4, 2, 4);
+ch_num!(Dma2Ch5, 2, 5);
+ch_num!(Dma2Ch6, 2, 6);
+ch_num!(Dma2Ch7, 2, 7);
+
+/// A data item that can be transferred by the dma controller.
+pub trait DmaItem: Copy + Send + Sync + 'static {
+    /// The data size of the item.
+    const SIZE: DataSize;
+}
+
+macro_rules! dma_item {
+    ($ty:ty, $size:ident) => {
+        impl DmaItem for $ty {
+            const SIZE: DataSize = DataSize::$size;
+        }
+    };
+}
+
+dma_item!(u8, Byte);
+dma_item!(i8, Byte);
+dma_item!(u16, HalfWord);
+dma_item!(i16, HalfWord);
+dma_item!(u32, Word);
+dma_item!(i32, Word);
+
 pub mod config {
     use super::*;
 
@@ -56,6 +111,8 @@ pub mod config {
         pub dma_int: DmaInt,
         /// Dma channel priority level.
         pub dma_pl: DmaPrio,
+        /// Dma channel data sizes, fifo and burst configuration.
+        pub dma_transfer: DmaTransfer,
     }
 
     pub enum DmaPrio {
@@ -65,8 +122,317 @@ pub mod config {
         VeryHigh,
     }
 
+    /// Dma data size, used for both the memory (MSIZE) and peripheral (PSIZE) side.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum DataSize {
+        /// Byte (8-bit).
+        Byte,
+        /// Half-word (16-bit).
+        HalfWord,
+        /// Word (32-bit).
+        Word,
+    }
+
+    impl DataSize {
+        /// The number of bytes in a single data item.
+        pub const fn bytes(self) -> usize {
+            match self {
+                DataSize::Byte => 1,
+                DataSize::HalfWord => 2,
+                DataSize::Word => 4,
+            }
+        }
+
+        /// The register value for the MSIZE and PSIZE fields.
+        pub const fn bits(self) -> u32 {
+            match self {
+                DataSize::Byte => 0b00,
+                DataSize::HalfWord => 0b01,
+                DataSize::Word => 0b10,
+            }
+        }
+    }
+
+    /// Dma fifo threshold level.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum FifoThreshold {
+        /// 1/4 full fifo (4 bytes).
+        Quarter,
+        /// 1/2 full fifo (8 bytes).
+        Half,
+        /// 3/4 full fifo (12 bytes).
+        ThreeQuarters,
+        /// Full fifo (16 bytes).
+        Full,
+    }
+
+    impl FifoThreshold {
+        /// The number of bytes in the fifo at the threshold level.
+        pub const fn bytes(self) -> usize {
+            match self {
+                FifoThreshold::Quarter => 4,
+                FifoThreshold::Half => 8,
+                FifoThreshold::ThreeQuarters => 12,
+                FifoThreshold::Full => 16,
+            }
+        }
+
+        /// The register value for the FTH field.
+        pub const fn bits(self) -> u32 {
+            match self {
+                FifoThreshold::Quarter => 0b00,
+                FifoThreshold::Half => 0b01,
+                FifoThreshold::ThreeQuarters => 0b10,
+                FifoThreshold::Full => 0b11,
+            }
+        }
+    }
+
+    /// Dma burst transfer configuration.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Burst {
+        /// Single transfer.
+        Single,
+        /// Incremental burst of 4 beats.
+        Incr4,
+        /// Incremental burst of 8 beats.
+        Incr8,
+        /// Incremental burst of 16 beats.
+        Incr16,
+    }
+
+    impl Burst {
+        /// The number of beats in a single burst.
+        pub const fn beats(self) -> usize {
+            match self {
+                Burst::Single => 1,
+                Burst::Incr4 => 4,
+                Burst::Incr8 => 8,
+                Burst::Incr16 => 16,
+            }
+        }
+
+        /// The register value for the MBURST and PBURST fields.
+        pub const fn bits(self) -> u32 {
+            match self {
+                Burst::Single => 0b00,
+                Burst::Incr4 => 0b01,
+                Burst::Incr8 => 0b10,
+                Burst::Incr16 => 0b11,
+            }
+        }
+
+        /// Get whether a burst of `size` items fits the fifo `threshold`.
+        /// The threshold level must correspond to an integer number of bursts, see RM0090 table 48.
+        pub const fn fits(self, size: DataSize, threshold: FifoThreshold) -> bool {
+            let burst_bytes = self.beats() * size.bytes();
+            burst_bytes <= threshold.bytes() && threshold.bytes() % burst_bytes == 0
+        }
+    }
+
+    /// Dma transfer configuration with data sizes, fifo and burst settings.
+    ///
+    /// A fifo configuration is built with [`DmaTransfer::fifo`],
+    /// where the fifo and burst restrictions in AN4031 §3 are verified at compile time, e.g.
+    /// `DmaTransfer::fifo::<size::HalfWord, size::Word, threshold::Full>().with_mburst::<burst::Incr4>().into()`.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct DmaTransfer {
+        psize: DataSize,
+        msize: DataSize,
+        fifo: Option<FifoThreshold>,
+        pburst: Burst,
+        mburst: Burst,
+    }
+
+    impl DmaTransfer {
+        /// Transfer in direct mode where each peripheral request immediately initiates a transfer from/to memory.
+        /// The memory data size is equal to the peripheral data size and bursts are not possible in direct mode.
+        pub const fn direct(size: DataSize) -> Self {
+            Self {
+                psize: size,
+                msize: size,
+                fifo: None,
+                pburst: Burst::Single,
+                mburst: Burst::Single,
+            }
+        }
+
+        /// Transfer through the fifo with the fifo threshold level `Th`.
+        /// The peripheral and memory data sizes may differ in fifo mode, in which case the data is packed/unpacked.
+        pub fn fifo<P: DataSizeToken, M: DataSizeToken, Th: FifoThresholdToken>(
+        ) -> FifoTransfer<P, M, Th, burst::Single, burst::Single> {
+            FifoTransfer(PhantomData)
+        }
+
+        /// The peripheral data size.
+        pub const fn psize(&self) -> DataSize {
+            self.psize
+        }
+
+        /// The memory data size.
+        pub const fn msize(&self) -> DataSize {
+            self.msize
+        }
+
+        /// The fifo threshold level, or `None` if in direct mode.
+        pub const fn fifo_threshold(&self) -> Option<FifoThreshold> {
+            self.fifo
+        }
+
+        /// The peripheral burst.
+        pub const fn pburst(&self) -> Burst {
+            self.pburst
+        }
+
+        /// The memory burst.
+        pub const fn mburst(&self) -> Burst {
+            self.mburst
+        }
+    }
+
+    /// A fifo mode transfer configuration with its data sizes, threshold and bursts selected by type,
+    /// so that illegal combinations are rejected at compile time.
+    pub struct FifoTransfer<P, M, Th, PB, MB>(PhantomData<(P, M, Th, PB, MB)>);
+
+    impl<P: DataSizeToken, M: DataSizeToken, Th: FifoThresholdToken, PB: BurstToken, MB: BurstToken>
+        FifoTransfer<P, M, Th, PB, MB>
+    {
+        /// Set the peripheral burst.
+        /// A peripheral burst must not exceed the fifo size of 16 bytes.
+        pub fn with_pburst<B: PBurstFits<P>>(self) -> FifoTransfer<P, M, Th, B, MB> {
+            FifoTransfer(PhantomData)
+        }
+
+        /// Set the memory burst.
+        /// The fifo threshold level must correspond to an integer number of memory bursts, see RM0090 table 48.
+        pub fn with_mburst<B: MBurstFits<M, Th>>(self) -> FifoTransfer<P, M, Th, PB, B> {
+            FifoTransfer(PhantomData)
+        }
+    }
+
+    impl<P: DataSizeToken, M: DataSizeToken, Th: FifoThresholdToken, PB: BurstToken, MB: BurstToken>
+        From<FifoTransfer<P, M, Th, PB, MB>> for DmaTransfer
+    {
+        fn from(_: FifoTransfer<P, M, Th, PB, MB>) -> Self {
+            Self {
+                psize: P::SIZE,
+                msize: M::SIZE,
+                fifo: Some(Th::THRESHOLD),
+                pburst: PB::BURST,
+                mburst: MB::BURST,
+            }
+        }
+    }
+
+    /// A data size selected by type.
+    pub trait DataSizeToken {
+        /// The data size.
+        const SIZE: DataSize;
+    }
+
+    /// A fifo threshold level selected by type.
+    pub trait FifoThresholdToken {
+        /// The fifo threshold level.
+        const THRESHOLD: FifoThreshold;
+    }
+
+    /// A burst selected by type.
+    pub trait BurstToken {
+        /// The burst.
+        const BURST: Burst;
+    }
+
+    /// A burst that fits the fifo for the peripheral data size `P`.
+    pub trait PBurstFits<P: DataSizeToken>: BurstToken {}
+
+    /// A burst that fits the fifo threshold `Th` for the memory data size `M`.
+    pub trait MBurstFits<M: DataSizeToken, Th: FifoThresholdToken>: BurstToken {}
+
+    /// Data sizes selected by type.
+    pub mod size {
+        /// Byte (8-bit).
+        pub struct Byte;
+        /// Half-word (16-bit).
+        pub struct HalfWord;
+        /// Word (32-bit).
+        pub struct Word;
+    }
+
+    /// Fifo threshold levels selected by type.
+    pub mod threshold {
+        /// 1/4 full fifo (4 bytes).
+        pub struct Quarter;
+        /// 1/2 full fifo (8 bytes).
+        pub struct Half;
+        /// 3/4 full fifo (12 bytes).
+        pub struct ThreeQuarters;
+        /// Full fifo (16 bytes).
+        pub struct Full;
+    }
+
+    /// Bursts selected by type.
+    pub mod burst {
+        /// Single transfer.
+        pub struct Single;
+        /// Incremental burst of 4 beats.
+        pub struct Incr4;
+        /// Incremental burst of 8 beats.
+        pub struct Incr8;
+        /// Incremental burst of 16 beats.
+        pub struct Incr16;
+    }
+
+    macro_rules! token {
+        ($trait:ident, $const:ident, $ty:ty, $mod:ident::$name:ident) => {
+            impl $trait for $mod::$name {
+                const $const: $ty = <$ty>::$name;
+            }
+        };
+    }
+
+    token!(DataSizeToken, SIZE, DataSize, size::Byte);
+    token!(DataSizeToken, SIZE, DataSize, size::HalfWord);
+    token!(DataSizeToken, SIZE, DataSize, size::Word);
+    token!(FifoThresholdToken, THRESHOLD, FifoThreshold, threshold::Quarter);
+    token!(FifoThresholdToken, THRESHOLD, FifoThreshold, threshold::Half);
+    token!(FifoThresholdToken, THRESHOLD, FifoThreshold, threshold::ThreeQuarters);
+    token!(FifoThresholdToken, THRESHOLD, FifoThreshold, threshold::Full);
+    token!(BurstToken, BURST, Burst, burst::Single);
+    token!(BurstToken, BURST, Burst, burst::Incr4);
+    token!(BurstToken, BURST, Burst, burst::Incr8);
+    token!(BurstToken, BURST, Burst, burst::Incr16);
+
+    // A single transfer always fits.
+    impl<P: DataSizeToken> PBurstFits<P> for burst::Single {}
+    impl<M: DataSizeToken, Th: FifoThresholdToken> MBurstFits<M, Th> for burst::Single {}
+
+    macro_rules! pburst_fits {
+        ($burst:ident, $($size:ident),+) => {
+            $(impl PBurstFits<size::$size> for burst::$burst {})+
+        };
+    }
+
+    // The peripheral burst must not exceed 16 bytes.
+    pburst_fits!(Incr4, Byte, HalfWord, Word);
+    pburst_fits!(Incr8, Byte, HalfWord);
+    pburst_fits!(Incr16, Byte);
+
+    macro_rules! mburst_fits {
+        ($burst:ident, $size:ident, $($threshold:ident),+) => {
+            $(impl MBurstFits<size::$size, threshold::$threshold> for burst::$burst {})+
+        };
+    }
+
+    // The fifo threshold must be an integer number of memory bursts, see RM0090 table 48.
+    mburst_fits!(Incr4, Byte, Quarter, Half, ThreeQuarters, Full);
+    mburst_fits!(Incr8, Byte, Half, Full);
+    mburst_fits!(Incr16, Byte, Full);
+    mburst_fits!(Incr4, HalfWord, Half, Full);
+    mburst_fits!(Incr8, HalfWord, Full);
+    mburst_fits!(Incr4, Word, Full);
+
     pub trait NewDmaChSetup<Dma: DmaMap, DmaCh: DmaChMap, DmaStCh, DmaInt: IntToken> {
-        /// Initialize a dma channel setup with medium priority level.
+        /// Initialize a dma channel setup with medium priority level and byte transfers in direct mode.
         fn new(ch: DmaChPeriph<DmaCh>, int: DmaInt) -> DmaChSetup<Dma, DmaCh, DmaStCh, DmaInt>;
     }
 
@@ -101,6 +467,7 @@ pub mod config {
                         dma_ch: ch,
                         dma_int: int,
                         dma_pl: DmaPrio::Medium,
+                        dma_transfer: DmaTransfer::direct(DataSize::Byte),
                     }
                 }
             }
@@ -259,6 +626,7 @@ impl<Dma: DmaMap> DmaCfg<Dma> {
             dma_ch,
             dma_int,
             dma_pl,
+            dma_transfer,
             ..
         } = setup;
         let pl = match dma_pl {
@@ -272,6 +640,7 @@ impl<Dma: DmaMap> DmaCfg<Dma> {
             dma_ch,
             dma_int,
             dma_pl: pl,
+            dma_transfer,
         }
     }
 }
@@ -285,4 +654,6 @@ pub struct DmaChCfg<DmaCh: DmaChMap, DmaStCh, DmaInt: IntToken> {
     pub dma_int: DmaInt,
     /// Dma priority level.
     pub dma_pl: u32,
+    /// Dma data sizes, fifo and burst configuration.
+    pub dma_transfer: DmaTransfer,
 }
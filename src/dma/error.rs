@@ -0,0 +1,26 @@
+use displaydoc::Display;
+
+/// Dma stream error.
+#[derive(Display, Debug, Copy, Clone, PartialEq)]
+/// DMA{dma} stream {stream} {kind} with {remaining} data items remaining.
+pub struct DmaError {
+    /// The dma controller number, i.e. 1 or 2.
+    pub dma: u32,
+    /// The stream number within the dma controller.
+    pub stream: u32,
+    /// The error kind.
+    pub kind: DmaErrorKind,
+    /// The number of data items remaining in the transfer (NDTR) when the error occurred.
+    pub remaining: usize,
+}
+
+/// Dma stream error kind.
+#[derive(Display, Debug, Copy, Clone, PartialEq)]
+pub enum DmaErrorKind {
+    /// transfer error
+    Transfer,
+    /// direct mode error
+    DirectMode,
+    /// fifo error
+    Fifo,
+}
@@ -1,12 +1,24 @@
 #![feature(prelude_import)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
+mod dbuf;
+mod diverged;
 mod drv;
+mod error;
+mod mem;
+pub mod request;
+
+extern crate alloc;
 
 pub use self::drv::{
-    config, DmaCfg, DmaChCfg, DmaStCh0, DmaStCh1, DmaStCh2, DmaStCh3, DmaStCh4, DmaStCh5, DmaStCh6,
-    DmaStCh7, DmaStChToken,
+    config, DmaCfg, DmaChCfg, DmaChNum, DmaItem, DmaStCh0, DmaStCh1, DmaStCh2, DmaStCh3, DmaStCh4, DmaStCh5,
+    DmaStCh6, DmaStCh7, DmaStChToken,
 };
+pub use self::diverged::{DmaChDiverged, DmaErrFlags};
+pub use self::dbuf::{DmaBufFree, DmaBufFull, DmaDoubleBufRx, DmaDoubleBufTx, DoubleBufError};
+pub use self::error::{DmaError, DmaErrorKind};
+pub use self::mem::{DmaMemDrv, MemToMemChMap};
+pub use self::request::{DmaRequest, DmaRequestToken};
 pub use drone_stm32_map::periph::dma::DmaMap;
 pub use drone_stm32_map::periph::dma::ch::DmaChMap;
 
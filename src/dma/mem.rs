@@ -0,0 +1,220 @@
+use crate::{
+    config::{Burst, DataSize, FifoThreshold},
+    diverged::DmaChDiverged,
+    DmaChCfg, DmaChNum, DmaError, DmaItem, DmaStCh0,
+};
+use core::{cmp::min, mem::size_of};
+use drone_cortexm::{reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::dma::ch::{
+    traits::*, DmaChMap, Dma2Ch0, Dma2Ch1, Dma2Ch2, Dma2Ch3, Dma2Ch4, Dma2Ch5, Dma2Ch6, Dma2Ch7,
+};
+
+/// The maximum number of items in a single transfer, as NDTR is a 16 bit register.
+const MAX_NDT: usize = 0xFFFF;
+
+/// A dma channel capable of memory-to-memory transfers.
+/// Only the DMA2 controller is able to perform memory-to-memory transfers, see RM0090 §10.3.6.
+pub trait MemToMemChMap: DmaChNum {}
+
+impl MemToMemChMap for Dma2Ch0 {}
+impl MemToMemChMap for Dma2Ch1 {}
+impl MemToMemChMap for Dma2Ch2 {}
+impl MemToMemChMap for Dma2Ch3 {}
+impl MemToMemChMap for Dma2Ch4 {}
+impl MemToMemChMap for Dma2Ch5 {}
+impl MemToMemChMap for Dma2Ch6 {}
+impl MemToMemChMap for Dma2Ch7 {}
+
+/// Memory-to-memory dma driver.
+pub struct DmaMemDrv<DmaCh: MemToMemChMap, DmaInt: IntToken> {
+    dma: DmaChDiverged<DmaCh>,
+    dma_int: DmaInt,
+    fifo_threshold: FifoThreshold,
+    burst: Burst,
+}
+
+impl<DmaCh: MemToMemChMap, DmaInt: IntToken> DmaMemDrv<DmaCh, DmaInt> {
+    /// Initialize a memory-to-memory driver on a DMA2 channel.
+    /// The stream channel is fixed to channel 0 as no peripheral request is involved in memory-to-memory transfers.
+    /// The fifo threshold and memory burst are taken from the channel transfer configuration,
+    /// where the fifo threshold defaults to full for a direct mode configuration.
+    /// The data sizes are given by the items in the individual transfers.
+    #[must_use]
+    pub fn init(cfg: DmaChCfg<DmaCh, DmaStCh0, DmaInt>) -> Self {
+        let DmaChCfg {
+            dma_ch,
+            dma_int,
+            dma_pl,
+            dma_transfer,
+            ..
+        } = cfg;
+        let drv = Self {
+            dma: dma_ch.into(),
+            dma_int,
+            fifo_threshold: dma_transfer.fifo_threshold().unwrap_or(FifoThreshold::Full),
+            burst: dma_transfer.mburst(),
+        };
+        drv.dma.dma_ccr.store_reg(|r, v| {
+            r.pl().write(v, dma_pl); // priority level
+            r.minc().set(v); // memory (destination) address pointer is incremented after each data transfer
+            r.dir().write(v, 0b10); // memory-to-memory
+            r.tcie().set(v); // transfer complete interrupt enable
+            r.teie().set(v); // transfer error interrupt enable
+
+            r.circ().clear(v); // normal mode (circular mode is not allowed for memory-to-memory)
+        });
+        drv.dma.dma_cfcr.store_reg(|r, v| {
+            r.dmdis().set(v); // direct mode disabled (the fifo is mandatory for memory-to-memory)
+            r.fth().write(v, drv.fifo_threshold.bits()); // fifo threshold
+        });
+        drv
+    }
+
+    /// Set the fifo threshold level.
+    pub fn with_fifo_threshold(mut self, threshold: FifoThreshold) -> Self {
+        self.fifo_threshold = threshold;
+        self.dma.dma_cfcr.modify_reg(|r, v| {
+            r.fth().write(v, threshold.bits());
+        });
+        self
+    }
+
+    /// Set the burst configuration used for both the source and the destination.
+    ///
+    /// The burst is only used if it fits the fifo threshold level for the transferred item size,
+    /// and if the source and destination addresses are aligned to the burst size.
+    /// Otherwise the items are transferred using single transfers.
+    pub fn with_burst(mut self, burst: Burst) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Copy all items in `src` into `dst`.
+    /// Transfers larger than 65535 items are split into multiple consecutive dma transfers.
+    /// The copy is stopped at the first failing transfer.
+    pub async fn copy<T: DmaItem>(&mut self, src: &[T], dst: &mut [T]) -> Result<(), DmaError> {
+        assert_eq!(src.len(), dst.len(), "Source and destination lengths must be equal");
+
+        let mut offset = 0;
+        while offset < src.len() {
+            let src_addr = src[offset..].as_ptr() as u32;
+            let dst_addr = dst[offset..].as_mut_ptr() as u32;
+            let (burst, cnt) = self.next_chunk::<T>(Some(src_addr), dst_addr, src.len() - offset);
+            unsafe {
+                self.xfer(T::SIZE, src_addr, true, dst_addr, cnt, burst).await?;
+            }
+            offset += cnt;
+        }
+        Ok(())
+    }
+
+    /// Fill all items in `dst` with `value`.
+    /// Transfers larger than 65535 items are split into multiple consecutive dma transfers.
+    /// The fill is stopped at the first failing transfer.
+    pub async fn fill<T: DmaItem>(&mut self, value: T, dst: &mut [T]) -> Result<(), DmaError> {
+        // The value must outlive the transfer, so keep it in the future.
+        let value = [value];
+        let src_addr = value.as_ptr() as u32;
+
+        let mut offset = 0;
+        while offset < dst.len() {
+            let dst_addr = dst[offset..].as_mut_ptr() as u32;
+            let (burst, cnt) = self.next_chunk::<T>(None, dst_addr, dst.len() - offset);
+            unsafe {
+                self.xfer(T::SIZE, src_addr, false, dst_addr, cnt, burst).await?;
+            }
+            offset += cnt;
+        }
+        Ok(())
+    }
+
+    /// Get the burst configuration and number of items for the next transfer.
+    fn next_chunk<T: DmaItem>(&self, src_addr: Option<u32>, dst_addr: u32, len: usize) -> (Burst, usize) {
+        let beats = self.burst.beats();
+        let burst_bytes = (beats * size_of::<T>()) as u32;
+        let aligned = |addr: u32| addr % burst_bytes == 0;
+        if self.burst != Burst::Single
+            && self.burst.fits(T::SIZE, self.fifo_threshold)
+            && src_addr.map_or(true, aligned)
+            && aligned(dst_addr)
+            && len >= beats
+        {
+            // The number of items must be a multiple of the burst beats, see RM0090 §10.3.11.
+            // The aligned addresses ensures that no burst crosses a 1KB address boundary.
+            let cnt = min(len, MAX_NDT) / beats * beats;
+            (self.burst, cnt)
+        } else {
+            (Burst::Single, min(len, MAX_NDT))
+        }
+    }
+
+    async unsafe fn xfer(
+        &self,
+        size: DataSize,
+        src_addr: u32,
+        src_inc: bool,
+        dst_addr: u32,
+        cnt: usize,
+        burst: Burst,
+    ) -> Result<(), DmaError> {
+        let dma = &self.dma;
+
+        // The source is read using the peripheral port, and the destination is written using the memory port.
+        dma.dma_ccr.modify_reg(|r, v| {
+            r.msize().write(v, size.bits()); // memory data size
+            r.psize().write(v, size.bits()); // peripheral data size
+            r.mburst().write(v, burst.bits()); // memory burst
+            r.pburst().write(v, burst.bits()); // peripheral burst
+            if src_inc {
+                r.pinc().set(v); // source address pointer is incremented after each data transfer
+            } else {
+                r.pinc().clear(v); // source address pointer is fixed
+            }
+        });
+        dma.dma_cpar.store_reg(|r, v| {
+            r.pa().write(v, src_addr); // source address
+        });
+        dma.dma_cm0ar.store_reg(|r, v| {
+            r.m0a().write(v, dst_addr); // destination address
+        });
+        dma.dma_cndtr.store_reg(|r, v| {
+            r.ndt().write(v, cnt as u32); // number of items to transfer
+        });
+
+        // Start listen for the transfer to complete.
+        let dma_tc = dma.transfer_future(self.dma_int);
+
+        // Clear transfer completed interrupt flag.
+        dma.dma_ifcr_ctcif.set_bit();
+
+        // Clear any outstanding fifo error interrupt flag by settings its clear register.
+        dma.dma_ifcr_cfeif.set_bit();
+
+        // Enable stream, the transfer starts immediately.
+        dma.dma_ccr.modify_reg(|r, v| r.en().set(v));
+
+        // Abort the transfer if the future is dropped before it completes.
+        let guard = AbortGuard(dma);
+
+        // Wait for the transfer to complete.
+        let result = dma_tc.await;
+
+        // The stream is automatically disabled on completion.
+        core::mem::forget(guard);
+
+        if result.is_err() {
+            // Reset the stream so that the next transfer can proceed.
+            dma.reset();
+        }
+
+        result
+    }
+}
+
+struct AbortGuard<'a, DmaCh: DmaChMap>(&'a DmaChDiverged<DmaCh>);
+
+impl<DmaCh: DmaChMap> Drop for AbortGuard<'_, DmaCh> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,330 @@
+//! Dma request routing.
+//!
+//! Each peripheral dma request is connected to one or more (stream, stream channel) combinations,
+//! see RM0090 §10.3.3 tables 42 and 43.
+//! A driver accepts a `DmaChCfg<DmaCh, DmaStCh, _>` for a request `Req` only if `DmaCh: DmaRequest<Req, DmaStCh>`.
+//!
+//! A `DmaChCfg` owns the register tokens of its stream, so two drivers can never be handed the same stream.
+
+use crate::drv::*;
+use drone_stm32_map::periph::dma::ch::*;
+
+/// A peripheral dma request.
+pub trait DmaRequestToken: Send + Sync + 'static {}
+
+/// A dma stream that is connected to the peripheral request `Req` on the stream channel `DmaStCh`.
+pub trait DmaRequest<Req: DmaRequestToken, DmaStCh: DmaStChToken>: DmaChNum {}
+
+macro_rules! dma_requests {
+    ($($(#[$attr:meta])* $req:ident => [$($ch:ident: $stch:ident),+];)*) => {
+        $(
+            $(#[$attr])*
+            pub struct $req;
+
+            $(#[$attr])*
+            impl DmaRequestToken for $req {}
+
+            $(
+                $(#[$attr])*
+                impl DmaRequest<$req, $stch> for $ch {}
+            )+
+
+            #[cfg(test)]
+            $(#[$attr])*
+            impl $req {
+                /// The (stream, stream channel) routes of the request.
+                const ROUTES: &'static [(&'static str, u32)] = &[$((stringify!($ch), $stch::NUM)),+];
+            }
+        )*
+
+        /// Call `f` with the name and routes of every request.
+        #[cfg(test)]
+        fn for_each_request(mut f: impl FnMut(&'static str, &'static [(&'static str, u32)])) {
+            $(
+                $(#[$attr])*
+                f(stringify!($req), $req::ROUTES);
+            )*
+        }
+    };
+}
+
+dma_requests! {
+    Spi1Rx => [Dma2Ch0: DmaStCh3, Dma2Ch2: DmaStCh3];
+    Spi1Tx => [Dma2Ch3: DmaStCh3, Dma2Ch5: DmaStCh3];
+    Spi2Rx => [Dma1Ch3: DmaStCh0];
+    Spi2Tx => [Dma1Ch4: DmaStCh0];
+    Spi3Rx => [Dma1Ch0: DmaStCh0, Dma1Ch2: DmaStCh0];
+    Spi3Tx => [Dma1Ch5: DmaStCh0, Dma1Ch7: DmaStCh0];
+    #[cfg(any(
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f446",
+        stm32_mcu = "stm32f469",
+    ))]
+    Spi4Rx => [Dma2Ch0: DmaStCh4, Dma2Ch3: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f446",
+        stm32_mcu = "stm32f469",
+    ))]
+    Spi4Tx => [Dma2Ch1: DmaStCh4, Dma2Ch4: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f446",
+        stm32_mcu = "stm32f469",
+    ))]
+    Spi5Rx => [Dma2Ch3: DmaStCh2, Dma2Ch5: DmaStCh7];
+    #[cfg(any(
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f446",
+        stm32_mcu = "stm32f469",
+    ))]
+    Spi5Tx => [Dma2Ch4: DmaStCh2, Dma2Ch6: DmaStCh7];
+    #[cfg(any(stm32_mcu = "stm32f427", stm32_mcu = "stm32f469"))]
+    Spi6Rx => [Dma2Ch6: DmaStCh1];
+    #[cfg(any(stm32_mcu = "stm32f427", stm32_mcu = "stm32f469"))]
+    Spi6Tx => [Dma2Ch5: DmaStCh1];
+
+    #[cfg(any(
+        stm32_mcu = "stm32f401",
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f411",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f429",
+        stm32_mcu = "stm32f469",
+    ))]
+    I2s2ExtRx => [Dma1Ch3: DmaStCh3];
+    #[cfg(any(
+        stm32_mcu = "stm32f401",
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f411",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f429",
+        stm32_mcu = "stm32f469",
+    ))]
+    I2s2ExtTx => [Dma1Ch4: DmaStCh2];
+    #[cfg(any(
+        stm32_mcu = "stm32f401",
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f411",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f429",
+        stm32_mcu = "stm32f469",
+    ))]
+    I2s3ExtRx => [Dma1Ch0: DmaStCh3, Dma1Ch2: DmaStCh2];
+    #[cfg(any(
+        stm32_mcu = "stm32f401",
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f411",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f429",
+        stm32_mcu = "stm32f469",
+    ))]
+    I2s3ExtTx => [Dma1Ch5: DmaStCh2];
+
+    Usart1Rx => [Dma2Ch2: DmaStCh4, Dma2Ch5: DmaStCh4];
+    Usart1Tx => [Dma2Ch7: DmaStCh4];
+    Usart2Rx => [Dma1Ch5: DmaStCh4];
+    Usart2Tx => [Dma1Ch6: DmaStCh4];
+    Usart3Rx => [Dma1Ch1: DmaStCh4];
+    Usart3Tx => [Dma1Ch3: DmaStCh4, Dma1Ch4: DmaStCh7];
+    Uart4Rx => [Dma1Ch2: DmaStCh4];
+    Uart4Tx => [Dma1Ch4: DmaStCh4];
+    Uart5Rx => [Dma1Ch0: DmaStCh4];
+    Uart5Tx => [Dma1Ch7: DmaStCh4];
+    Usart6Rx => [Dma2Ch1: DmaStCh5, Dma2Ch2: DmaStCh5];
+    Usart6Tx => [Dma2Ch6: DmaStCh5, Dma2Ch7: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f417",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f437",
+        stm32_mcu = "stm32f469",
+    ))]
+    Uart7Rx => [Dma1Ch3: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f417",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f437",
+        stm32_mcu = "stm32f469",
+    ))]
+    Uart7Tx => [Dma1Ch1: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f417",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f437",
+        stm32_mcu = "stm32f469",
+    ))]
+    Uart8Rx => [Dma1Ch6: DmaStCh5];
+    #[cfg(any(
+        stm32_mcu = "stm32f405",
+        stm32_mcu = "stm32f407",
+        stm32_mcu = "stm32f413",
+        stm32_mcu = "stm32f417",
+        stm32_mcu = "stm32f427",
+        stm32_mcu = "stm32f437",
+        stm32_mcu = "stm32f469",
+    ))]
+    Uart8Tx => [Dma1Ch0: DmaStCh5];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DMA1 request mapping, indexed by stream channel and stream, see RM0090 table 42.
+    const DMA1: [[&str; 8]; 8] = [
+        ["SPI3_RX", "", "SPI3_RX", "SPI2_RX", "SPI2_TX", "SPI3_TX", "", "SPI3_TX"],
+        ["I2C1_RX", "", "TIM7_UP", "", "TIM7_UP", "I2C1_RX", "I2C1_TX", "I2C1_TX"],
+        ["TIM4_CH1", "", "I2S3_EXT_RX", "TIM4_CH2", "I2S2_EXT_TX", "I2S3_EXT_TX", "TIM4_UP", "TIM4_CH3"],
+        [
+            "I2S3_EXT_RX",
+            "TIM2_UP/TIM2_CH3",
+            "I2C3_RX",
+            "I2S2_EXT_RX",
+            "I2C3_TX",
+            "TIM2_CH1",
+            "TIM2_CH2/TIM2_CH4",
+            "TIM2_UP/TIM2_CH4",
+        ],
+        ["UART5_RX", "USART3_RX", "UART4_RX", "USART3_TX", "UART4_TX", "USART2_RX", "USART2_TX", "UART5_TX"],
+        [
+            "UART8_TX",
+            "UART7_TX",
+            "TIM3_CH4/TIM3_UP",
+            "UART7_RX",
+            "TIM3_CH1/TIM3_TRIG",
+            "TIM3_CH2",
+            "UART8_RX",
+            "TIM3_CH3",
+        ],
+        [
+            "TIM5_CH3/TIM5_UP",
+            "TIM5_CH4/TIM5_TRIG",
+            "TIM5_CH1",
+            "TIM5_CH4/TIM5_TRIG",
+            "TIM5_CH2",
+            "",
+            "TIM5_UP",
+            "",
+        ],
+        ["", "TIM6_UP", "I2C2_RX", "I2C2_RX", "USART3_TX", "DAC1", "DAC2", "I2C2_TX"],
+    ];
+
+    /// DMA2 request mapping, indexed by stream channel and stream, see RM0090 table 43.
+    const DMA2: [[&str; 8]; 8] = [
+        [
+            "ADC1",
+            "SAI1_A",
+            "TIM8_CH1/TIM8_CH2/TIM8_CH3",
+            "SAI1_A",
+            "ADC1",
+            "SAI1_B",
+            "TIM1_CH1/TIM1_CH2/TIM1_CH3",
+            "",
+        ],
+        ["", "DCMI", "ADC2", "ADC2", "SAI1_B", "SPI6_TX", "SPI6_RX", "DCMI"],
+        ["ADC3", "ADC3", "", "SPI5_RX", "SPI5_TX", "CRYP_OUT", "CRYP_IN", "HASH_IN"],
+        ["SPI1_RX", "", "SPI1_RX", "SPI1_TX", "", "SPI1_TX", "", ""],
+        ["SPI4_RX", "SPI4_TX", "USART1_RX", "SDIO", "", "USART1_RX", "SDIO", "USART1_TX"],
+        ["", "USART6_RX", "USART6_RX", "SPI4_RX", "SPI4_TX", "", "USART6_TX", "USART6_TX"],
+        [
+            "TIM1_TRIG",
+            "TIM1_CH1",
+            "TIM1_CH2",
+            "TIM1_CH1",
+            "TIM1_CH4/TIM1_TRIG/TIM1_COM",
+            "TIM1_UP",
+            "TIM1_CH3",
+            "",
+        ],
+        [
+            "",
+            "TIM8_UP",
+            "TIM8_CH1",
+            "TIM8_CH2",
+            "TIM8_CH3",
+            "SPI5_RX",
+            "SPI5_TX",
+            "TIM8_CH4/TIM8_TRIG/TIM8_COM",
+        ],
+    ];
+
+    /// Compare a request type name, e.g. `I2s2ExtRx`, with a reference manual name, e.g. `I2S2_EXT_RX`.
+    fn same_request(req: &str, name: &str) -> bool {
+        normalize(req).eq(normalize(name))
+    }
+
+    fn normalize(s: &str) -> impl Iterator<Item = char> + '_ {
+        s.chars().filter(|&c| c != '_').map(|c| c.to_ascii_uppercase())
+    }
+
+    /// Get the requests connected to `stch` on the stream named e.g. `Dma2Ch3`.
+    fn cell(ch: &str, stch: u32) -> &'static str {
+        let (dma, stream) = match ch.as_bytes() {
+            [b'D', b'm', b'a', dma, b'C', b'h', stream] => (dma - b'0', stream - b'0'),
+            _ => panic!("Invalid stream name {}", ch),
+        };
+        let table = match dma {
+            1 => &DMA1,
+            2 => &DMA2,
+            _ => panic!("Invalid dma controller in {}", ch),
+        };
+        table[stch as usize][stream as usize]
+    }
+
+    #[test]
+    fn routes_match_reference_manual() {
+        for_each_request(|req, routes| {
+            for &(ch, stch) in routes {
+                let cell = cell(ch, stch);
+                assert!(
+                    cell.split('/').any(|name| same_request(req, name)),
+                    "{} is routed to {} channel {}, which is {:?} in RM0090",
+                    req,
+                    ch,
+                    stch,
+                    cell
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn all_routes_are_declared() {
+        for_each_request(|req, routes| {
+            for (dma, table) in [(1, &DMA1), (2, &DMA2)].iter() {
+                for (stch, streams) in table.iter().enumerate() {
+                    for (stream, cell) in streams.iter().enumerate() {
+                        if cell.split('/').any(|name| same_request(req, name)) {
+                            assert!(
+                                routes.iter().any(|&(ch, ch_stch)| {
+                                    ch_stch == stch as u32
+                                        && ch.as_bytes() == [b'D', b'm', b'a', b'0' + dma, b'C', b'h', b'0' + stream as u8]
+                                }),
+                                "{} is missing the route DMA{} stream {} channel {}",
+                                req,
+                                dma,
+                                stream,
+                                stch
+                            );
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{
+    layer::{Framebuf, Geometry, Image},
+    periph::Dma2dPeriph,
+    pixel::Alpha,
+};
+use displaydoc::Display;
+use drone_core::fib::FiberFuture;
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::reg;
+
+pub struct Dma2dSetup<Dma2dInt: IntToken> {
+    /// The dma2d peripheral.
+    pub dma2d: Dma2dPeriph,
+    /// The dma2d global interrupt.
+    pub dma2d_int: Dma2dInt,
+}
+
+impl<Dma2dInt: IntToken> Dma2dSetup<Dma2dInt> {
+    pub fn new(dma2d: Dma2dPeriph, dma2d_int: Dma2dInt) -> Self {
+        Self { dma2d, dma2d_int }
+    }
+}
+
+/// DMA2D error.
+#[derive(Display, Debug, Copy, Clone, PartialEq)]
+pub enum Dma2dError {
+    /// An AHB bus error occurred during the transfer.
+    Transfer,
+    /// The DMA2D was started with an invalid configuration.
+    Configuration,
+    /// The color lookup table was accessed while being loaded.
+    ClutAccess,
+}
+
+/// The DMA2D operating mode (MODE) in the CR register.
+#[derive(Clone, Copy)]
+enum Mode {
+    MemToMem = 0b00,
+    MemToMemPfc = 0b01,
+    MemToMemBlend = 0b10,
+    RegToMem = 0b11,
+}
+
+/// Chrom-ART Accelerator (DMA2D) driver.
+pub struct Dma2dDrv<Dma2dInt: IntToken> {
+    dma2d_cr: reg::dma2d::Cr<Srt>,
+    dma2d_isr: reg::dma2d::Isr<Crt>,
+    dma2d_ifcr: reg::dma2d::Ifcr<Crt>,
+    dma2d_fgmar: reg::dma2d::Fgmar<Srt>,
+    dma2d_fgor: reg::dma2d::Fgor<Srt>,
+    dma2d_bgmar: reg::dma2d::Bgmar<Srt>,
+    dma2d_bgor: reg::dma2d::Bgor<Srt>,
+    dma2d_fgpfccr: reg::dma2d::Fgpfccr<Srt>,
+    dma2d_fgcolr: reg::dma2d::Fgcolr<Srt>,
+    dma2d_bgpfccr: reg::dma2d::Bgpfccr<Srt>,
+    dma2d_bgcolr: reg::dma2d::Bgcolr<Srt>,
+    dma2d_fgcmar: reg::dma2d::Fgcmar<Srt>,
+    dma2d_bgcmar: reg::dma2d::Bgcmar<Srt>,
+    dma2d_opfccr: reg::dma2d::Opfccr<Srt>,
+    dma2d_ocolr: reg::dma2d::Ocolr<Srt>,
+    dma2d_omar: reg::dma2d::Omar<Srt>,
+    dma2d_oor: reg::dma2d::Oor<Srt>,
+    dma2d_nlr: reg::dma2d::Nlr<Srt>,
+    dma2d_int: Dma2dInt,
+}
+
+impl<Dma2dInt: IntToken> Dma2dDrv<Dma2dInt> {
+    /// Enable the DMA2D clock and initialize the driver.
+    #[must_use]
+    pub fn init(setup: Dma2dSetup<Dma2dInt>) -> Self {
+        let Dma2dSetup { dma2d, dma2d_int } = setup;
+        let Dma2dPeriph {
+            rcc_ahb1enr_dma2den,
+            dma2d_cr,
+            dma2d_isr,
+            dma2d_ifcr,
+            dma2d_fgmar,
+            dma2d_fgor,
+            dma2d_bgmar,
+            dma2d_bgor,
+            dma2d_fgpfccr,
+            dma2d_fgcolr,
+            dma2d_bgpfccr,
+            dma2d_bgcolr,
+            dma2d_fgcmar,
+            dma2d_bgcmar,
+            dma2d_opfccr,
+            dma2d_ocolr,
+            dma2d_omar,
+            dma2d_oor,
+            dma2d_nlr,
+        } = dma2d;
+
+        // Enable the DMA2D clock.
+        rcc_ahb1enr_dma2den.set_bit();
+
+        Self {
+            dma2d_cr,
+            dma2d_isr: dma2d_isr.into_copy(),
+            dma2d_ifcr: dma2d_ifcr.into_copy(),
+            dma2d_fgmar,
+            dma2d_fgor,
+            dma2d_bgmar,
+            dma2d_bgor,
+            dma2d_fgpfccr,
+            dma2d_fgcolr,
+            dma2d_bgpfccr,
+            dma2d_bgcolr,
+            dma2d_fgcmar,
+            dma2d_bgcmar,
+            dma2d_opfccr,
+            dma2d_ocolr,
+            dma2d_omar,
+            dma2d_oor,
+            dma2d_nlr,
+            dma2d_int,
+        }
+    }
+
+    /// Fill `dst` with `color`.
+    /// The color is given in the pixel format of `dst`, e.g. `0xF800` is red for `Rgb565`.
+    pub async fn fill(&mut self, dst: &mut Framebuf<'_>, color: u32) -> Result<(), Dma2dError> {
+        // The color is written as is into the output color register, where its layout depends on the output format.
+        self.dma2d_ocolr.store(|r| {
+            r.write_blue(color & 0xFF)
+                .write_green((color >> 8) & 0xFF)
+                .write_red((color >> 16) & 0xFF)
+                .write_aplha(color >> 24) // sic
+        });
+        self.setup_output(&dst.geometry);
+        self.start(Mode::RegToMem).await
+    }
+
+    /// Copy `src` into `dst` while converting the pixel format.
+    /// The two must have the same size.
+    pub async fn copy(&mut self, src: &Image<'_>, dst: &mut Framebuf<'_>) -> Result<(), Dma2dError> {
+        assert_same_size(&src.geometry, &dst.geometry);
+
+        self.setup_fg(src, Alpha::Keep).await?;
+        self.setup_output(&dst.geometry);
+        let mode = if src.geometry.format == dst.geometry.format {
+            Mode::MemToMem
+        } else {
+            Mode::MemToMemPfc
+        };
+        self.start(mode).await
+    }
+
+    /// Blend the foreground `fg` on top of the background `bg` and store the result in `dst`.
+    /// All three must have the same size, and `bg` may be the same pixels as `dst`.
+    pub async fn blend(
+        &mut self,
+        fg: &Image<'_>,
+        fg_alpha: Alpha,
+        bg: &Image<'_>,
+        bg_alpha: Alpha,
+        dst: &mut Framebuf<'_>,
+    ) -> Result<(), Dma2dError> {
+        assert_same_size(&fg.geometry, &dst.geometry);
+        assert_same_size(&bg.geometry, &dst.geometry);
+
+        self.setup_fg(fg, fg_alpha).await?;
+        self.setup_bg(bg, bg_alpha).await?;
+        self.setup_output(&dst.geometry);
+        self.start(Mode::MemToMemBlend).await
+    }
+
+    /// Blend the foreground `fg` on top of `dst` in place.
+    pub async fn blend_over(
+        &mut self,
+        fg: &Image<'_>,
+        fg_alpha: Alpha,
+        dst: &mut Framebuf<'_>,
+    ) -> Result<(), Dma2dError> {
+        assert_same_size(&fg.geometry, &dst.geometry);
+
+        self.setup_fg(fg, fg_alpha).await?;
+        let bg = dst.as_image();
+        self.setup_bg(&bg, Alpha::Keep).await?;
+        self.setup_output(&dst.geometry);
+        self.start(Mode::MemToMemBlend).await
+    }
+
+    async fn setup_fg(&mut self, fg: &Image<'_>, alpha: Alpha) -> Result<(), Dma2dError> {
+        let geometry = &fg.geometry;
+        let (am, alpha) = alpha.am_alpha();
+        self.dma2d_fgmar.store(|r| r.write_ma(geometry.addr as u32));
+        self.dma2d_fgor.store(|r| r.write_lo(geometry.line_offset()));
+        self.dma2d_fgcolr.store(|r| {
+            r.write_blue(fg.color & 0xFF)
+                .write_green((fg.color >> 8) & 0xFF)
+                .write_red((fg.color >> 16) & 0xFF)
+        });
+        if let Some(clut) = fg.clut {
+            self.dma2d_fgcmar.store(|r| r.write_ma(clut.as_ptr() as u32));
+        }
+        self.dma2d_fgpfccr.store(|r| {
+            r.write_cm(geometry.format.cm())
+                .clear_ccm() // The color lookup table is in ARGB8888
+                .write_cs(fg.clut_size())
+                .write_am(am)
+                .write_alpha(alpha)
+        });
+        if fg.clut.is_some() {
+            // Load the color lookup table, see RM0090 §11.3.6.
+            let clut_loaded = self.completion(true);
+            self.dma2d_fgpfccr.modify(|r| r.set_start());
+            clut_loaded.await?;
+        }
+        Ok(())
+    }
+
+    async fn setup_bg(&mut self, bg: &Image<'_>, alpha: Alpha) -> Result<(), Dma2dError> {
+        let geometry = &bg.geometry;
+        let (am, alpha) = alpha.am_alpha();
+        self.dma2d_bgmar.store(|r| r.write_ma(geometry.addr as u32));
+        self.dma2d_bgor.store(|r| r.write_lo(geometry.line_offset()));
+        self.dma2d_bgcolr.store(|r| {
+            r.write_blue(bg.color & 0xFF)
+                .write_green((bg.color >> 8) & 0xFF)
+                .write_red((bg.color >> 16) & 0xFF)
+        });
+        if let Some(clut) = bg.clut {
+            self.dma2d_bgcmar.store(|r| r.write_ma(clut.as_ptr() as u32));
+        }
+        self.dma2d_bgpfccr.store(|r| {
+            r.write_cm(geometry.format.cm())
+                .clear_ccm() // The color lookup table is in ARGB8888
+                .write_cs(bg.clut_size())
+                .write_am(am)
+                .write_alpha(alpha)
+        });
+        if bg.clut.is_some() {
+            // Load the color lookup table, see RM0090 §11.3.6.
+            let clut_loaded = self.completion(true);
+            self.dma2d_bgpfccr.modify(|r| r.set_start());
+            clut_loaded.await?;
+        }
+        Ok(())
+    }
+
+    fn setup_output(&self, geometry: &Geometry) {
+        assert!(geometry.width <= 0x3FFF, "The width is too large");
+        assert!(geometry.height <= 0xFFFF, "The height is too large");
+        self.dma2d_opfccr.store(|r| r.write_cm(geometry.format.cm()));
+        self.dma2d_omar.store(|r| r.write_ma(geometry.addr as u32));
+        self.dma2d_oor.store(|r| r.write_lo(geometry.line_offset()));
+        self.dma2d_nlr.store(|r| {
+            r.write_pl(geometry.width as u32) // Pixels per line
+                .write_nl(geometry.height as u32) // Number of lines
+        });
+    }
+
+    /// Start the transfer and wait for it to complete.
+    async fn start(&mut self, mode: Mode) -> Result<(), Dma2dError> {
+        let completed = self.completion(false);
+
+        // Start the transfer.
+        self.dma2d_cr.store(|r| {
+            r.write_mode(mode as u32)
+                .set_tcie() // Transfer complete interrupt enable
+                .set_teie() // Transfer error interrupt enable
+                .set_ceie() // Configuration error interrupt enable
+                .set_start()
+        });
+
+        // Abort the transfer if the future is dropped before it completes.
+        let guard = AbortGuard(&self.dma2d_cr);
+
+        let result = completed.await;
+
+        // The START bit is automatically reset when the transfer completes, or by a configuration error.
+        core::mem::forget(guard);
+
+        result
+    }
+
+    /// Create a future that completes when the transfer,
+    /// or the color lookup table loading if `clut` is true, is completed or has failed.
+    fn completion(&self, clut: bool) -> FiberFuture<Result<(), Dma2dError>> {
+        let dma2d_isr = self.dma2d_isr;
+        let dma2d_ifcr = self.dma2d_ifcr;
+        if clut {
+            self.dma2d_cr.modify(|r| {
+                r.set_ctcie() // CLUT transfer complete interrupt enable
+                    .set_caeie() // CLUT access error interrupt enable
+                    .set_teie() // Transfer error interrupt enable
+            });
+        }
+        self.dma2d_int.add_future(fib::new_fn(move || {
+            let isr = dma2d_isr.load();
+            if isr.teif() {
+                dma2d_ifcr.store(|r| r.set_cteif());
+                fib::Complete(Err(Dma2dError::Transfer))
+            } else if isr.ceif() {
+                dma2d_ifcr.store(|r| r.set_cceif());
+                fib::Complete(Err(Dma2dError::Configuration))
+            } else if isr.caeif() {
+                dma2d_ifcr.store(|r| r.set_caecif());
+                fib::Complete(Err(Dma2dError::ClutAccess))
+            } else if clut && isr.ctcif() {
+                dma2d_ifcr.store(|r| r.set_cctcif());
+                fib::Complete(Ok(()))
+            } else if !clut && isr.tcif() {
+                dma2d_ifcr.store(|r| r.set_ctcif());
+                fib::Complete(Ok(()))
+            } else {
+                fib::Yielded(())
+            }
+        }))
+    }
+}
+
+struct AbortGuard<'a>(&'a reg::dma2d::Cr<Srt>);
+
+impl Drop for AbortGuard<'_> {
+    fn drop(&mut self) {
+        self.0.modify(|r| r.set_abort());
+        while self.0.load().start() {}
+    }
+}
+
+fn assert_same_size(a: &Geometry, b: &Geometry) {
+    assert!(
+        a.width == b.width && a.height == b.height,
+        "The input and output must have the same size"
+    );
+}
@@ -0,0 +1,322 @@
+use crate::pixel::PixelFormat;
+use core::{marker::PhantomData, mem::size_of};
+
+/// A rectangle in pixel coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A pixel buffer that is the output of the DMA2D operations, e.g. a framebuffer in the FMC sdram.
+pub struct Framebuf<'a> {
+    pub(crate) geometry: Geometry,
+    _buf: PhantomData<&'a mut [u8]>,
+}
+
+/// A pixel buffer that is the input to the DMA2D operations.
+#[derive(Clone, Copy)]
+pub struct Image<'a> {
+    pub(crate) geometry: Geometry,
+    pub(crate) clut: Option<&'a [u32]>,
+    pub(crate) color: u32,
+    _buf: PhantomData<&'a [u8]>,
+}
+
+/// The location of a rectangular pixel area in memory.
+#[derive(Clone, Copy)]
+pub(crate) struct Geometry {
+    /// The address of the first pixel.
+    pub(crate) addr: usize,
+    pub(crate) format: PixelFormat,
+    /// The number of pixels between the start of two consecutive lines.
+    pub(crate) stride: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+impl<'a> Framebuf<'a> {
+    /// Create a `width` x `height` framebuffer in `buf`, e.g. obtained from `FmcDrv::bank2_slice()`.
+    pub fn new<T>(buf: &'a mut [T], format: PixelFormat, width: usize, height: usize) -> Self {
+        Self::with_stride(buf, format, width, height, width)
+    }
+
+    /// Create a `width` x `height` framebuffer in `buf`,
+    /// where consecutive lines start `stride` pixels apart.
+    pub fn with_stride<T>(
+        buf: &'a mut [T],
+        format: PixelFormat,
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Self {
+        assert!(format.is_output(), "The pixel format cannot be used for output");
+        Self {
+            geometry: Geometry::new(
+                buf.as_mut_ptr() as usize,
+                buf.len() * size_of::<T>(),
+                format,
+                width,
+                height,
+                stride,
+            ),
+            _buf: PhantomData,
+        }
+    }
+
+    /// Get the part of the framebuffer within `rect`.
+    pub fn crop(&mut self, rect: Rect) -> Framebuf<'_> {
+        Framebuf {
+            geometry: self.geometry.crop(rect),
+            _buf: PhantomData,
+        }
+    }
+
+    /// Use the framebuffer as input, e.g. as the background when blending.
+    pub fn as_image(&self) -> Image<'_> {
+        Image {
+            geometry: self.geometry,
+            clut: None,
+            color: 0,
+            _buf: PhantomData,
+        }
+    }
+
+    /// The framebuffer pixel format.
+    pub fn format(&self) -> PixelFormat {
+        self.geometry.format
+    }
+
+    /// The framebuffer width in pixels.
+    pub fn width(&self) -> usize {
+        self.geometry.width
+    }
+
+    /// The framebuffer height in pixels.
+    pub fn height(&self) -> usize {
+        self.geometry.height
+    }
+}
+
+impl<'a> Image<'a> {
+    /// Create a `width` x `height` image from the pixels in `buf`.
+    pub fn new<T>(buf: &'a [T], format: PixelFormat, width: usize, height: usize) -> Self {
+        Self::with_stride(buf, format, width, height, width)
+    }
+
+    /// Create a `width` x `height` image from the pixels in `buf`,
+    /// where consecutive lines start `stride` pixels apart.
+    pub fn with_stride<T>(buf: &'a [T], format: PixelFormat, width: usize, height: usize, stride: usize) -> Self {
+        Self {
+            geometry: Geometry::new(buf.as_ptr() as usize, buf.len() * size_of::<T>(), format, width, height, stride),
+            clut: None,
+            color: 0,
+            _buf: PhantomData,
+        }
+    }
+
+    /// Use an ARGB8888 color lookup table for an indexed image format, i.e. `L8`, `Al44`, or `Al88`.
+    pub fn with_clut(mut self, clut: &'a [u32]) -> Self {
+        assert!(self.geometry.format.is_indexed(), "The pixel format is not indexed");
+        assert!(
+            !clut.is_empty() && clut.len() <= 256,
+            "The color lookup table must have between 1 and 256 entries"
+        );
+        self.clut = Some(clut);
+        self
+    }
+
+    /// Use a RGB888 color for an alpha only image format, i.e. `A8`.
+    pub fn with_color(mut self, rgb: u32) -> Self {
+        self.color = rgb & 0x00FF_FFFF;
+        self
+    }
+
+    /// Get the part of the image within `rect`.
+    pub fn crop(&self, rect: Rect) -> Image<'a> {
+        Image {
+            geometry: self.geometry.crop(rect),
+            ..*self
+        }
+    }
+
+    /// The color lookup table size (CS) value in the FGPFCCR and BGPFCCR registers, i.e. the number of entries minus one.
+    pub(crate) fn clut_size(&self) -> u32 {
+        self.clut.map_or(0, |clut| clut.len() as u32 - 1)
+    }
+
+    /// The image pixel format.
+    pub fn format(&self) -> PixelFormat {
+        self.geometry.format
+    }
+
+    /// The image width in pixels.
+    pub fn width(&self) -> usize {
+        self.geometry.width
+    }
+
+    /// The image height in pixels.
+    pub fn height(&self) -> usize {
+        self.geometry.height
+    }
+}
+
+impl Geometry {
+    fn new(addr: usize, len: usize, format: PixelFormat, width: usize, height: usize, stride: usize) -> Self {
+        assert!(stride >= width, "The stride must not be less than the width");
+        let pixels = if height == 0 { 0 } else { (height - 1) * stride + width };
+        assert!(len >= pixels * format.bytes(), "The buffer is too small");
+        Self {
+            addr,
+            format,
+            stride,
+            width,
+            height,
+        }
+    }
+
+    fn crop(&self, rect: Rect) -> Self {
+        assert!(
+            rect.x + rect.width <= self.width && rect.y + rect.height <= self.height,
+            "The rectangle is outside the buffer"
+        );
+        Self {
+            addr: self.addr + (rect.y * self.stride + rect.x) * self.format.bytes(),
+            width: rect.width,
+            height: rect.height,
+            ..*self
+        }
+    }
+
+    /// The number of pixels skipped at the end of each line, i.e. the value for the line offset registers.
+    pub(crate) fn line_offset(&self) -> u32 {
+        let line_offset = self.stride - self.width;
+        assert!(line_offset <= 0x3FFF, "The line offset is too large");
+        line_offset as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stride_defaults_to_width() {
+        let buf = [0u16; 6];
+        let image = Image::new(&buf, PixelFormat::Rgb565, 3, 2);
+        assert_eq!(image.geometry.stride, 3);
+        assert_eq!(image.geometry.line_offset(), 0);
+    }
+
+    #[test]
+    fn stride_sets_line_offset() {
+        let mut buf = [0u32; 10 * 4];
+        let fb = Framebuf::with_stride(&mut buf, PixelFormat::Argb8888, 8, 4, 10);
+        assert_eq!(fb.geometry.line_offset(), 2);
+    }
+
+    #[test]
+    fn last_line_may_end_at_width() {
+        // Two lines of 3 pixels with a stride of 5 need 5 + 3 pixels.
+        let buf = [0u8; 8];
+        let image = Image::with_stride(&buf, PixelFormat::L8, 3, 2, 5);
+        assert_eq!(image.geometry.line_offset(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "The buffer is too small")]
+    fn buffer_too_small() {
+        let buf = [0u8; 7];
+        Image::with_stride(&buf, PixelFormat::L8, 3, 2, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "The stride must not be less than the width")]
+    fn stride_less_than_width() {
+        let buf = [0u8; 16];
+        Image::with_stride(&buf, PixelFormat::L8, 4, 2, 3);
+    }
+
+    #[test]
+    fn crop_keeps_stride() {
+        let mut buf = [0u16; 10 * 8];
+        let addr = buf.as_ptr() as usize;
+        let mut fb = Framebuf::with_stride(&mut buf, PixelFormat::Rgb565, 8, 8, 10);
+        let rect = Rect::new(2, 3, 4, 5);
+        let cropped = fb.crop(rect);
+        assert_eq!(cropped.geometry.addr, addr + (3 * 10 + 2) * 2);
+        assert_eq!(cropped.width(), 4);
+        assert_eq!(cropped.height(), 5);
+        assert_eq!(cropped.geometry.line_offset(), 6);
+    }
+
+    #[test]
+    fn crop_of_crop() {
+        let buf = [0u8; 16 * 16 * 3];
+        let addr = buf.as_ptr() as usize;
+        let image = Image::new(&buf, PixelFormat::Rgb888, 16, 16);
+        let inner = image.crop(Rect::new(4, 4, 8, 8)).crop(Rect::new(1, 2, 3, 4));
+        assert_eq!(inner.geometry.addr, addr + (6 * 16 + 5) * 3);
+        assert_eq!(inner.geometry.line_offset(), 13);
+    }
+
+    #[test]
+    #[should_panic(expected = "The rectangle is outside the buffer")]
+    fn crop_outside() {
+        let buf = [0u8; 16];
+        Image::new(&buf, PixelFormat::A8, 4, 4).crop(Rect::new(2, 0, 3, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The pixel format cannot be used for output")]
+    fn indexed_output() {
+        let mut buf = [0u8; 16];
+        Framebuf::new(&mut buf, PixelFormat::L8, 4, 4);
+    }
+
+    #[test]
+    fn clut_size() {
+        let buf = [0u8; 4];
+        let clut = [0u32; 256];
+        let image = Image::new(&buf, PixelFormat::L8, 2, 2);
+        assert_eq!(image.clut_size(), 0);
+        assert_eq!(image.with_clut(&clut).clut_size(), 255);
+        assert_eq!(image.with_clut(&clut[..16]).clut_size(), 15);
+    }
+
+    #[test]
+    #[should_panic(expected = "The pixel format is not indexed")]
+    fn clut_for_direct_color() {
+        let buf = [0u16; 4];
+        let clut = [0u32; 2];
+        Image::new(&buf, PixelFormat::Rgb565, 2, 2).with_clut(&clut);
+    }
+
+    #[test]
+    #[should_panic(expected = "The color lookup table must have between 1 and 256 entries")]
+    fn clut_too_large() {
+        let buf = [0u8; 4];
+        let clut = [0u32; 257];
+        Image::new(&buf, PixelFormat::L8, 2, 2).with_clut(&clut);
+    }
+
+    #[test]
+    fn color_drops_alpha() {
+        let buf = [0u8; 4];
+        let image = Image::new(&buf, PixelFormat::A8, 2, 2).with_color(0xAA12_3456);
+        assert_eq!(image.color, 0x0012_3456);
+    }
+}
@@ -0,0 +1,20 @@
+//! Chrom-ART Accelerator (DMA2D) driver.
+//!
+//! The DMA2D is only available on the stm32f427/f429/f437/f439/f469/f479 devices.
+
+#![feature(prelude_import)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod drv;
+mod layer;
+mod periph;
+mod pixel;
+
+pub use self::drv::{Dma2dDrv, Dma2dError, Dma2dSetup};
+pub use self::layer::{Framebuf, Image, Rect};
+pub use self::periph::*;
+pub use self::pixel::{Alpha, PixelFormat};
+
+#[prelude_import]
+#[allow(unused_imports)]
+use drone_core::prelude::*;
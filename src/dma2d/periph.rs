@@ -0,0 +1,38 @@
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts DMA2D register tokens.
+    pub macro periph_dma2d;
+    /// DMA2D peripheral.
+    pub struct Dma2dPeriph;
+    // Path prefix to reach registers.
+    drone_stm32_map::reg;
+    // Absolute path to the current module.
+    crate;
+
+    RCC {
+        AHB1ENR {
+            DMA2DEN;
+        }
+    }
+    DMA2D {
+        CR;
+        ISR;
+        IFCR;
+        FGMAR;
+        FGOR;
+        BGMAR;
+        BGOR;
+        FGPFCCR;
+        FGCOLR;
+        BGPFCCR;
+        BGCOLR;
+        FGCMAR;
+        BGCMAR;
+        OPFCCR;
+        OCOLR;
+        OMAR;
+        OOR;
+        NLR;
+    }
+}
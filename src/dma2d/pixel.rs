@@ -0,0 +1,143 @@
+/// Pixel format.
+///
+/// All formats can be used as input for the foreground and background layers,
+/// but only the direct color formats `Argb8888`, `Rgb888`, `Rgb565`, `Argb1555`, and `Argb4444` can be used as output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Argb8888,
+    Rgb888,
+    Rgb565,
+    Argb1555,
+    Argb4444,
+    /// 8 bit luminance, i.e. an index into a color lookup table (CLUT).
+    L8,
+    /// 4 bit alpha and 4 bit luminance.
+    Al44,
+    /// 8 bit alpha and 8 bit luminance.
+    Al88,
+    /// 8 bit alpha.
+    A8,
+}
+
+/// Alpha value used for a layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alpha {
+    /// Use the alpha value of the pixels.
+    Keep,
+    /// Replace the alpha value of the pixels with the given value.
+    Replace(u8),
+    /// Multiply the alpha value of the pixels with the given value.
+    Multiply(u8),
+}
+
+impl PixelFormat {
+    /// The number of bytes per pixel.
+    pub const fn bytes(self) -> usize {
+        match self {
+            PixelFormat::Argb8888 => 4,
+            PixelFormat::Rgb888 => 3,
+            PixelFormat::Rgb565 | PixelFormat::Argb1555 | PixelFormat::Argb4444 => 2,
+            PixelFormat::Al88 => 2,
+            PixelFormat::L8 | PixelFormat::Al44 | PixelFormat::A8 => 1,
+        }
+    }
+
+    /// Whether the format can be used for the output.
+    pub const fn is_output(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Argb8888
+                | PixelFormat::Rgb888
+                | PixelFormat::Rgb565
+                | PixelFormat::Argb1555
+                | PixelFormat::Argb4444
+        )
+    }
+
+    /// Whether the format is indexed, i.e. it requires a color lookup table.
+    pub const fn is_indexed(self) -> bool {
+        matches!(self, PixelFormat::L8 | PixelFormat::Al44 | PixelFormat::Al88)
+    }
+
+    /// The color mode (CM) value in the FGPFCCR, BGPFCCR, and OPFCCR registers, see RM0090 §11.5.
+    pub(crate) fn cm(self) -> u32 {
+        match self {
+            PixelFormat::Argb8888 => 0b0000,
+            PixelFormat::Rgb888 => 0b0001,
+            PixelFormat::Rgb565 => 0b0010,
+            PixelFormat::Argb1555 => 0b0011,
+            PixelFormat::Argb4444 => 0b0100,
+            PixelFormat::L8 => 0b0101,
+            PixelFormat::Al44 => 0b0110,
+            PixelFormat::Al88 => 0b0111,
+            PixelFormat::A8 => 0b1001,
+        }
+    }
+}
+
+impl Alpha {
+    /// The alpha mode (AM) and alpha (ALPHA) values in the FGPFCCR and BGPFCCR registers.
+    pub(crate) fn am_alpha(self) -> (u32, u32) {
+        match self {
+            Alpha::Keep => (0b00, 0xFF),
+            Alpha::Replace(alpha) => (0b01, alpha.into()),
+            Alpha::Multiply(alpha) => (0b10, alpha.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [PixelFormat; 9] = [
+        PixelFormat::Argb8888,
+        PixelFormat::Rgb888,
+        PixelFormat::Rgb565,
+        PixelFormat::Argb1555,
+        PixelFormat::Argb4444,
+        PixelFormat::L8,
+        PixelFormat::Al44,
+        PixelFormat::Al88,
+        PixelFormat::A8,
+    ];
+
+    #[test]
+    fn bytes() {
+        let bytes: [usize; 9] = [4, 3, 2, 2, 2, 1, 1, 2, 1];
+        for (format, bytes) in FORMATS.iter().zip(bytes.iter()) {
+            assert_eq!(format.bytes(), *bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn color_mode() {
+        // RM0090 §11.5.5, the value 0b1000 is A4 which is not supported.
+        let cm: [u32; 9] = [0b0000, 0b0001, 0b0010, 0b0011, 0b0100, 0b0101, 0b0110, 0b0111, 0b1001];
+        for (format, cm) in FORMATS.iter().zip(cm.iter()) {
+            assert_eq!(format.cm(), *cm, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn output_formats_are_direct_color() {
+        for format in FORMATS.iter() {
+            // The output color mode (OPFCCR.CM) only supports the values 0b000..=0b100.
+            assert_eq!(format.is_output(), format.cm() <= 0b0100, "{:?}", format);
+            assert!(!(format.is_output() && format.is_indexed()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn indexed_formats() {
+        let indexed = FORMATS.iter().filter(|f| f.is_indexed());
+        assert!(indexed.eq([PixelFormat::L8, PixelFormat::Al44, PixelFormat::Al88].iter()));
+    }
+
+    #[test]
+    fn alpha_mode() {
+        assert_eq!(Alpha::Keep.am_alpha(), (0b00, 0xFF));
+        assert_eq!(Alpha::Replace(0x80).am_alpha(), (0b01, 0x80));
+        assert_eq!(Alpha::Multiply(0x40).am_alpha(), (0b10, 0x40));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{
+    line::{LineLevel, MaskingPulses},
+    EdgeMap, ExtiLineMap, ExtiSourceLine,
+};
+use alloc::sync::Arc;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+use drone_core::thr::ThrToken;
+use drone_cortexm::{fib, thr::prelude::*};
+#[cfg(feature = "tim")]
+use drone_stm32_map::periph::tim::general::{Tim2, Tim5};
+#[cfg(feature = "tim")]
+use drone_stm32f4_tim_drv::{
+    GeneralTimCh, GeneralTimChDrv, OutputCompareMode, TimerCompareCh, TimerCounter,
+};
+use futures::{
+    future::{self, Either},
+    prelude::*,
+    stream,
+};
+
+/// Debounce configuration.
+/// All durations are in ticks of the [`DebounceTimer`], and must be less than half its period.
+#[derive(Clone, Copy)]
+pub struct DebounceConfig {
+    /// The time the input is left to settle after an edge, before the pin level is checked.
+    /// It is also the interval at which the level is polled when the edge that would end the current state is not selected.
+    pub hold: u32,
+    /// The time the input must be active before a `LongPress` event is generated.
+    pub long_press: Option<u32>,
+    /// Whether the input is active when the pin is low, e.g. a button to ground with a pull-up.
+    pub active_low: bool,
+}
+
+/// A debounced input event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonEvent {
+    /// The input became active.
+    Pressed,
+    /// The input has been active for the configured long press time.
+    LongPress,
+    /// The input became inactive after being active for `duration` ticks.
+    Released { duration: u32 },
+}
+
+/// The time base of a debounced stream, i.e. a counter together with a timer on that same counter.
+/// The deadlines are computed with wrapping arithmetic, so the counter must count the full 32-bit range.
+pub trait DebounceTimer: Send {
+    /// Get the current counter value.
+    fn now(&self) -> u32;
+
+    /// Returns a future that resolves when the counter reaches `deadline`.
+    /// The future resolves immediately if the deadline has passed with at most half the counter period.
+    fn sleep_until(&mut self, deadline: u32) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// A [`DebounceTimer`] counting SysTick interrupts.
+/// SysTick must be configured to interrupt periodically, e.g. every millisecond, which is then the tick of the durations.
+pub struct SysTickTimer<SysTick: ThrToken> {
+    sys_tick: SysTick,
+    ticks: Arc<AtomicU32>,
+}
+
+/// A stream of debounced input events.
+pub struct DebouncedStream<'a>(Pin<Box<dyn Stream<Item = ButtonEvent> + Send + 'a>>);
+
+struct Debouncer<'a, Line, Pulses, Timer> {
+    line: &'a Line,
+    timer: &'a mut Timer,
+    pulses: Pulses,
+    filter: Filter,
+}
+
+/// The debounce state machine, fed with the sampled levels and the time.
+struct Filter {
+    config: DebounceConfig,
+    /// The debounced state, i.e. whether the input is active.
+    pressed: bool,
+    /// The time of the press while the input is active.
+    since: Option<u32>,
+    /// Whether the long press event is yet to be generated for the current press.
+    long_press_pending: bool,
+}
+
+impl DebounceConfig {
+    /// Create a configuration with a `hold` time and no long press detection for an active high input.
+    pub const fn new(hold: u32) -> Self {
+        Self {
+            hold,
+            long_press: None,
+            active_low: false,
+        }
+    }
+}
+
+impl<SysTick: ThrToken> SysTickTimer<SysTick> {
+    /// Count the interrupts of `sys_tick`.
+    pub fn new(sys_tick: SysTick) -> Self {
+        let ticks = Arc::new(AtomicU32::new(0));
+        let weak = Arc::downgrade(&ticks);
+        sys_tick.add_fn(move || match weak.upgrade() {
+            Some(ticks) => {
+                ticks.fetch_add(1, Ordering::AcqRel);
+                fib::Yielded(())
+            }
+            None => fib::Complete(()),
+        });
+        Self { sys_tick, ticks }
+    }
+}
+
+impl<SysTick: ThrToken> DebounceTimer for SysTickTimer<SysTick> {
+    fn now(&self) -> u32 {
+        self.ticks.load(Ordering::Acquire)
+    }
+
+    fn sleep_until(&mut self, deadline: u32) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        if reached(self.now(), deadline) {
+            return Box::pin(future::ready(()));
+        }
+        let ticks = self.ticks.clone();
+        Box::pin(self.sys_tick.add_future(fib::new_fn(move || {
+            if reached(ticks.load(Ordering::Acquire), deadline) {
+                fib::Complete(())
+            } else {
+                fib::Yielded(())
+            }
+        })))
+    }
+}
+
+/// Implement [`DebounceTimer`] for the compare channels of a timer with a 32-bit counter.
+#[cfg(feature = "tim")]
+macro_rules! debounce_timer {
+    ($tim:ident) => {
+        impl<Int: IntToken, Ch: GeneralTimCh<$tim>> DebounceTimer
+            for GeneralTimChDrv<$tim, Int, Ch, OutputCompareMode>
+        {
+            fn now(&self) -> u32 {
+                self.value()
+            }
+
+            fn sleep_until(&mut self, deadline: u32) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+                Box::pin(self.next(deadline, true))
+            }
+        }
+    };
+}
+
+#[cfg(feature = "tim")]
+debounce_timer!(Tim2);
+#[cfg(feature = "tim")]
+debounce_timer!(Tim5);
+
+impl<Exti: ExtiLineMap, ExtiInt: IntToken, Src: LineLevel, Edge: EdgeMap>
+    ExtiSourceLine<Exti, ExtiInt, Src, Edge>
+{
+    /// Creates a new stream of debounced input events.
+    ///
+    /// The pin level is checked `hold` ticks after an edge on `timer`.
+    /// The line is masked during the hold time, so the bounces are not seen by the other streams and futures of the line either.
+    /// Edges that do not change the debounced level are discarded as well.
+    /// If the edge that ends the current state is not selected, e.g. the release of an active high input on a rising edge line,
+    /// then the level is polled every `hold` ticks instead.
+    pub fn debounced_stream<'a, Timer: DebounceTimer>(
+        &'a self,
+        timer: &'a mut Timer,
+        config: DebounceConfig,
+    ) -> DebouncedStream<'a> {
+        let filter = Filter::new(config, self.pin.level(), timer.now());
+        let debouncer = Debouncer {
+            line: self,
+            timer,
+            pulses: self.masking_pulses(),
+            filter,
+        };
+        DebouncedStream(Box::pin(stream::unfold(
+            debouncer,
+            move |mut debouncer| async move {
+                let event = debouncer.next_event().await?;
+                Some((event, debouncer))
+            },
+        )))
+    }
+}
+
+impl<
+        'a,
+        Exti: ExtiLineMap,
+        ExtiInt: IntToken,
+        Src: LineLevel,
+        Edge: EdgeMap,
+        Timer: DebounceTimer,
+    > Debouncer<'a, ExtiSourceLine<Exti, ExtiInt, Src, Edge>, MaskingPulses<Exti>, Timer>
+{
+    async fn next_event(&mut self) -> Option<ButtonEvent> {
+        let hold = self.filter.config.hold;
+        loop {
+            // Wait for an edge, for the long press time to elapse, or for the next poll of the level.
+            let now = self.timer.now();
+            let poll = !self.filter.edge_ends_state(Edge::RISING, Edge::FALLING);
+            let poll_at = if poll { Some(now.wrapping_add(hold)) } else { None };
+            let edge = match earliest(now, poll_at, self.filter.long_press_deadline()) {
+                Some(deadline) => {
+                    match future::select(self.pulses.next(), self.timer.sleep_until(deadline)).await
+                    {
+                        Either::Left((pulse, _)) => {
+                            pulse?;
+                            true
+                        }
+                        Either::Right(_) => false,
+                    }
+                }
+                None => {
+                    self.pulses.next().await?;
+                    true
+                }
+            };
+
+            if edge {
+                // Let the input settle while the line is masked by the edge.
+                let settled = self.timer.now().wrapping_add(hold);
+                self.timer.sleep_until(settled).await;
+                // Unmask the line before the level is sampled, so that an edge after the sample is not lost.
+                self.pulses.unmask();
+            } else if let Some(event) = self.filter.elapse(self.timer.now()) {
+                return Some(event);
+            } else if !poll {
+                continue;
+            }
+
+            if let Some(event) = self.filter.sample(self.line.pin.level(), self.timer.now()) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+impl Filter {
+    fn new(config: DebounceConfig, level: bool, now: u32) -> Self {
+        let pressed = level != config.active_low;
+        Self {
+            config,
+            pressed,
+            since: if pressed { Some(now) } else { None },
+            long_press_pending: pressed && config.long_press.is_some(),
+        }
+    }
+
+    /// Whether the edge towards the level that ends the current state is selected.
+    fn edge_ends_state(&self, rising: bool, falling: bool) -> bool {
+        // The inactive level is high for an active low input.
+        if self.pressed == self.config.active_low {
+            rising
+        } else {
+            falling
+        }
+    }
+
+    /// The time of the long press event, if it is yet to be generated.
+    fn long_press_deadline(&self) -> Option<u32> {
+        match (self.long_press_pending, self.since, self.config.long_press) {
+            (true, Some(since), Some(long_press)) => Some(since.wrapping_add(long_press)),
+            _ => None,
+        }
+    }
+
+    /// Handle the time `now` without a level change.
+    fn elapse(&mut self, now: u32) -> Option<ButtonEvent> {
+        let deadline = self.long_press_deadline()?;
+        if reached(now, deadline) {
+            self.long_press_pending = false;
+            Some(ButtonEvent::LongPress)
+        } else {
+            None
+        }
+    }
+
+    /// Handle the `level` sampled at `now` after the input has settled.
+    fn sample(&mut self, level: bool, now: u32) -> Option<ButtonEvent> {
+        let pressed = level != self.config.active_low;
+        if pressed == self.pressed {
+            // The level did not change, i.e. the edges were bounces.
+            return None;
+        }
+        self.pressed = pressed;
+        if pressed {
+            self.since = Some(now);
+            self.long_press_pending = self.config.long_press.is_some();
+            Some(ButtonEvent::Pressed)
+        } else {
+            self.long_press_pending = false;
+            let since = self.since.take()?;
+            Some(ButtonEvent::Released {
+                duration: now.wrapping_sub(since),
+            })
+        }
+    }
+}
+
+/// Whether the counter value `now` has reached `deadline`.
+fn reached(now: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(deadline) as i32) >= 0
+}
+
+/// The earliest of two deadlines relative to `now`, where a passed deadline is earlier than any future deadline.
+fn earliest(now: u32, a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            if (a.wrapping_sub(now) as i32) <= (b.wrapping_sub(now) as i32) {
+                Some(a)
+            } else {
+                Some(b)
+            }
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+impl Stream for DebouncedStream<'_> {
+    type Item = ButtonEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(long_press: Option<u32>, active_low: bool) -> DebounceConfig {
+        DebounceConfig {
+            hold: 20,
+            long_press,
+            active_low,
+        }
+    }
+
+    #[test]
+    fn idle_input_has_no_press_time() {
+        let filter = Filter::new(config(Some(100), false), false, 5);
+        assert!(!filter.pressed);
+        assert_eq!(None, filter.since);
+        assert_eq!(None, filter.long_press_deadline());
+    }
+
+    #[test]
+    fn active_input_is_pressed_from_start() {
+        let filter = Filter::new(config(Some(100), true), false, 5);
+        assert!(filter.pressed);
+        assert_eq!(Some(5), filter.since);
+        assert_eq!(Some(105), filter.long_press_deadline());
+    }
+
+    #[test]
+    fn bounces_are_discarded() {
+        let mut filter = Filter::new(config(None, false), false, 0);
+        assert_eq!(None, filter.sample(false, 10));
+        assert_eq!(Some(ButtonEvent::Pressed), filter.sample(true, 20));
+        assert_eq!(None, filter.sample(true, 30));
+        assert_eq!(
+            Some(ButtonEvent::Released { duration: 40 }),
+            filter.sample(false, 60)
+        );
+        assert_eq!(None, filter.sample(false, 70));
+    }
+
+    #[test]
+    fn active_low_input() {
+        let mut filter = Filter::new(config(None, true), true, 0);
+        assert!(!filter.pressed);
+        assert_eq!(Some(ButtonEvent::Pressed), filter.sample(false, 10));
+        assert_eq!(
+            Some(ButtonEvent::Released { duration: 15 }),
+            filter.sample(true, 25)
+        );
+    }
+
+    #[test]
+    fn duration_wraps() {
+        let mut filter = Filter::new(config(None, false), false, 0);
+        assert_eq!(Some(ButtonEvent::Pressed), filter.sample(true, u32::MAX - 5));
+        assert_eq!(
+            Some(ButtonEvent::Released { duration: 16 }),
+            filter.sample(false, 10)
+        );
+    }
+
+    #[test]
+    fn long_press_is_generated_once() {
+        let mut filter = Filter::new(config(Some(100), false), false, 0);
+        assert_eq!(None, filter.elapse(1000));
+        assert_eq!(Some(ButtonEvent::Pressed), filter.sample(true, 10));
+        assert_eq!(Some(110), filter.long_press_deadline());
+        assert_eq!(None, filter.elapse(109));
+        assert_eq!(Some(ButtonEvent::LongPress), filter.elapse(110));
+        assert_eq!(None, filter.long_press_deadline());
+        assert_eq!(None, filter.elapse(200));
+        assert_eq!(
+            Some(ButtonEvent::Released { duration: 190 }),
+            filter.sample(false, 200)
+        );
+    }
+
+    #[test]
+    fn release_cancels_long_press() {
+        let mut filter = Filter::new(config(Some(100), false), false, 0);
+        assert_eq!(Some(ButtonEvent::Pressed), filter.sample(true, 0));
+        assert_eq!(
+            Some(ButtonEvent::Released { duration: 50 }),
+            filter.sample(false, 50)
+        );
+        assert_eq!(None, filter.long_press_deadline());
+        assert_eq!(None, filter.elapse(100));
+    }
+
+    #[test]
+    fn level_is_polled_when_the_ending_edge_is_not_selected() {
+        // Active high input on a rising edge line: the press is an edge, the release is polled.
+        let mut filter = Filter::new(config(None, false), false, 0);
+        assert!(filter.edge_ends_state(true, false));
+        filter.sample(true, 0);
+        assert!(!filter.edge_ends_state(true, false));
+        assert!(filter.edge_ends_state(true, true));
+
+        // Active low input on a falling edge line.
+        let mut filter = Filter::new(config(None, true), true, 0);
+        assert!(filter.edge_ends_state(false, true));
+        filter.sample(false, 0);
+        assert!(!filter.edge_ends_state(false, true));
+    }
+
+    #[test]
+    fn deadlines() {
+        assert!(reached(10, 10));
+        assert!(reached(11, 10));
+        assert!(!reached(9, 10));
+        assert!(reached(5, u32::MAX - 5));
+        assert_eq!(Some(20), earliest(10, Some(30), Some(20)));
+        assert_eq!(Some(5), earliest(10, Some(30), Some(5)));
+        assert_eq!(Some(1), earliest(u32::MAX - 5, Some(3), Some(1)));
+        assert_eq!(Some(30), earliest(10, None, Some(30)));
+        assert_eq!(None, earliest(10, None, None));
+    }
+}
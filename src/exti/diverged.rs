@@ -1,13 +1,9 @@
+use crate::{ExtiInternalMap, ExtiLineMap, ExtiMap};
 use drone_cortexm::reg::prelude::*;
-use drone_stm32_map::periph::exti::{
-    ExtiFtsrFt, ExtiMap, ExtiPeriph, ExtiPrPif, ExtiRtsrRt, ExtiSwierSwi, SyscfgExticrExti,
-};
+use drone_stm32_map::periph::exti::ExtiPeriph;
 
 #[allow(dead_code)]
-pub(crate) struct ExtiDiverged<
-    Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif,
-> {
-    pub(crate) syscfg_exticr_exti: Exti::SSyscfgExticrExti,
+pub(crate) struct ExtiDiverged<Exti: ExtiLineMap> {
     pub(crate) exti_imr_im: Exti::CExtiImrIm,
     pub(crate) exti_emr_em: Exti::SExtiEmrEm,
     pub(crate) exti_rtsr_rt: Exti::SExtiRtsrRt,
@@ -16,10 +12,20 @@ pub(crate) struct ExtiDiverged<
     pub(crate) exti_pr_pif: Exti::CExtiPrPif,
 }
 
-impl<Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif>
-    From<ExtiPeriph<Exti>> for ExtiDiverged<Exti>
-{
-    fn from(periph: ExtiPeriph<Exti>) -> Self {
+/// The `SYSCFG_EXTICR` port selection of a gpio line.
+pub(crate) struct ExtiPortDiverged<Exti: ExtiMap> {
+    pub(crate) syscfg_exticr_exti: Exti::SSyscfgExticrExti,
+}
+
+/// The port selection of a line, which is reset when the line is disabled.
+pub(crate) trait ExtiPort: Send + Sync {
+    /// Select the gpio port `num`, where 0 is port A.
+    fn select(&self, num: u32);
+}
+
+impl<Exti: ExtiMap> ExtiDiverged<Exti> {
+    /// Split the tokens of a gpio line into the line and its port selection.
+    pub(crate) fn with_port(periph: ExtiPeriph<Exti>) -> (Self, ExtiPortDiverged<Exti>) {
         let ExtiPeriph {
             syscfg_exticr_exti,
             exti_imr_im,
@@ -29,8 +35,32 @@ impl<Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi +
             exti_swier_swi,
             exti_pr_pif,
         } = periph;
+        (
+            Self {
+                exti_imr_im: exti_imr_im.into_copy(),
+                exti_emr_em,
+                exti_rtsr_rt,
+                exti_ftsr_ft,
+                exti_swier_swi,
+                exti_pr_pif: exti_pr_pif.into_copy(),
+            },
+            ExtiPortDiverged { syscfg_exticr_exti },
+        )
+    }
+}
+
+impl<Exti: ExtiInternalMap> From<ExtiPeriph<Exti>> for ExtiDiverged<Exti> {
+    fn from(periph: ExtiPeriph<Exti>) -> Self {
+        let ExtiPeriph {
+            syscfg_exticr_exti: _,
+            exti_imr_im,
+            exti_emr_em,
+            exti_rtsr_rt,
+            exti_ftsr_ft,
+            exti_swier_swi,
+            exti_pr_pif,
+        } = periph;
         Self {
-            syscfg_exticr_exti,
             exti_imr_im: exti_imr_im.into_copy(),
             exti_emr_em,
             exti_rtsr_rt,
@@ -40,3 +70,9 @@ impl<Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi +
         }
     }
 }
+
+impl<Exti: ExtiMap> ExtiPort for ExtiPortDiverged<Exti> {
+    fn select(&self, num: u32) {
+        self.syscfg_exticr_exti.write_bits(num);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{diverged::ExtiDiverged, ExtiLine, Syscfg, ExtiMap};
+use crate::{diverged::{ExtiDiverged, ExtiPortDiverged}, line::HeadNum, shared::ExtiDispatch, ExtiLine, Syscfg, ExtiMap};
 use core::marker::PhantomData;
 use alloc::sync::Arc;
 use drone_cortexm::{reg::prelude::*, thr::prelude::*};
@@ -7,19 +7,36 @@ use drone_stm32_map::periph::{
 };
 use drone_stm32f4_gpio_drv::{GpioHeadMap, GpioPinMap, GpioPin, prelude::*};
 
-pub trait EdgeMap: Send + Sync + 'static {}
+pub trait EdgeMap: Send + Sync + 'static {
+    /// Whether the rising trigger is enabled.
+    const RISING: bool;
+    /// Whether the falling trigger is enabled.
+    const FALLING: bool;
+}
 
 pub struct RisingEdge;
-impl EdgeMap for RisingEdge {}
+impl EdgeMap for RisingEdge {
+    const RISING: bool = true;
+    const FALLING: bool = false;
+}
 
 pub struct FallingEdge;
-impl EdgeMap for FallingEdge {}
+impl EdgeMap for FallingEdge {
+    const RISING: bool = false;
+    const FALLING: bool = true;
+}
 
 pub struct BothEdges;
-impl EdgeMap for BothEdges {}
+impl EdgeMap for BothEdges {
+    const RISING: bool = true;
+    const FALLING: bool = true;
+}
 
 pub struct NoEdge;
-impl EdgeMap for NoEdge {}
+impl EdgeMap for NoEdge {
+    const RISING: bool = false;
+    const FALLING: bool = false;
+}
 
 
 /// EXTI driver.
@@ -30,7 +47,9 @@ pub struct ExtiDrv<
     Edge,
 > {
     pub(crate) exti: Arc<ExtiDiverged<Exti>>,
+    pub(crate) port: Arc<ExtiPortDiverged<Exti>>,
     pub(crate) exti_int: ExtiInt,
+    pub(crate) dispatch: Arc<ExtiDispatch>,
     head: PhantomData<Head>,
     edge: PhantomData<Edge>,
 }
@@ -44,9 +63,20 @@ impl<
     /// Sets up a new [`ExtiDrv`].
     /// Syscfg is required as its clock must be enabled prior to initialization.
     pub fn new(exti: ExtiPeriph<Exti>, exti_int: ExtiInt, _syscfg: &Syscfg) -> Self {
+        Self::with_dispatch(exti, exti_int, ExtiDispatch::new(exti_int))
+    }
+
+    pub(crate) fn with_dispatch(
+        exti: ExtiPeriph<Exti>,
+        exti_int: ExtiInt,
+        dispatch: Arc<ExtiDispatch>,
+    ) -> Self {
+        let (exti, port) = ExtiDiverged::with_port(exti);
         Self {
-            exti: Arc::new(exti.into()),
+            exti: Arc::new(exti),
+            port: Arc::new(port),
             exti_int,
+            dispatch,
             head: PhantomData,
             edge: PhantomData,
         }
@@ -63,7 +93,9 @@ impl<
         self.exti.exti_rtsr_rt.set_bit(); // rising trigger enabled
         ExtiDrv {
             exti: self.exti,
+            port: self.port,
             exti_int: self.exti_int,
+            dispatch: self.dispatch,
             head: PhantomData,
             edge: PhantomData,
         }
@@ -73,7 +105,9 @@ impl<
         self.exti.exti_ftsr_ft.set_bit(); // falling trigger enabled
         ExtiDrv {
             exti: self.exti,
+            port: self.port,
             exti_int: self.exti_int,
+            dispatch: self.dispatch,
             head: PhantomData,
             edge: PhantomData,
         }
@@ -84,7 +118,31 @@ impl<
         self.exti.exti_ftsr_ft.set_bit(); // falling trigger enabled
         ExtiDrv {
             exti: self.exti,
+            port: self.port,
             exti_int: self.exti_int,
+            dispatch: self.dispatch,
+            head: PhantomData,
+            edge: PhantomData,
+        }
+    }
+}
+
+impl<
+        Exti: ExtiMap,
+        ExtiInt: IntToken,
+        Head: GpioHeadMap,
+        Edge: EdgeMap,
+    > ExtiDrv<Exti, ExtiInt, Head, Edge>
+{
+    /// Use the line for a pin on another port.
+    /// The lines previously created from the driver must be dropped or released.
+    pub fn into_head<ToHead: GpioHeadMap>(self) -> ExtiDrv<Exti, ExtiInt, ToHead, Edge> {
+        assert_eq!(Arc::strong_count(&self.exti), 1, "The line is still in use");
+        ExtiDrv {
+            exti: self.exti,
+            port: self.port,
+            exti_int: self.exti_int,
+            dispatch: self.dispatch,
             head: PhantomData,
             edge: PhantomData,
         }
@@ -105,6 +163,12 @@ pub trait ExtiDrvLine<
     ) -> ExtiLine<Exti, ExtiInt, Pin, PinMode, PinType, PinPull, Edge>;
 }
 
+/// The gpio port of a pin, as selected in `SYSCFG_EXTICR`.
+pub trait ExtiPinHead: GpioPinMap {
+    /// The port of the pin.
+    type Head: GpioHeadMap + HeadNum;
+}
+
 pub trait ExtiPinModes: PinModeMap {}
 impl ExtiPinModes for InputMode {}
 impl<Af: PinAf> ExtiPinModes for AlternateMode<Af> {}
@@ -113,6 +177,10 @@ impl<Af: PinAf> ExtiPinModes for AlternateMode<Af> {}
 macro_rules! exti_line {
     ($($exti:ident, $head:ident, $pin:ident;)+) => {
         $(
+            impl crate::drv::ExtiPinHead for $pin {
+                type Head = $head;
+            }
+
             impl<
                 ExtiInt: drone_cortexm::thr::IntToken,
                 PinMode: crate::drv::ExtiPinModes,
@@ -1,27 +1,44 @@
 #![feature(prelude_import)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
+mod debounce;
 mod diverged;
 mod drv;
 mod line;
 mod mappings;
 mod periph;
+mod shared;
 mod syscfg;
 
 extern crate alloc;
 
+pub use self::debounce::{ButtonEvent, DebounceConfig, DebounceTimer, DebouncedStream, SysTickTimer};
 pub use self::drv::ExtiDrv;
-pub use self::line::{ExtiLine, ExtiOverflow};
+pub use self::line::{
+    EdgeEvent, ExtiEvent, ExtiInternalEvent, ExtiInternalLine, ExtiLine, ExtiOverflow,
+    ExtiSourceEvent, ExtiSourceLine, InternalSource, LineLevel,
+};
 pub use self::syscfg::Syscfg;
 pub use self::periph::*;
+pub use self::shared::ExtiShared;
 pub use self::prelude::*;
 use drone_stm32_map::periph::exti::{
     ExtiFtsrFt, ExtiPrPif, ExtiRtsrRt, ExtiSwierSwi, SyscfgExticrExti,
 };
 
 /// A redefinition of the `ExtiMap` from the `drone-stm32-map` crate with forced
-/// availability of required registers.
-pub trait ExtiMap: drone_stm32_map::periph::exti::ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif {}
+/// availability of the registers required by any line.
+pub trait ExtiLineMap: drone_stm32_map::periph::exti::ExtiMap + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif + 'static {
+    /// The line number.
+    const LINE: u32;
+}
+
+/// An EXTI line that is connected to a gpio pin through the `SYSCFG_EXTICR` port selection.
+pub trait ExtiMap: ExtiLineMap + SyscfgExticrExti {}
+
+/// An EXTI line that is connected to a peripheral instead of a gpio pin,
+/// i.e. without the `SYSCFG_EXTICR` port selection.
+pub trait ExtiInternalMap: ExtiLineMap {}
 
 pub mod prelude {
     pub use crate::drv::ExtiDrvLine;
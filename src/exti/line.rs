@@ -1,9 +1,10 @@
-use crate::{EdgeMap, ExtiDrv, ExtiMap, FallingEdge, diverged::ExtiDiverged};
-use core::{marker::PhantomData, pin::Pin, task::{Context, Poll}};
+use crate::{BothEdges, EdgeMap, ExtiDrv, ExtiInternalMap, ExtiLineMap, ExtiMap, FallingEdge, NoEdge, RisingEdge, diverged::{ExtiDiverged, ExtiPort}, shared::ExtiDispatch};
+use core::{marker::PhantomData, num::NonZeroUsize, pin::Pin, sync::atomic::{AtomicBool, AtomicU32, Ordering}, task::{Context, Poll}};
 use alloc::sync::Arc;
 use displaydoc::Display;
-use drone_core::fib::{FiberStreamPulse, TryFiberStreamPulse};
+use drone_core::fib::{FiberStreamPulse, FiberStreamRing, TryFiberStreamPulse, TryFiberStreamRing};
 use drone_cortexm::{fib, fib::Fiber, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::exti::ExtiPeriph;
 use drone_stm32f4_gpio_drv::{GpioPin, prelude::*, GpioHeadMap, GpioPinMap};
 use futures::{future, prelude::*};
 
@@ -11,25 +12,82 @@ use futures::{future, prelude::*};
 #[derive(Display, Debug)]
 pub struct ExtiOverflow;
 
+/// An edge on an EXTI line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EdgeEvent {
+    /// The pin level when the edge was handled, i.e. `true` after a rising edge.
+    pub level: bool,
+    /// The timestamp when the edge was handled.
+    pub timestamp: u32,
+}
+
 pub trait HeadNum {
     const NUM: u32;
 }
 
-pub struct ExtiLine<
-    Exti: ExtiMap,
-    ExtiInt: IntToken,
-    Pin: GpioPinMap,
-    PinMode: PinModeMap,
-    PinType: PinTypeMap,
-    PinPull: PinPullMap,
-    Edge: EdgeMap,
-> {
-    exti: Arc<ExtiDiverged<Exti>>,
+/// A line source with a readable level, i.e. a gpio pin in input or alternate mode.
+pub trait LineLevel: Send + Sync + 'static {
+    /// Get the current level.
+    fn level(&self) -> bool;
+
+    /// Get a copy of the source for reading the level from a fiber.
+    ///
+    /// # Safety
+    ///
+    /// The copy must only be used to read the level.
+    unsafe fn clone_level(&self) -> Self;
+}
+
+/// The source of an internal line, i.e. a peripheral output instead of a gpio pin.
+pub struct InternalSource;
+
+/// An EXTI line connected to `Src`, i.e. a gpio pin or a peripheral output.
+pub struct ExtiSourceLine<Exti: ExtiLineMap, ExtiInt: IntToken, Src, Edge: EdgeMap> {
+    pub(crate) shared: Arc<LineShared<Exti>>,
     exti_int: ExtiInt,
-    pub pin: GpioPin<Pin, PinMode, PinType, PinPull>,
+    /// The source of the line.
+    pub pin: Src,
     edge: PhantomData<Edge>,
 }
 
+/// An EXTI line connected to a gpio pin.
+pub type ExtiLine<Exti, ExtiInt, Pin, PinMode, PinType, PinPull, Edge> =
+    ExtiSourceLine<Exti, ExtiInt, GpioPin<Pin, PinMode, PinType, PinPull>, Edge>;
+
+/// EXTI driver for an internal line, i.e. a line that is connected to a peripheral instead of a gpio pin.
+///
+/// The internal lines are
+/// * `Exti16`: PVD output,
+/// * `Exti17`: RTC alarm,
+/// * `Exti18`: USB OTG FS wakeup,
+/// * `Exti19`: Ethernet wakeup,
+/// * `Exti20`: USB OTG HS wakeup,
+/// * `Exti21`: RTC tamper and timestamp,
+/// * `Exti22`: RTC wakeup.
+pub type ExtiInternalLine<Exti, ExtiInt, Edge> = ExtiSourceLine<Exti, ExtiInt, InternalSource, Edge>;
+
+/// The line state shared between the line and the fibers of its streams and futures.
+/// The line is disabled when the last of them is dropped, see [`Drop`].
+pub(crate) struct LineShared<Exti: ExtiLineMap> {
+    pub(crate) exti: Arc<ExtiDiverged<Exti>>,
+    /// The port selection of a gpio line.
+    port: Option<Arc<dyn ExtiPort>>,
+    pub(crate) dispatch: Arc<ExtiDispatch>,
+    /// The selected edges, indexed by the level after the edge.
+    edges: [AtomicBool; 2],
+    /// The number of level waits that need the edge towards the level, indexed by the level.
+    waits: [AtomicU32; 2],
+    /// Whether the line is masked by a [`MaskingPulses`] stream.
+    masked: AtomicBool,
+}
+
+/// A pulse stream that masks the line at the first pulse, e.g. to ignore the bounces after an edge.
+/// The line stays masked for all its streams and futures until it is unmasked, or the stream is dropped.
+pub(crate) struct MaskingPulses<Exti: ExtiLineMap> {
+    pulses: FiberStreamPulse,
+    shared: Arc<LineShared<Exti>>,
+}
+
 impl<
         Exti: ExtiMap,
         ExtiInt: IntToken,
@@ -44,10 +102,56 @@ impl<
         exti: &ExtiDrv<Exti, ExtiInt, Head, Edge>,
         pin: GpioPin<Pin, PinMode, PinType, PinPull>,
     ) -> Self {
-        exti.exti.syscfg_exticr_exti.write_bits(Head::NUM);
+        exti.port.select(Head::NUM);
+        let port: Arc<dyn ExtiPort> = exti.port.clone();
+        Self::with_shared(
+            exti.exti.clone(),
+            Some(port),
+            exti.dispatch.clone(),
+            exti.exti_int,
+            pin,
+        )
+    }
+}
+
+impl<Exti: ExtiInternalMap, ExtiInt: IntToken> ExtiInternalLine<Exti, ExtiInt, NoEdge> {
+    /// Sets up a new [`ExtiInternalLine`].
+    pub fn new(exti: ExtiPeriph<Exti>, exti_int: ExtiInt) -> Self {
+        Self::with_shared(
+            Arc::new(exti.into()),
+            None,
+            ExtiDispatch::new(exti_int),
+            exti_int,
+            InternalSource,
+        )
+    }
+}
+
+impl<Exti: ExtiLineMap, ExtiInt: IntToken, Src, Edge: EdgeMap>
+    ExtiSourceLine<Exti, ExtiInt, Src, Edge>
+{
+    fn with_shared(
+        exti: Arc<ExtiDiverged<Exti>>,
+        port: Option<Arc<dyn ExtiPort>>,
+        dispatch: Arc<ExtiDispatch>,
+        exti_int: ExtiInt,
+        pin: Src,
+    ) -> Self {
+        let shared = Arc::new(LineShared {
+            exti,
+            port,
+            dispatch,
+            edges: Default::default(),
+            waits: Default::default(),
+            masked: AtomicBool::new(false),
+        });
+        // The triggers are disabled when a previous line is dropped.
+        shared.select::<Edge>();
+        shared.dispatch.add_line(Exti::LINE);
+        shared.exti.exti_imr_im.set_bit(); // unmask interrupt request
         Self {
-            exti: exti.exti.clone(),
-            exti_int: exti.exti_int,
+            shared,
+            exti_int,
             pin,
             edge: PhantomData,
         }
@@ -55,72 +159,383 @@ impl<
 
     /// Creates a new saturating stream of external events.
     pub fn saturating_pulse_stream(&self) -> FiberStreamPulse {
-        self.exti_int.add_saturating_pulse_stream(self.new_fib())
+        let stream = self.exti_int.add_saturating_pulse_stream(self.new_fib());
+        self.dispatch_first();
+        stream
     }
 
     /// Creates a new fallible stream of external events.
     pub fn pulse_try_stream(
         &self,
     ) -> TryFiberStreamPulse<ExtiOverflow> {
-        self.exti_int
-            .add_pulse_try_stream(|| Err(ExtiOverflow), self.new_fib())
+        let stream = self
+            .exti_int
+            .add_pulse_try_stream(|| Err(ExtiOverflow), self.new_fib());
+        self.dispatch_first();
+        stream
     }
 
-    fn new_fib<R>(&self) -> impl Fiber<Input = (), Yield = Option<usize>, Return = R> {
-        let exti_pr_pif = self.exti.exti_pr_pif;
-        let fib = fib::new_fn(move || {
-            if exti_pr_pif.read_bit() {
-                // Selected trigger request occurred: clear pending flag
-                exti_pr_pif.set_bit();
-                fib::Yielded(Some(1))
+    /// Wait for the next selected edge on the line.
+    pub fn wait_edge(&self) -> WaitFuture {
+        let mut events = self.events();
+        let future = self.exti_int.add_future(fib::new_fn(move || {
+            if events.take() > 0 {
+                // Selected trigger request occurred
+                fib::Complete(())
             } else {
-                fib::Yielded(None)
+                fib::Yielded(())
             }
-        });
-        self.exti.exti_imr_im.set_bit(); // unmask interrupt request
-        fib
+        }));
+        self.dispatch_first();
+        WaitFuture(Box::pin(future))
+    }
+
+    /// Select the rising edge only.
+    pub fn into_rising_edge(self) -> ExtiSourceLine<Exti, ExtiInt, Src, RisingEdge> {
+        self.into_edge()
+    }
+
+    /// Select the falling edge only.
+    pub fn into_falling_edge(self) -> ExtiSourceLine<Exti, ExtiInt, Src, FallingEdge> {
+        self.into_edge()
+    }
+
+    /// Select both edges.
+    pub fn into_both_edges(self) -> ExtiSourceLine<Exti, ExtiInt, Src, BothEdges> {
+        self.into_edge()
+    }
+
+    /// Disable both edges.
+    pub fn into_no_edge(self) -> ExtiSourceLine<Exti, ExtiInt, Src, NoEdge> {
+        self.into_edge()
+    }
+
+    /// Release the line and get back the pin.
+    /// The line is disabled once its remaining streams and futures are dropped as well.
+    pub fn release(self) -> Src {
+        self.pin
+    }
+
+    /// Trigger the line from software by generating an interrupt request,
+    /// exactly as if the selected edge had occurred on the source.
+    pub fn trigger(&self) {
+        // The software interrupt bit is cleared together with the pending flag,
+        // i.e. when the event is dispatched.
+        self.shared.exti.exti_swier_swi.set_bit();
+    }
+
+    /// Use the line in event mode, where the selected edges wake the core from WFE,
+    /// or from Stop mode entered with WFE, without running an interrupt handler.
+    pub fn into_event(self) -> ExtiSourceEvent<Exti, ExtiInt, Src, Edge> {
+        self.shared.exti.exti_imr_im.clear_bit(); // mask interrupt request
+        self.shared.exti.exti_emr_em.set_bit(); // unmask event request
+        ExtiSourceEvent { line: self }
+    }
+
+    fn new_fib<R>(&self) -> impl Fiber<Input = (), Yield = Option<usize>, Return = R> {
+        let mut events = self.events();
+        fib::new_fn(move || match events.take() {
+            0 => fib::Yielded(None),
+            count => fib::Yielded(Some(count as usize)),
+        })
+    }
+
+    /// Creates a new saturating stream of external events, which masks the line at the first event.
+    pub(crate) fn masking_pulses(&self) -> MaskingPulses<Exti> {
+        let mut events = self.events();
+        let pulses = self.exti_int.add_saturating_pulse_stream(fib::new_fn(move || {
+            match events.take() {
+                0 => fib::Yielded(None),
+                count => {
+                    // Mask the line already in the interrupt of the first edge.
+                    events.shared.mask();
+                    fib::Yielded(Some(count as usize))
+                }
+            }
+        }));
+        self.dispatch_first();
+        MaskingPulses {
+            pulses,
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn into_edge<ToEdge: EdgeMap>(self) -> ExtiSourceLine<Exti, ExtiInt, Src, ToEdge> {
+        self.shared.select::<ToEdge>();
+        ExtiSourceLine {
+            shared: self.shared,
+            exti_int: self.exti_int,
+            pin: self.pin,
+            edge: PhantomData,
+        }
+    }
+
+    /// Start consuming the events of the line from now on.
+    fn events(&self) -> LineEvents<Exti> {
+        LineEvents::new(self.shared.clone())
+    }
+
+    /// Attach a new dispatcher fiber, so that the fibers of the line run after the events are dispatched.
+    fn dispatch_first(&self) {
+        self.shared.dispatch.attach(self.exti_int);
     }
 }
 
-impl<
-        Exti: ExtiMap,
-        ExtiInt: IntToken,
-        Pin: GpioPinMap,
-        PinMode: PinGetMode,
-        PinType: PinTypeMap,
-        PinPull: PinPullMap,
-    > ExtiLine<Exti, ExtiInt, Pin, PinMode, PinType, PinPull, FallingEdge>
+impl<Exti: ExtiLineMap, ExtiInt: IntToken, Src: LineLevel, Edge: EdgeMap>
+    ExtiSourceLine<Exti, ExtiInt, Src, Edge>
 {
+    /// Creates a new saturating stream of timestamped edges.
+    /// The timestamp is sampled by calling `timestamp`, e.g. `move || dwt.value()` for a `DwtCounter`.
+    pub fn saturating_edge_stream<Timestamp: FnMut() -> u32 + Send + 'static>(
+        &self,
+        capacity: usize,
+        timestamp: Timestamp,
+    ) -> FiberStreamRing<EdgeEvent> {
+        let stream = self
+            .exti_int
+            .add_saturating_stream(capacity, self.new_edge_fib(timestamp));
+        self.dispatch_first();
+        stream
+    }
+
+    /// Creates a new fallible stream of timestamped edges.
+    /// The timestamp is sampled by calling `timestamp`, e.g. `move || dwt.value()` for a `DwtCounter`.
+    pub fn edge_try_stream<Timestamp: FnMut() -> u32 + Send + 'static>(
+        &self,
+        capacity: usize,
+        timestamp: Timestamp,
+    ) -> TryFiberStreamRing<EdgeEvent, ExtiOverflow> {
+        let stream = self
+            .exti_int
+            .add_try_stream(capacity, |_| Err(ExtiOverflow), self.new_edge_fib(timestamp));
+        self.dispatch_first();
+        stream
+    }
+
+    /// Wait for the line to become high. Return immediately if this is already the case.
+    pub fn wait_high(&self) -> WaitFuture {
+        self.wait_level(true)
+    }
+
     /// Wait for the line to become low. Return immediately if this is already the case.
     pub fn wait_low(&self) -> WaitFuture {
-        // Register interrupt
-        let exti_pr_pif = self.exti.exti_pr_pif;
-        let exti_imr_im = self.exti.exti_imr_im;
+        self.wait_level(false)
+    }
+
+    /// Wait for the line to reach `level`. Return immediately if this is already the case.
+    /// The trigger for the edge towards `level` is enabled while waiting if it is not selected,
+    /// in which case the other streams and futures of the line also see that edge.
+    pub fn wait_level(&self, level: bool) -> WaitFuture {
+        let shared = self.shared.clone();
+        // The fiber only reads the pin level, so the copy does not change the pin configuration.
+        let pin = unsafe { self.pin.clone_level() };
+
+        self.shared.add_wait(level);
         let future = self.exti_int.add_future(fib::new_fn(move || {
-            if exti_pr_pif.read_bit() {
-                // Selected trigger request occurred
-                exti_imr_im.clear_bit(); // Disable interrupt
+            if pin.level() == level {
+                shared.remove_wait(level);
                 fib::Complete(())
             } else {
                 fib::Yielded(())
             }
         }));
 
-        exti_pr_pif.set_bit(); // Clear pending flag
-        exti_imr_im.set_bit(); // Enable interrupt
-
-        // Only wait for falling interrupt if we are currently high
-        if self.pin.get() {
+        if self.pin.level() == level {
+            // No edge is generated as the level is already reached. Run the fiber from the
+            // interrupt to complete it, without a software trigger that the other streams and
+            // futures of the line would see.
+            self.exti_int.set_pending();
+            WaitFuture(Box::pin(future::ready(())))
+        } else {
             WaitFuture(Box::pin(future))
         }
-        else {
-            exti_imr_im.clear_bit(); // Disable interrupt
-            WaitFuture(Box::pin(future::ready(())))
+    }
+
+    fn new_edge_fib<Timestamp: FnMut() -> u32 + Send + 'static, R>(
+        &self,
+        mut timestamp: Timestamp,
+    ) -> impl Fiber<Input = (), Yield = Option<EdgeEvent>, Return = R> {
+        let mut events = self.events();
+        // The fiber only reads the pin level, so the copy does not change the pin configuration.
+        let pin = unsafe { self.pin.clone_level() };
+        fib::new_fn(move || {
+            if events.take() > 0 {
+                // Sample the timestamp and the level as early as possible.
+                let timestamp = timestamp();
+                let level = pin.level();
+                fib::Yielded(Some(EdgeEvent { level, timestamp }))
+            } else {
+                fib::Yielded(None)
+            }
+        })
+    }
+}
+
+impl<Pin: GpioPinMap, PinMode: PinGetMode, PinType: PinTypeMap, PinPull: PinPullMap> LineLevel
+    for GpioPin<Pin, PinMode, PinType, PinPull>
+{
+    fn level(&self) -> bool {
+        self.get()
+    }
+
+    unsafe fn clone_level(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl<Exti: ExtiLineMap> LineShared<Exti> {
+    /// Select the edges of `Edge`.
+    fn select<Edge: EdgeMap>(&self) {
+        self.edges[1].store(Edge::RISING, Ordering::Release);
+        self.edges[0].store(Edge::FALLING, Ordering::Release);
+        self.update_trigger(true);
+        self.update_trigger(false);
+    }
+
+    /// Register a wait for `level`, which needs the trigger for the edge towards `level`.
+    fn add_wait(&self, level: bool) {
+        self.waits[level as usize].fetch_add(1, Ordering::AcqRel);
+        self.update_trigger(level);
+    }
+
+    /// Unregister a wait for `level`.
+    fn remove_wait(&self, level: bool) {
+        self.waits[level as usize].fetch_sub(1, Ordering::AcqRel);
+        self.update_trigger(level);
+    }
+
+    /// Mask the interrupt request of the line until [`Self::unmask`].
+    fn mask(&self) {
+        self.masked.store(true, Ordering::Release);
+        self.exti.exti_imr_im.clear_bit(); // mask interrupt request
+    }
+
+    /// Unmask the interrupt request of a masked line, and discard the edges while it was masked.
+    fn unmask(&self) {
+        if self.masked.swap(false, Ordering::AcqRel) {
+            self.exti.exti_pr_pif.set_bit(); // clear pending flag
+            self.exti.exti_imr_im.set_bit(); // unmask interrupt request
+        }
+    }
+
+    /// Enable the trigger for the edge towards `level` if it is selected or needed by a wait.
+    fn update_trigger(&self, level: bool) {
+        let enable = self.edges[level as usize].load(Ordering::Acquire)
+            || self.waits[level as usize].load(Ordering::Acquire) > 0;
+        match (level, enable) {
+            (true, true) => self.exti.exti_rtsr_rt.set_bit(),
+            (true, false) => self.exti.exti_rtsr_rt.clear_bit(),
+            (false, true) => self.exti.exti_ftsr_ft.set_bit(),
+            (false, false) => self.exti.exti_ftsr_ft.clear_bit(),
         }
     }
 }
 
-pub struct WaitFuture<'a>(Pin<Box<dyn Future<Output = ()> + Send + 'a>>);
+impl<Exti: ExtiLineMap> Drop for LineShared<Exti> {
+    /// Mask the line, disable both edges, and reset the SYSCFG port mapping of a gpio line,
+    /// so that the line can be assigned to another port.
+    fn drop(&mut self) {
+        let exti = &self.exti;
+        exti.exti_imr_im.clear_bit(); // mask interrupt request
+        exti.exti_emr_em.clear_bit(); // mask event request
+        exti.exti_rtsr_rt.clear_bit(); // rising trigger disabled
+        exti.exti_ftsr_ft.clear_bit(); // falling trigger disabled
+        exti.exti_pr_pif.set_bit(); // clear pending flag
+        if let Some(port) = &self.port {
+            port.select(0); // release the port mapping
+        }
+        self.dispatch.remove_line(Exti::LINE);
+    }
+}
+
+impl<Exti: ExtiLineMap> MaskingPulses<Exti> {
+    /// Unmask the line, and discard the edges while it was masked.
+    pub(crate) fn unmask(&self) {
+        self.shared.unmask();
+    }
+}
+
+impl<Exti: ExtiLineMap> Stream for MaskingPulses<Exti> {
+    type Item = NonZeroUsize;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.pulses).poll_next(cx)
+    }
+}
+
+impl<Exti: ExtiLineMap> Drop for MaskingPulses<Exti> {
+    fn drop(&mut self) {
+        self.shared.unmask();
+    }
+}
+
+/// An EXTI line in event mode.
+pub struct ExtiSourceEvent<Exti: ExtiLineMap, ExtiInt: IntToken, Src, Edge: EdgeMap> {
+    line: ExtiSourceLine<Exti, ExtiInt, Src, Edge>,
+}
+
+/// An EXTI gpio line in event mode.
+pub type ExtiEvent<Exti, ExtiInt, Pin, PinMode, PinType, PinPull, Edge> =
+    ExtiSourceEvent<Exti, ExtiInt, GpioPin<Pin, PinMode, PinType, PinPull>, Edge>;
+
+/// An EXTI internal line in event mode.
+pub type ExtiInternalEvent<Exti, ExtiInt, Edge> = ExtiSourceEvent<Exti, ExtiInt, InternalSource, Edge>;
+
+impl<Exti: ExtiLineMap, ExtiInt: IntToken, Src, Edge: EdgeMap>
+    ExtiSourceEvent<Exti, ExtiInt, Src, Edge>
+{
+    /// Generate an event from software, e.g. to wake the core from WFE.
+    pub fn trigger(&self) {
+        let exti = &self.line.shared.exti;
+        // An event is generated when the software interrupt bit is set from 0 to 1,
+        // and the bit is then reset by clearing the pending flag so that the line can be triggered again.
+        exti.exti_swier_swi.set_bit();
+        exti.exti_pr_pif.set_bit();
+    }
+
+    /// The pin of the line.
+    pub fn pin(&self) -> &Src {
+        &self.line.pin
+    }
+
+    /// Use the line in interrupt mode again.
+    pub fn into_line(self) -> ExtiSourceLine<Exti, ExtiInt, Src, Edge> {
+        let line = self.line;
+        line.shared.exti.exti_emr_em.clear_bit(); // mask event request
+        line.shared.exti.exti_imr_im.set_bit(); // unmask interrupt request
+        line
+    }
+}
+
+/// The events of a line as seen by one of its fibers.
+/// The fiber keeps the line enabled while it is registered.
+struct LineEvents<Exti: ExtiLineMap> {
+    shared: Arc<LineShared<Exti>>,
+    seen: u32,
+}
+
+impl<Exti: ExtiLineMap> LineEvents<Exti> {
+    fn new(shared: Arc<LineShared<Exti>>) -> Self {
+        let mut events = Self { shared, seen: 0 };
+        events.seen = events.count();
+        events
+    }
+
+    /// Take the number of events since the previous call.
+    fn take(&mut self) -> u32 {
+        let count = self.count();
+        let events = count.wrapping_sub(self.seen);
+        self.seen = count;
+        events
+    }
+
+    /// The number of events dispatched for the line.
+    fn count(&self) -> u32 {
+        self.shared.dispatch.events(Exti::LINE)
+    }
+}
+
+pub struct WaitFuture<'a>(pub(crate) Pin<Box<dyn Future<Output = ()> + Send + 'a>>);
 
 impl Future for WaitFuture<'_> {
     type Output = ();
@@ -128,4 +543,4 @@ impl Future for WaitFuture<'_> {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         self.0.as_mut().poll(cx)
     }
-}
\ No newline at end of file
+}
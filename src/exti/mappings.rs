@@ -10,23 +10,100 @@ macro_rules! port {
     }
 }
 
+impl crate::ExtiLineMap for Exti0 {
+    const LINE: u32 = 0;
+}
 impl crate::ExtiMap for Exti0 {}
+impl crate::ExtiLineMap for Exti1 {
+    const LINE: u32 = 1;
+}
 impl crate::ExtiMap for Exti1 {}
+impl crate::ExtiLineMap for Exti2 {
+    const LINE: u32 = 2;
+}
 impl crate::ExtiMap for Exti2 {}
+impl crate::ExtiLineMap for Exti3 {
+    const LINE: u32 = 3;
+}
 impl crate::ExtiMap for Exti3 {}
+impl crate::ExtiLineMap for Exti4 {
+    const LINE: u32 = 4;
+}
 impl crate::ExtiMap for Exti4 {}
+impl crate::ExtiLineMap for Exti5 {
+    const LINE: u32 = 5;
+}
 impl crate::ExtiMap for Exti5 {}
+impl crate::ExtiLineMap for Exti6 {
+    const LINE: u32 = 6;
+}
 impl crate::ExtiMap for Exti6 {}
+impl crate::ExtiLineMap for Exti7 {
+    const LINE: u32 = 7;
+}
 impl crate::ExtiMap for Exti7 {}
+impl crate::ExtiLineMap for Exti8 {
+    const LINE: u32 = 8;
+}
 impl crate::ExtiMap for Exti8 {}
+impl crate::ExtiLineMap for Exti9 {
+    const LINE: u32 = 9;
+}
 impl crate::ExtiMap for Exti9 {}
+impl crate::ExtiLineMap for Exti10 {
+    const LINE: u32 = 10;
+}
 impl crate::ExtiMap for Exti10 {}
+impl crate::ExtiLineMap for Exti11 {
+    const LINE: u32 = 11;
+}
 impl crate::ExtiMap for Exti11 {}
+impl crate::ExtiLineMap for Exti12 {
+    const LINE: u32 = 12;
+}
 impl crate::ExtiMap for Exti12 {}
+impl crate::ExtiLineMap for Exti13 {
+    const LINE: u32 = 13;
+}
 impl crate::ExtiMap for Exti13 {}
+impl crate::ExtiLineMap for Exti14 {
+    const LINE: u32 = 14;
+}
 impl crate::ExtiMap for Exti14 {}
+impl crate::ExtiLineMap for Exti15 {
+    const LINE: u32 = 15;
+}
 impl crate::ExtiMap for Exti15 {}
 
+impl crate::ExtiLineMap for Exti16 {
+    const LINE: u32 = 16; // PVD output
+}
+impl crate::ExtiInternalMap for Exti16 {}
+impl crate::ExtiLineMap for Exti17 {
+    const LINE: u32 = 17; // RTC alarm
+}
+impl crate::ExtiInternalMap for Exti17 {}
+impl crate::ExtiLineMap for Exti18 {
+    const LINE: u32 = 18; // USB OTG FS wakeup
+}
+impl crate::ExtiInternalMap for Exti18 {}
+impl crate::ExtiLineMap for Exti19 {
+    const LINE: u32 = 19; // Ethernet wakeup
+}
+impl crate::ExtiInternalMap for Exti19 {}
+impl crate::ExtiLineMap for Exti20 {
+    const LINE: u32 = 20; // USB OTG HS wakeup
+}
+impl crate::ExtiInternalMap for Exti20 {}
+impl crate::ExtiLineMap for Exti21 {
+    const LINE: u32 = 21; // RTC tamper and timestamp
+}
+impl crate::ExtiInternalMap for Exti21 {}
+impl crate::ExtiLineMap for Exti22 {
+    const LINE: u32 = 22; // RTC wakeup
+}
+impl crate::ExtiInternalMap for Exti22 {}
+
 port!(GpioAHead, 0,
     (
         Exti0, GpioA0;
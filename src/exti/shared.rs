@@ -0,0 +1,184 @@
+use crate::{
+    drv::{ExtiDrvLine, ExtiPinHead},
+    ExtiDrv, ExtiLine, ExtiMap, NoEdge, Syscfg,
+};
+use alloc::sync::Arc;
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use drone_core::token::Token;
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::{periph::exti::ExtiPeriph, reg::exti};
+use drone_stm32f4_gpio_drv::{prelude::*, GpioPin};
+
+/// The number of EXTI lines.
+const LINES: usize = 23;
+
+/// The interrupt number of `EXTI9_5`.
+const EXTI9_5: usize = 23;
+
+/// The interrupt number of `EXTI15_10`.
+const EXTI15_10: usize = 40;
+
+/// EXTI dispatcher for the lines sharing one interrupt vector, i.e. lines 5 to 9 on `EXTI9_5`,
+/// and lines 10 to 15 on `EXTI15_10`.
+///
+/// The dispatcher owns the interrupt token and hands out an [`ExtiLine`] for each line.
+/// The pending register is read once per interrupt on behalf of all lines,
+/// and only the streams and futures of the lines that are actually pending are woken.
+pub struct ExtiShared<ExtiInt: IntToken> {
+    exti_int: ExtiInt,
+    dispatch: Arc<ExtiDispatch>,
+    /// The lines on the interrupt vector.
+    lines: RangeInclusive<u32>,
+}
+
+/// The events of the lines of an interrupt vector.
+///
+/// A single fiber on the vector reads PR, clears the pending flags of the dispatched lines,
+/// and counts the events for each line.
+/// The fibers of the lines never read PR themselves, but compare the count with the count they have seen,
+/// so any number of streams and futures can consume the events of a line.
+///
+/// The fibers of a thread run in the reverse order of their registration,
+/// so a new dispatcher fiber is attached after each fiber of a line.
+/// The fibers of the lines then see the events dispatched in the same interrupt, without pending it again.
+/// An earlier dispatcher fiber completes the next time it runs.
+pub(crate) struct ExtiDispatch {
+    /// The lines dispatched by the fiber.
+    lines: AtomicU32,
+    /// The number of events dispatched for each line.
+    events: [AtomicU32; LINES],
+    /// The number of dispatcher fibers attached so far.
+    attaching: AtomicU32,
+    /// The generation of the newest attached dispatcher fiber.
+    attached: AtomicU32,
+}
+
+impl<ExtiInt: IntToken> ExtiShared<ExtiInt> {
+    /// Sets up a new [`ExtiShared`] for the shared interrupt `exti_int`,
+    /// which must be either `EXTI9_5` or `EXTI15_10`.
+    pub fn new(exti_int: ExtiInt) -> Self {
+        let lines = shared_lines(ExtiInt::INT_NUM).expect("Not a shared EXTI interrupt");
+        Self {
+            exti_int,
+            dispatch: ExtiDispatch::new(exti_int),
+            lines,
+        }
+    }
+
+    /// Sets up a new [`ExtiLine`] for `pin` on the shared interrupt.
+    /// Syscfg is required as its clock must be enabled prior to initialization.
+    pub fn line<
+        Exti: ExtiMap,
+        Pin: ExtiPinHead,
+        PinMode: PinModeMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    >(
+        &self,
+        exti: ExtiPeriph<Exti>,
+        pin: GpioPin<Pin, PinMode, PinType, PinPull>,
+        _syscfg: &Syscfg,
+    ) -> ExtiLine<Exti, ExtiInt, Pin, PinMode, PinType, PinPull, NoEdge>
+    where
+        ExtiDrv<Exti, ExtiInt, Pin::Head, NoEdge>: ExtiDrvLine<Exti, ExtiInt, Pin, PinMode, NoEdge>,
+    {
+        assert!(
+            self.lines.contains(&Exti::LINE),
+            "The line is not on the shared interrupt vector"
+        );
+        ExtiDrv::<Exti, ExtiInt, Pin::Head, NoEdge>::with_dispatch(
+            exti,
+            self.exti_int,
+            self.dispatch.clone(),
+        )
+        .line(pin)
+    }
+}
+
+impl ExtiDispatch {
+    /// Sets up a new dispatcher on `exti_int`.
+    pub(crate) fn new<ExtiInt: IntToken>(exti_int: ExtiInt) -> Arc<Self> {
+        let dispatch = Arc::new(Self {
+            lines: AtomicU32::new(0),
+            events: Default::default(),
+            attaching: AtomicU32::new(0),
+            attached: AtomicU32::new(0),
+        });
+        dispatch.attach(exti_int);
+        dispatch
+    }
+
+    /// Attach a new dispatcher fiber, which runs before the fibers that are already attached to `exti_int`.
+    pub(crate) fn attach<ExtiInt: IntToken>(self: &Arc<Self>, exti_int: ExtiInt) {
+        let generation = self.attaching.fetch_add(1, Ordering::AcqRel) + 1;
+        let weak = Arc::downgrade(self);
+        // PR is write-1-to-clear, and the fiber only writes the bits of the dispatched lines.
+        let exti_pr = unsafe { exti::Pr::<Crt>::take() };
+        // The fiber only reads IMR, so that the pending flags of masked lines are left for when they are unmasked.
+        let exti_imr = unsafe { exti::Imr::<Crt>::take() };
+        exti_int.add_fn(move || {
+            let dispatch = match weak.upgrade() {
+                Some(dispatch) => dispatch,
+                None => return fib::Complete(()),
+            };
+            // The earlier fibers keep dispatching until the new fiber is attached,
+            // so that the pending flags are always cleared.
+            if dispatch.attached.load(Ordering::Acquire) > generation {
+                return fib::Complete(());
+            }
+            // Read PR once on behalf of all lines.
+            let pending =
+                exti_pr.load_bits() & exti_imr.load_bits() & dispatch.lines.load(Ordering::Acquire);
+            if pending != 0 {
+                exti_pr.store_bits(pending); // clear the pending flags
+                for (line, events) in dispatch.events.iter().enumerate() {
+                    if pending & 1 << line != 0 {
+                        events.fetch_add(1, Ordering::AcqRel);
+                    }
+                }
+            }
+            fib::Yielded(())
+        });
+        self.attached.fetch_max(generation, Ordering::AcqRel);
+    }
+
+    /// Start dispatching `line`.
+    pub(crate) fn add_line(&self, line: u32) {
+        self.lines.fetch_or(1 << line, Ordering::AcqRel);
+    }
+
+    /// Stop dispatching `line`.
+    pub(crate) fn remove_line(&self, line: u32) {
+        self.lines.fetch_and(!(1 << line), Ordering::AcqRel);
+    }
+
+    /// The number of events dispatched for `line`.
+    pub(crate) fn events(&self, line: u32) -> u32 {
+        self.events[line as usize].load(Ordering::Acquire)
+    }
+}
+
+/// The lines on the shared interrupt with the interrupt number `int_num`.
+fn shared_lines(int_num: usize) -> Option<RangeInclusive<u32>> {
+    match int_num {
+        EXTI9_5 => Some(5..=9),
+        EXTI15_10 => Some(10..=15),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_of_shared_interrupts() {
+        assert_eq!(shared_lines(EXTI9_5), Some(5..=9));
+        assert_eq!(shared_lines(EXTI15_10), Some(10..=15));
+        // EXTI4.
+        assert_eq!(shared_lines(10), None);
+    }
+}
@@ -12,6 +12,9 @@ pub mod dwt;
 #[cfg(feature = "dma")]
 pub extern crate drone_stm32f4_dma_drv as dma;
 
+#[cfg(feature = "dma2d")]
+pub extern crate drone_stm32f4_dma2d_drv as dma2d;
+
 #[cfg(feature = "exti")]
 pub extern crate drone_stm32f4_exti_drv as exti;
 
@@ -96,7 +96,10 @@ pub trait Freq {
 }
 
 /// A peripheral clock token.
-pub trait PClkToken: Freq {}
+pub trait PClkToken: Freq {
+    /// The prescaler from the ahb clock, i.e. hclk = pclk * ppre.
+    fn ppre(&self) -> u32;
+}
 
 // Clock source selector
 pub struct Mux<Signal> {
@@ -196,6 +199,10 @@ impl PllSrc {
         let vco = PllVco::new(self, pll_n);
         Pll::new(vco, pll_p, pll_q)
     }
+
+    pub const fn to_plli2s(self, plli2s_n: u32, plli2s_r: u32) -> PllI2s {
+        PllI2s::new(self, plli2s_n, plli2s_r)
+    }
 }
 
 impl Freq for PllSrc {
@@ -291,6 +298,39 @@ impl Pll {
     }
 }
 
+/// The i2s pll, sharing the input clock (vcoin) with the main pll.
+/// plli2sclk = vcoin * n / r
+#[derive(Copy, Clone)]
+pub struct PllI2s {
+    /// The pll input clock signal (vcoin).
+    pub src: PllSrc,
+    /// PllI2s multiplication factor for vco.
+    pub n: u32,
+    /// PllI2s division factor for the i2s clock.
+    pub r: u32,
+}
+
+impl PllI2s {
+    #[must_use]
+    const fn new(src: PllSrc, n: u32, r: u32) -> PllI2s {
+        assert!(n >= 50 && n <= 432);
+        assert!(r >= 2 && r <= 7);
+        let plli2s = PllI2s { src, n, r };
+        assert!(plli2s.f() <= 192_000_000);
+        plli2s
+    }
+
+    pub const fn f(&self) -> u32 {
+        self.src.f() * self.n / self.r
+    }
+}
+
+impl Freq for PllI2s {
+    fn freq(&self) -> u32 {
+        self.f()
+    }
+}
+
 /// The system clock source mux.
 
 /// The system clock source signal.
@@ -419,7 +459,11 @@ impl PClk1 {
     }
 }
 
-impl PClkToken for PClk1 {}
+impl PClkToken for PClk1 {
+    fn ppre(&self) -> u32 {
+        self.ppre1
+    }
+}
 
 impl Freq for PClk1 {
     fn freq(&self) -> u32 {
@@ -436,7 +480,11 @@ pub struct PClk2 {
     pub ppre2: u32,
 }
 
-impl PClkToken for PClk2 {}
+impl PClkToken for PClk2 {
+    fn ppre(&self) -> u32 {
+        self.ppre2
+    }
+}
 
 impl PClk2 {
     #[must_use]
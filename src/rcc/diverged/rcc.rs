@@ -8,6 +8,7 @@ pub(crate) struct RccDiverged {
     pub(crate) rcc_pllcfgr: reg::rcc::Pllcfgr<Srt>,
     pub(crate) rcc_cfgr: reg::rcc::Cfgr<Srt>,
     pub(crate) rcc_cir: reg::rcc::Cir<Crt>,
+    pub(crate) rcc_plli2scfgr: reg::rcc::Plli2Scfgr<Srt>,
 }
 
 impl From<RccPeriph> for RccDiverged {
@@ -17,12 +18,14 @@ impl From<RccPeriph> for RccDiverged {
             rcc_pllcfgr,
             rcc_cfgr,
             rcc_cir,
+            rcc_plli2scfgr,
         } = periph;
         Self {
             rcc_cr,
             rcc_pllcfgr,
             rcc_cfgr,
             rcc_cir: rcc_cir.into_copy(),
+            rcc_plli2scfgr,
         }
     }
 }
@@ -15,6 +15,7 @@ periph::singular! {
         PLLCFGR;
         CFGR;
         CIR;
+        PLLI2SCFGR;
     }
 }
 
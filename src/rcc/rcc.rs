@@ -124,6 +124,53 @@ impl<RccInt: IntToken, SrcClk> StabilizingClkCtrl<Pll>
     }
 }
 
+impl<RccInt: IntToken, SrcClk> StabilizingClkCtrl<PllI2s>
+    for ConfiguredClkBuilder<'_, RccInt, SrcClk>
+{
+    fn stabilize(&self, clk: PllI2s) -> FiberFuture<ConfiguredClk<PllI2s>> {
+        let rcc = self.rcc;
+        assert!(rcc.rcc_int.is_int_enabled());
+
+        // Enable ready interrupt.
+        rcc.rcc.rcc_cir.modify(|r| r.set_plli2srdyie());
+
+        let reg::rcc::Cir {
+            plli2srdyc,
+            plli2srdyf,
+            ..
+        } = rcc.rcc.rcc_cir;
+
+        // Attach a listener that will notify us when the clock has stabilized.
+        let plli2srdy = rcc.rcc_int.add_future(fib::new_fn(move || {
+            if plli2srdyf.read_bit() {
+                plli2srdyc.set_bit();
+                fib::Complete(ConfiguredClk { clk })
+            } else {
+                fib::Yielded(())
+            }
+        }));
+
+        // Configure the clock.
+        // The input clock division factor (PLLM) is shared with the main pll,
+        // and must not be changed while the main pll is enabled.
+        if rcc.rcc.rcc_cr.load().pllon() {
+            assert_eq!(rcc.rcc.rcc_pllcfgr.load().pllm(), clk.src.m);
+        } else {
+            rcc.rcc.rcc_pllcfgr.modify(|r| r.write_pllm(clk.src.m));
+        }
+        rcc.rcc.rcc_plli2scfgr.modify(|r| r.write_plli2sn(clk.n).write_plli2sr(clk.r));
+
+        // Use the i2s pll as the i2s clock source.
+        rcc.rcc.rcc_cfgr.modify(|r| r.clear_i2ssrc());
+
+        // Enable the clock.
+        rcc.rcc.rcc_cr.modify(|r| r.set_plli2son());
+
+        // Wait for the clock to stabilize.
+        plli2srdy
+    }
+}
+
 impl ConfiguredClk<Pll> {
     pub fn p(self) -> ConfiguredClk<PllClk<PllP>> {
         ConfiguredClk { clk: self.clk.p }
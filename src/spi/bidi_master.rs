@@ -0,0 +1,120 @@
+use crate::{diverged::SpiDiverged, DataFrame, FirstBit, Prescaler, SpiMode, SpiWord};
+use core::hint;
+use drone_cortexm::reg::prelude::*;
+use drone_stm32_map::periph::spi::{traits::*, SpiMap};
+
+/// Spi master driver for a single bidirectional data line (three-wire), where the transfers block until completed.
+/// The data line is driven by the master while writing, and by the slave while reading.
+pub struct SpiBidiMasterDrv<Spi: SpiMap> {
+    pub(crate) spi: SpiDiverged<Spi>,
+    /// The number of cpu cycles per peripheral clock cycle.
+    ppre: u32,
+}
+
+impl<Spi: SpiMap> SpiBidiMasterDrv<Spi> {
+    pub(crate) fn init(spi: SpiDiverged<Spi>, ppre: u32) -> Self {
+        let master = Self { spi, ppre };
+        master.spi.enable_master();
+        master
+    }
+
+    /// Send to the currently selected slave.
+    /// The buffer items must match the configured data frame, i.e. `u8` for 8-bit and `u16` for 16-bit frames.
+    pub fn write<Word: SpiWord>(&mut self, buf: &[Word]) {
+        self.spi.assert_frame::<Word>();
+        for &word in buf {
+            while !self.spi.spi_sr.txe().read_bit() {}
+            self.spi.spi_dr.store_reg(|r, v| r.dr().write(v, word.into_frame()));
+        }
+        self.spi.wait_for_idle();
+    }
+
+    /// Read from the currently selected slave.
+    ///
+    /// The clock runs continuously while the data line is an input,
+    /// so the spi is disabled during the last frame to stop the clock after exactly `buf.len()` frames, see RM0090 §28.3.8.
+    /// Additional frames are clocked if the read is preempted for longer than a frame before the last frame.
+    pub fn read<Word: SpiWord>(&mut self, buf: &mut [Word]) {
+        self.spi.assert_frame::<Word>();
+        let len = buf.len();
+        if len == 0 {
+            return;
+        }
+
+        let sck_cycles = sck_cycles(self.ppre, self.spi.config().0);
+
+        // Discard any stale data.
+        self.spi.spi_dr.load_val();
+        self.spi.spi_sr.load_val();
+
+        // Switch the data line to input, which starts the clock when the spi is enabled.
+        self.spi.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+        self.spi.spi_cr1.modify_reg(|r, v| {
+            r.bidioe().clear(v);
+            r.spe().set(v);
+        });
+
+        for (i, word) in buf.iter_mut().enumerate() {
+            if i == len - 1 {
+                // The last frame has started, wait one clock cycle before disabling the spi.
+                for _ in 0..sck_cycles {
+                    hint::spin_loop();
+                }
+                self.spi.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+            }
+            while !self.spi.spi_sr.rxne().read_bit() {}
+            *word = Word::from_frame(self.spi.spi_dr.dr().read_bits());
+        }
+
+        // Switch the data line back to output, where the clock only runs while sending.
+        self.spi.spi_cr1.modify_reg(|r, v| {
+            r.bidioe().set(v);
+            r.spe().set(v);
+        });
+    }
+
+    /// Change the spi mode, e.g. before selecting a chip that requires another mode than the previous one.
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        self.spi.set_mode(mode);
+    }
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    pub fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        self.spi.configure(prescaler, mode, first_bit);
+    }
+
+    /// The current spi mode.
+    pub fn mode(&self) -> SpiMode {
+        self.spi.mode()
+    }
+
+    /// The configured data frame.
+    pub fn data_frame(&self) -> DataFrame {
+        self.spi.data_frame()
+    }
+}
+
+impl<Spi: SpiMap> Drop for SpiBidiMasterDrv<Spi> {
+    fn drop(&mut self) {
+        self.spi.disable();
+    }
+}
+
+/// The number of cpu cycles per spi clock cycle.
+fn sck_cycles(ppre: u32, prescaler: Prescaler) -> u32 {
+    ppre * prescaler.div()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sck_cycles_per_prescaler() {
+        // Apb2 at the cpu clock.
+        assert_eq!(sck_cycles(1, Prescaler::Div2), 2);
+        assert_eq!(sck_cycles(1, Prescaler::Div256), 256);
+        // Apb1 at a quarter of the cpu clock.
+        assert_eq!(sck_cycles(4, Prescaler::Div8), 32);
+    }
+}
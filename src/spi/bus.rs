@@ -0,0 +1,204 @@
+use crate::{
+    chipctrl::{SelectGuard, SpiChip},
+    master::{SpiError, SpiMasterDrv},
+    FirstBit, Prescaler, SpiMode, SpiWord,
+};
+use alloc::sync::Arc;
+use core::ops::{Deref, DerefMut};
+use drone_core::sync::{Mutex, MutexGuard};
+use drone_cortexm::thr::prelude::*;
+use drone_stm32_map::periph::{dma::ch::DmaChMap, gpio::pin::GpioPinMap, spi::SpiMap};
+use drone_stm32f4_gpio_drv::prelude::*;
+
+/// The spi configuration of a device on a shared bus.
+#[derive(Copy, Clone, PartialEq)]
+pub struct SpiDeviceConfig {
+    /// The baud rate prescaler.
+    pub prescaler: Prescaler,
+    /// The clock polarity and phase.
+    pub mode: SpiMode,
+    /// The bit transmission order.
+    pub first_bit: FirstBit,
+}
+
+/// A spi bus shared between multiple devices, possibly used from different tasks.
+pub struct SpiBus<
+    Spi: SpiMap,
+    DmaRx: DmaChMap,
+    DmaRxInt: IntToken,
+    DmaTx: DmaChMap,
+    DmaTxInt: IntToken,
+> {
+    master: Mutex<SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>>,
+}
+
+/// A device on a shared spi bus with its own chip select and configuration.
+pub struct SpiDevice<
+    Spi: SpiMap,
+    DmaRx: DmaChMap,
+    DmaRxInt: IntToken,
+    DmaTx: DmaChMap,
+    DmaTxInt: IntToken,
+    Pin: GpioPinMap,
+    PinType: PinTypeMap,
+    PinPull: PinPullMap,
+> {
+    bus: Arc<SpiBus<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>>,
+    chip: SpiChip<Pin, PinType, PinPull>,
+    config: SpiDeviceConfig,
+}
+
+/// A transaction on a shared spi bus, i.e. exclusive access to the bus with the device selected.
+/// The device is deselected and the bus is released when dropped.
+pub struct SpiDeviceGuard<
+    'a,
+    Spi: SpiMap,
+    DmaRx: DmaChMap,
+    DmaRxInt: IntToken,
+    DmaTx: DmaChMap,
+    DmaTxInt: IntToken,
+    Pin: GpioPinMap,
+    PinType: PinTypeMap,
+    PinPull: PinPullMap,
+> {
+    // The chip is deselected before the bus is released.
+    selection: SelectGuard<'a, Pin, PinType, PinPull>,
+    master: MutexGuard<'a, SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>>,
+}
+
+impl SpiDeviceConfig {
+    /// Create a configuration for spi mode 0 with msb first.
+    pub const fn new(prescaler: Prescaler) -> Self {
+        Self {
+            prescaler,
+            mode: SpiMode::Mode0,
+            first_bit: FirstBit::Msb,
+        }
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+    > SpiBus<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>
+{
+    /// Share the spi master between multiple devices.
+    pub fn new(master: SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>) -> Arc<Self> {
+        Arc::new(Self {
+            master: Mutex::new(master),
+        })
+    }
+
+    /// Add a device with the chip select `chip` and its own spi configuration.
+    pub fn device<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap>(
+        self: &Arc<Self>,
+        chip: SpiChip<Pin, PinType, PinPull>,
+        config: SpiDeviceConfig,
+    ) -> SpiDevice<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull> {
+        SpiDevice {
+            bus: self.clone(),
+            chip,
+            config,
+        }
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        Pin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > SpiDevice<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull>
+{
+    /// Start a transaction, i.e. wait for exclusive access to the bus,
+    /// apply the device configuration, and select the device.
+    /// The returned guard provides the `write()`, `read()`, and `xfer()` methods of the spi master.
+    pub async fn transaction(
+        &mut self,
+    ) -> SpiDeviceGuard<'_, Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull> {
+        let mut master = self.bus.master.lock().await;
+        // Waits for any ongoing frame of the previous device before reconfiguring.
+        master.configure(self.config.prescaler, self.config.mode, self.config.first_bit);
+        SpiDeviceGuard {
+            selection: SelectGuard::new(&mut self.chip),
+            master,
+        }
+    }
+
+    /// Send to the device in a single transaction.
+    pub async fn write<Word: SpiWord>(&mut self, buf: &[Word]) -> Result<(), SpiError> {
+        self.transaction().await.write(buf).await
+    }
+
+    /// Read from the device in a single transaction.
+    pub async fn read<Word: SpiWord>(&mut self, buf: &mut [Word]) -> Result<(), SpiError> {
+        self.transaction().await.read(buf).await
+    }
+
+    /// Send to and receive from the device in a single transaction.
+    pub async fn xfer<Word: SpiWord>(
+        &mut self,
+        tx_buf: &[Word],
+        rx_buf: &mut [Word],
+    ) -> Result<(), SpiError> {
+        self.transaction().await.xfer(tx_buf, rx_buf).await
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        Pin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > Deref for SpiDeviceGuard<'_, Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull>
+{
+    type Target = SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.master
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        Pin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > DerefMut for SpiDeviceGuard<'_, Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.master
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        Pin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > Drop for SpiDeviceGuard<'_, Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, Pin, PinType, PinPull>
+{
+    fn drop(&mut self) {
+        // Wait for the last frame before the device is deselected.
+        self.master.wait_for_idle();
+    }
+}
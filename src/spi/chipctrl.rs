@@ -1,10 +1,16 @@
-use crate::master::SpiMasterDrv;
+use crate::{
+    bidi_master::SpiBidiMasterDrv, int_master::SpiIntMasterDrv, master::SpiMasterDrv,
+    polled_master::SpiPolledMasterDrv, DataFrame, FirstBit, Prescaler, SpiError, SpiMode,
+};
+use alloc::boxed::Box;
+use core::{future::Future, pin::Pin};
 use drone_cortexm::thr::prelude::*;
 use drone_stm32_map::periph::{dma::ch::DmaChMap, gpio::pin::GpioPinMap, spi::SpiMap};
 use drone_stm32f4_gpio_drv::{GpioPin, prelude::*};
 
 pub struct SpiChip<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> {
     cs: GpioPin<Pin, OutputMode, PinType, PinPull>,
+    mode: Option<SpiMode>,
 }
 
 impl<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> SpiChip<Pin, PinType, PinPull> {
@@ -19,12 +25,18 @@ impl<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> SpiChip<Pin, Pin
     pub fn deselect(&mut self) {
         self.cs.set();
     }
+
+    /// Use a specific spi mode when the chip is selected.
+    pub fn with_mode(mut self, mode: SpiMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
 }
 
 impl<Pin: GpioPinMap, PinType: PinTypeMap> SpiChip<Pin, PinType, PullUp> {
     /// Initialize a new `SpiChip` as deselected.
     pub fn new_deselected(cs: GpioPin<Pin, OutputMode, PinType, PullUp>) -> Self {
-        let mut chip = Self { cs };
+        let mut chip = Self { cs, mode: None };
         chip.deselect();
         chip
     }
@@ -34,6 +46,14 @@ pub struct SelectGuard<'a, Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPul
     chip: &'a mut SpiChip<Pin, PinType, PinPull>,
 }
 
+impl<'a, Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> SelectGuard<'a, Pin, PinType, PinPull> {
+    /// Select the chip and return a guard that deselects the chip when dropped.
+    pub(crate) fn new(chip: &'a mut SpiChip<Pin, PinType, PinPull>) -> Self {
+        chip.select();
+        Self { chip }
+    }
+}
+
 impl<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> Drop
     for SelectGuard<'_, Pin, PinType, PinPull>
 {
@@ -44,14 +64,20 @@ impl<Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap> Drop
 }
 
 pub trait ChipCtrl {
+    /// Change the spi mode.
+    fn set_mode(&mut self, mode: SpiMode);
+
     /// Select a specific chip and return a guard that deselects the chip when dropped.
+    /// The spi mode is changed first if the chip requires a specific mode.
     #[inline]
     fn select<'guard, Pin: GpioPinMap, PinType: PinTypeMap, PinPull: PinPullMap>(
         &mut self,
         chip: &'guard mut SpiChip<Pin, PinType, PinPull>,
     ) -> SelectGuard<'guard, Pin, PinType, PinPull> {
-        chip.select();
-        SelectGuard { chip }
+        if let Some(mode) = chip.mode {
+            self.set_mode(mode);
+        }
+        SelectGuard::new(chip)
     }
 }
 
@@ -63,4 +89,158 @@ impl<
         DmaTxInt: IntToken,
     > ChipCtrl for SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>
 {
+    #[inline]
+    fn set_mode(&mut self, mode: SpiMode) {
+        SpiMasterDrv::set_mode(self, mode);
+    }
+}
+
+impl<Spi: SpiMap, SpiInt: IntToken> ChipCtrl for SpiIntMasterDrv<Spi, SpiInt> {
+    #[inline]
+    fn set_mode(&mut self, mode: SpiMode) {
+        SpiIntMasterDrv::set_mode(self, mode);
+    }
+}
+
+impl<Spi: SpiMap> ChipCtrl for SpiPolledMasterDrv<Spi> {
+    #[inline]
+    fn set_mode(&mut self, mode: SpiMode) {
+        SpiPolledMasterDrv::set_mode(self, mode);
+    }
+}
+
+impl<Spi: SpiMap> ChipCtrl for SpiBidiMasterDrv<Spi> {
+    #[inline]
+    fn set_mode(&mut self, mode: SpiMode) {
+        SpiBidiMasterDrv::set_mode(self, mode);
+    }
+}
+
+/// A spi link to a single device, i.e. a dedicated spi master and the chip of the device.
+/// The device drivers, e.g. [`crate::nor::NorFlash`], operate on a link, so that they can be tested against a simulated device.
+pub trait SpiLink {
+    /// Select the device.
+    fn select(&mut self);
+
+    /// Deselect the device.
+    fn deselect(&mut self);
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit);
+
+    /// Send `buf`, and discard the received bytes.
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>>;
+
+    /// Receive into `buf` while sending zeros.
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>>;
+
+    /// Send `tx_buf` and receive into `rx_buf`.
+    fn xfer<'a>(
+        &'a mut self,
+        tx_buf: &'a [u8],
+        rx_buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>>;
+}
+
+/// A [`SpiLink`] on a dma spi master with 8-bit data frames.
+pub struct SpiMasterLink<
+    Spi: SpiMap,
+    DmaRx: DmaChMap,
+    DmaRxInt: IntToken,
+    DmaTx: DmaChMap,
+    DmaTxInt: IntToken,
+    CsPin: GpioPinMap,
+    PinType: PinTypeMap,
+    PinPull: PinPullMap,
+> {
+    spi: SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+    chip: SpiChip<CsPin, PinType, PinPull>,
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        CsPin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > SpiMasterLink<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, CsPin, PinType, PinPull>
+{
+    /// Create a link to the device selected by `chip`, where `spi` must be a dedicated master for the device.
+    pub fn new(spi: SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>, chip: SpiChip<CsPin, PinType, PinPull>) -> Self {
+        assert!(
+            spi.data_frame() == DataFrame::Bits8,
+            "The spi link requires 8-bit data frames"
+        );
+        Self { spi, chip }
+    }
+
+    /// Release the spi master and the chip.
+    pub fn free(
+        self,
+    ) -> (
+        SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+        SpiChip<CsPin, PinType, PinPull>,
+    ) {
+        (self.spi, self.chip)
+    }
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        CsPin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > SpiLink for SpiMasterLink<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, CsPin, PinType, PinPull>
+{
+    fn select(&mut self) {
+        if let Some(mode) = self.chip.mode {
+            self.spi.set_mode(mode);
+        }
+        self.chip.select();
+    }
+
+    fn deselect(&mut self) {
+        self.chip.deselect();
+    }
+
+    fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        self.spi.configure(prescaler, mode, first_bit);
+    }
+
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+        Box::pin(self.spi.write(buf))
+    }
+
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+        Box::pin(self.spi.read(buf))
+    }
+
+    fn xfer<'a>(
+        &'a mut self,
+        tx_buf: &'a [u8],
+        rx_buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+        Box::pin(self.spi.xfer(tx_buf, rx_buf))
+    }
+}
+
+/// Run a future to completion, for use with simulated devices that are always ready.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use core::task::{Context, Poll};
+    futures::pin_mut!(future);
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
 }
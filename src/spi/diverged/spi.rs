@@ -1,5 +1,6 @@
+use crate::{ClkPhase, ClkPol, DataFrame, FirstBit, Prescaler, SpiMode, SpiWord};
 use drone_cortexm::reg::prelude::*;
-use drone_stm32_map::periph::spi::{SpiMap, SpiPeriph};
+use drone_stm32_map::periph::spi::{traits::*, SpiMap, SpiPeriph};
 
 #[allow(dead_code)]
 pub(crate) struct SpiDiverged<Spi: SpiMap> {
@@ -7,9 +8,9 @@ pub(crate) struct SpiDiverged<Spi: SpiMap> {
     pub(crate) rcc_busrstr_spirst: Spi::SRccBusrstrSpirst,
     pub(crate) rcc_bussmenr_spismen: Spi::SRccBussmenrSpismen,
     pub(crate) spi_sr: Spi::CSpiSr,
-    pub(crate) spi_dr: Spi::SSpiDr,
-    pub(crate) spi_cr1: Spi::SSpiCr1,
-    pub(crate) spi_cr2: Spi::SSpiCr2,
+    pub(crate) spi_dr: Spi::CSpiDr,
+    pub(crate) spi_cr1: Spi::CSpiCr1,
+    pub(crate) spi_cr2: Spi::CSpiCr2,
     pub(crate) spi_crcpr: Spi::SSpiCrcpr,
     pub(crate) spi_rxcrcr: Spi::SSpiRxcrcr,
     pub(crate) spi_txcrcr: Spi::SSpiTxcrcr,
@@ -38,9 +39,9 @@ impl<Spi: SpiMap> From<SpiPeriph<Spi>> for SpiDiverged<Spi> {
             rcc_busrstr_spirst,
             rcc_bussmenr_spismen,
             spi_sr: spi_sr.into_copy(),
-            spi_dr,
-            spi_cr1,
-            spi_cr2,
+            spi_dr: spi_dr.into_copy(),
+            spi_cr1: spi_cr1.into_copy(),
+            spi_cr2: spi_cr2.into_copy(),
             spi_crcpr,
             spi_rxcrcr,
             spi_txcrcr,
@@ -49,3 +50,167 @@ impl<Spi: SpiMap> From<SpiPeriph<Spi>> for SpiDiverged<Spi> {
         }
     }
 }
+
+impl<Spi: SpiMap> SpiDiverged<Spi> {
+    /// Enable the spi as master with software slave management.
+    pub(crate) fn enable_master(&self) {
+        self.spi_cr1.modify_reg(|r, v| {
+            // Master configuration.
+            r.mstr().set(v);
+
+            // Use software slave management, i.e. the app controls slave selection.
+            // The hardware NSS pin is free for other use.
+            r.ssm().set(v);
+
+            // Internal slave select (required for master operation when software slave management (SSM) is being used).
+            r.ssi().set(v);
+
+            // Enable spi after being fully configured.
+            r.spe().set(v);
+        });
+    }
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    pub(crate) fn configure(&self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        if self.config() == (prescaler, mode, first_bit) {
+            return;
+        }
+
+        self.wait_for_idle();
+
+        // The configuration must not be changed while the spi is enabled.
+        self.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+        self.spi_cr1.modify_reg(|r, v| {
+            r.br().write(v, prescaler.bits());
+            if mode.clk_pol() == ClkPol::High {
+                r.cpol().set(v);
+            } else {
+                r.cpol().clear(v);
+            }
+            if mode.clk_phase() == ClkPhase::Second {
+                r.cpha().set(v);
+            } else {
+                r.cpha().clear(v);
+            }
+            if first_bit == FirstBit::Lsb {
+                r.lsbfirst().set(v);
+            } else {
+                r.lsbfirst().clear(v);
+            }
+            r.spe().set(v);
+        });
+    }
+
+    /// Change the spi mode, and keep the baud rate prescaler and the bit transmission order.
+    pub(crate) fn set_mode(&self, mode: SpiMode) {
+        let (prescaler, _, first_bit) = self.config();
+        self.configure(prescaler, mode, first_bit);
+    }
+
+    /// The current spi mode.
+    pub(crate) fn mode(&self) -> SpiMode {
+        self.config().1
+    }
+
+    /// The current baud rate prescaler, spi mode, and bit transmission order.
+    pub(crate) fn config(&self) -> (Prescaler, SpiMode, FirstBit) {
+        let cr1 = self.spi_cr1.load_val();
+        let prescaler = Prescaler::from_bits(self.spi_cr1.br().read(&cr1));
+        let first_bit = if self.spi_cr1.lsbfirst().read(&cr1) {
+            FirstBit::Lsb
+        } else {
+            FirstBit::Msb
+        };
+        let clk_pol = if self.spi_cr1.cpol().read(&cr1) {
+            ClkPol::High
+        } else {
+            ClkPol::Low
+        };
+        let clk_phase = if self.spi_cr1.cpha().read(&cr1) {
+            ClkPhase::Second
+        } else {
+            ClkPhase::First
+        };
+        (prescaler, SpiMode::new(clk_pol, clk_phase), first_bit)
+    }
+
+    /// Whether the hardware crc calculation is enabled.
+    pub(crate) fn crc_enabled(&self) -> bool {
+        self.spi_cr1.crcen().read_bit()
+    }
+
+    /// Clear the crc registers before a transfer.
+    pub(crate) fn reset_crc(&self) {
+        // The crc registers are cleared when the crc calculation is enabled,
+        // which must only be done while the spi is disabled, see RM0090 §28.5.1.
+        self.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+        self.spi_cr1.modify_reg(|r, v| r.crcen().clear(v));
+        self.spi_cr1.modify_reg(|r, v| {
+            r.crcen().set(v);
+            r.spe().set(v);
+        });
+    }
+
+    /// The configured data frame.
+    pub(crate) fn data_frame(&self) -> DataFrame {
+        if self.spi_cr1.dff().read_bit() {
+            DataFrame::Bits16
+        } else {
+            DataFrame::Bits8
+        }
+    }
+
+    /// Assert that the buffer items match the configured data frame.
+    pub(crate) fn assert_frame<Word: SpiWord>(&self) {
+        assert!(
+            Word::FRAME == self.data_frame(),
+            "The buffer items do not match the data frame"
+        );
+    }
+
+    /// Wait for the last frame to be transferred.
+    pub(crate) fn wait_for_idle(&self) {
+        loop {
+            let sr_val = self.spi_sr.load_val();
+            if self.spi_sr.txe().read(&sr_val) && !self.spi_sr.bsy().read(&sr_val) {
+                break;
+            }
+        }
+    }
+
+    /// Send a single frame and wait for the frame received at the same time.
+    /// Only a single frame is in transfer at any time, so the receive buffer cannot overrun.
+    pub(crate) fn transfer_frame(&self, frame: u32) -> u32 {
+        while !self.spi_sr.txe().read_bit() {}
+        self.spi_dr.store_reg(|r, v| r.dr().write(v, frame));
+        while !self.spi_sr.rxne().read_bit() {}
+        self.spi_dr.dr().read_bits()
+    }
+
+    /// Send `buf` by polling, and discard the received frames.
+    pub(crate) fn write_polled<Word: SpiWord>(&self, buf: &[Word]) {
+        for &word in buf {
+            self.transfer_frame(word.into_frame());
+        }
+    }
+
+    /// Receive into `buf` by polling, while sending zeros.
+    pub(crate) fn read_polled<Word: SpiWord>(&self, buf: &mut [Word]) {
+        for word in buf {
+            *word = Word::from_frame(self.transfer_frame(0));
+        }
+    }
+
+    /// Send `tx_buf` and receive into `rx_buf` by polling.
+    pub(crate) fn xfer_polled<Word: SpiWord>(&self, tx_buf: &[Word], rx_buf: &mut [Word]) {
+        for (&tx, rx) in tx_buf.iter().zip(rx_buf) {
+            *rx = Word::from_frame(self.transfer_frame(tx.into_frame()));
+        }
+    }
+
+    /// Disable spi after the last frame is transferred.
+    pub(crate) fn disable(&self) {
+        self.wait_for_idle();
+        self.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+    }
+}
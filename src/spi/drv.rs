@@ -1,16 +1,23 @@
-use crate::{setup::*, diverged::SpiDiverged, master::SpiMasterDrv, SpiMap};
+use crate::{
+    setup::*, bidi_master::SpiBidiMasterDrv, diverged::SpiDiverged, int_master::SpiIntMasterDrv, master::SpiMasterDrv, pins::NssPin,
+    master::SpiErrors, polled_master::SpiPolledMasterDrv, slave::SpiSlaveDrv, SpiMap,
+};
+use alloc::sync::Arc;
 use core::marker::PhantomData;
 use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::{
     dma::ch::DmaChMap,
     spi::traits::*,
 };
-use drone_stm32f4_dma_drv::{DmaChCfg, DmaStChToken};
+use drone_stm32f4_dma_drv::{DmaChCfg, DmaRequest, DmaRequestToken, DmaStChToken};
 use drone_stm32f4_rcc_drv::{clktree::*, ConfiguredClk};
 
 pub struct SpiDrv<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> {
     pub(crate) spi: SpiDiverged<Spi>,
     spi_int: SpiInt,
+    errors: Arc<SpiErrors>,
+    /// The number of cpu cycles per peripheral clock cycle.
+    ppre: u32,
     clk: PhantomData<Clk>,
 }
 
@@ -19,22 +26,81 @@ impl<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> SpiDrv<Spi, SpiInt, Clk> {
         let mut drv = Self {
             spi: setup.spi.into(),
             spi_int: setup.spi_int,
+            errors: Arc::new(SpiErrors::default()),
+            ppre: setup.clk.ppre(),
             clk: PhantomData,
         };
-        drv.init_spi(&setup.clk, setup.baud_rate, setup.clk_pol, setup.first_bit);
+        drv.init_spi(
+            &setup.clk,
+            setup.baud_rate,
+            SpiMode::new(setup.clk_pol, setup.clk_phase),
+            setup.first_bit,
+            setup.data_frame,
+            setup.frame_format,
+            setup.crc_polynomial,
+            setup.data_lines,
+        );
         drv
     }
 
+    /// Use the spi as master without dma, where the frames are transferred from the spi interrupt.
+    pub fn into_int_master(self) -> SpiIntMasterDrv<Spi, SpiInt> {
+        self.assert_full_duplex();
+        self.assert_no_crc();
+        SpiIntMasterDrv::init(self.spi, self.spi_int, self.errors)
+    }
+
+    /// Use the spi as master without dma, where the transfers block until completed.
+    pub fn into_polled_master(self) -> SpiPolledMasterDrv<Spi> {
+        self.assert_full_duplex();
+        self.assert_no_crc();
+        SpiPolledMasterDrv::init(self.spi)
+    }
+
+    /// Use the spi as master on a single bidirectional data line (three-wire),
+    /// where the transfers block until completed.
+    pub fn into_bidi_master(self) -> SpiBidiMasterDrv<Spi> {
+        assert!(
+            self.spi.spi_cr1.bidimode().read_bit(),
+            "The spi is not setup for a bidirectional data line"
+        );
+        self.assert_no_crc();
+        SpiBidiMasterDrv::init(self.spi, self.ppre)
+    }
+
+    fn assert_full_duplex(&self) {
+        assert!(
+            !self.spi.spi_cr1.bidimode().read_bit(),
+            "The spi is setup for a bidirectional data line"
+        );
+    }
+
+    fn assert_no_crc(&self) {
+        assert!(
+            !self.spi.crc_enabled(),
+            "The crc calculation is only supported by the dma master"
+        );
+    }
+
     fn init_spi(
         &mut self,
         clk: &ConfiguredClk<Clk>,
         baud_rate: BaudRate,
-        clk_pol: ClkPol,
+        mode: SpiMode,
         first_bit: FirstBit,
+        data_frame: DataFrame,
+        frame_format: FrameFormat,
+        crc_polynomial: Option<u16>,
+        data_lines: DataLines,
     ) {
         // Enable spi clock.
         self.spi.rcc_busenr_spien.set_bit();
 
+        if let Some(crc_polynomial) = crc_polynomial {
+            // Crc polynomial.
+            self.spi.spi_crcpr.store_reg(|r, v| r.crcpoly().write(v, crc_polynomial.into()));
+        }
+
         // Configure spi.
         self.spi.spi_cr1.store_reg(|r, v| {
             if first_bit == FirstBit::Lsb {
@@ -45,28 +111,53 @@ impl<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> SpiDrv<Spi, SpiInt, Clk> {
             r.br().write(v, spi_br(clk, baud_rate));
 
             // Clock polarity.
-            if clk_pol == ClkPol::High {
+            if mode.clk_pol() == ClkPol::High {
                 r.cpol().set(v);
             }
 
             // Clock phase.
-            // TODO: Expose configuration option?
-            r.cpha().clear(v);
+            if mode.clk_phase() == ClkPhase::Second {
+                r.cpha().set(v);
+            }
+
+            // Data frame format.
+            if data_frame == DataFrame::Bits16 {
+                r.dff().set(v);
+            }
+
+            // Hardware crc calculation.
+            if crc_polynomial.is_some() {
+                r.crcen().set(v);
+            }
+
+            // Single bidirectional data line, initially as output so that the clock is not started.
+            if data_lines == DataLines::Bidirectional {
+                r.bidimode().set(v);
+                r.bidioe().set(v);
+            }
 
             // Do not enable spi before it is fully configured.
         });
 
         // Attach spi error handler.
         let sr = self.spi.spi_sr;
+        let dr = self.spi.spi_dr;
+        let cr1 = self.spi.spi_cr1;
+        let errors = Arc::clone(&self.errors);
         self.spi_int.add_fn(move || {
             let val = sr.load_val();
-            handle_spi_err::<Spi>(&val, sr);
+            handle_spi_err::<Spi>(&val, sr, dr, cr1, &errors);
             fib::Yielded::<(), !>(())
         });
 
         // Enable error interrupt.
         self.spi.spi_cr2.store_reg(|r, v| {
             r.errie().set(v);
+
+            // Frame format.
+            if frame_format == FrameFormat::Ti {
+                r.frf().set(v);
+            }
         });
     }
 }
@@ -86,47 +177,71 @@ pub trait IntoMaster<
     ) -> SpiMasterDrv<Spi, DmaRxCh, DmaRxInt, DmaTxCh, DmaTxInt>;
 }
 
-#[macro_export]
-macro_rules! master_drv_init {
-    ($spi:ident, $miso_ch:ident, $miso_stch:ident, $mosi_ch:ident, $mosi_stch:ident) => {
-        impl<
-                SpiInt: drone_cortexm::thr::IntToken,
-                Clk: drone_stm32f4_rcc_drv::clktree::PClkToken,
-            >
-            crate::drv::IntoMaster<
-                $spi,
-                $miso_ch,
-                $miso_stch,
-                $mosi_ch,
-                $mosi_stch,
-            > for crate::drv::SpiDrv<$spi, SpiInt, Clk>
-        {
-            fn into_master<
-                DmaRxInt: drone_cortexm::thr::IntToken,
-                DmaTxInt: drone_cortexm::thr::IntToken,
-            >(
-                self,
-                miso_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    $miso_ch,
-                    $miso_stch,
-                    DmaRxInt,
-                >,
-                mosi_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    $mosi_ch,
-                    $mosi_stch,
-                    DmaTxInt,
-                >,
-            ) -> crate::master::SpiMasterDrv<
-                $spi,
-                $miso_ch,
-                DmaRxInt,
-                $mosi_ch,
-                DmaTxInt,
-            > {
-                crate::master::SpiMasterDrv::init(self.spi, miso_cfg, mosi_cfg)
-            }
-        }
-    };
+pub trait IntoSlave<
+    Spi: SpiMap,
+    DmaRxCh: DmaChMap,
+    DmaRxStCh: DmaStChToken,
+    DmaTxCh: DmaChMap,
+    DmaTxStCh: DmaStChToken,
+>
+{
+    /// Use the spi as slave, selected by the master through the hardware `nss` pin.
+    fn into_slave<DmaRxInt: IntToken, DmaTxInt: IntToken, Nss: NssPin<Spi>>(
+        self,
+        miso_cfg: DmaChCfg<DmaRxCh, DmaRxStCh, DmaRxInt>,
+        mosi_cfg: DmaChCfg<DmaTxCh, DmaTxStCh, DmaTxInt>,
+        nss: &Nss,
+    ) -> SpiSlaveDrv<Spi, DmaRxCh, DmaRxInt, DmaTxCh, DmaTxInt>;
+}
+
+/// The dma requests of a spi peripheral.
+pub trait SpiDmaRequests: SpiMap {
+    /// The receive dma request.
+    type Rx: DmaRequestToken;
+    /// The transmit dma request.
+    type Tx: DmaRequestToken;
+}
+
+impl<
+        Spi: SpiDmaRequests,
+        SpiInt: IntToken,
+        DmaRxCh: DmaRequest<Spi::Rx, DmaRxStCh>,
+        DmaRxStCh: DmaStChToken,
+        DmaTxCh: DmaRequest<Spi::Tx, DmaTxStCh>,
+        DmaTxStCh: DmaStChToken,
+        Clk: PClkToken,
+    > IntoMaster<Spi, DmaRxCh, DmaRxStCh, DmaTxCh, DmaTxStCh> for SpiDrv<Spi, SpiInt, Clk>
+{
+    fn into_master<DmaRxInt: IntToken, DmaTxInt: IntToken>(
+        self,
+        miso_cfg: DmaChCfg<DmaRxCh, DmaRxStCh, DmaRxInt>,
+        mosi_cfg: DmaChCfg<DmaTxCh, DmaTxStCh, DmaTxInt>,
+    ) -> SpiMasterDrv<Spi, DmaRxCh, DmaRxInt, DmaTxCh, DmaTxInt> {
+        self.assert_full_duplex();
+        SpiMasterDrv::init(self.spi, self.errors, miso_cfg, mosi_cfg)
+    }
+}
+
+impl<
+        Spi: SpiDmaRequests,
+        SpiInt: IntToken,
+        DmaRxCh: DmaRequest<Spi::Rx, DmaRxStCh>,
+        DmaRxStCh: DmaStChToken,
+        DmaTxCh: DmaRequest<Spi::Tx, DmaTxStCh>,
+        DmaTxStCh: DmaStChToken,
+        Clk: PClkToken,
+    > IntoSlave<Spi, DmaRxCh, DmaRxStCh, DmaTxCh, DmaTxStCh> for SpiDrv<Spi, SpiInt, Clk>
+{
+    fn into_slave<DmaRxInt: IntToken, DmaTxInt: IntToken, Nss: NssPin<Spi>>(
+        self,
+        miso_cfg: DmaChCfg<DmaRxCh, DmaRxStCh, DmaRxInt>,
+        mosi_cfg: DmaChCfg<DmaTxCh, DmaTxStCh, DmaTxInt>,
+        _nss: &Nss,
+    ) -> SpiSlaveDrv<Spi, DmaRxCh, DmaRxInt, DmaTxCh, DmaTxInt> {
+        self.assert_full_duplex();
+        self.assert_no_crc();
+        SpiSlaveDrv::init(self.spi, miso_cfg, mosi_cfg)
+    }
 }
 
 fn spi_br<Clk: PClkToken>(clk: &ConfiguredClk<Clk>, baud_rate: BaudRate) -> u32 {
@@ -146,30 +261,35 @@ fn spi_br<Clk: PClkToken>(clk: &ConfiguredClk<Clk>, baud_rate: BaudRate) -> u32
         BaudRate::Prescaler(prescaler) => prescaler,
     };
 
-    match presc {
-        Prescaler::Div2 => 0b000,
-        Prescaler::Div4 => 0b001,
-        Prescaler::Div8 => 0b010,
-        Prescaler::Div16 => 0b011,
-        Prescaler::Div32 => 0b100,
-        Prescaler::Div64 => 0b101,
-        Prescaler::Div128 => 0b110,
-        Prescaler::Div256 => 0b111,
-    }
+    presc.bits()
 }
 
-fn handle_spi_err<Spi: SpiMap>(val: &Spi::SpiSrVal, sr: Spi::CSpiSr) {
+fn handle_spi_err<Spi: SpiMap>(
+    val: &Spi::SpiSrVal,
+    sr: Spi::CSpiSr,
+    dr: Spi::CSpiDr,
+    cr1: Spi::CSpiCr1,
+    errors: &SpiErrors,
+) {
     if sr.fre().read(&val) {
         panic!("Frame format error");
     }
     if sr.ovr().read(&val) {
-        panic!("Overrun error");
+        // Reading DR followed by SR clears an overrun (OVR) condition, see RM0090 §28.4.8.
+        dr.load_val();
+        sr.load_val();
+        errors.report_overrun();
     }
     if sr.modf().read(&val) {
-        panic!("Mode fault");
+        // Writing CR1 after reading SR clears a mode fault (MODF) condition.
+        // The hardware has cleared MSTR and SPE, and SPE is set again by the master after the transfer.
+        cr1.modify_reg(|r, v| r.mstr().set(v));
+        errors.report_mode_fault();
     }
     if sr.crcerr().read(&val) {
-        panic!("CRC error");
+        // The crc mismatch is returned as an error from the transfer.
+        sr.crcerr().clear_bit();
+        errors.report_crc();
     }
     if sr.udr().read(&val) {
         panic!("Underrun error");
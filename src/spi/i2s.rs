@@ -0,0 +1,624 @@
+use crate::{diverged::SpiDiverged, pins::*, ClkPol, SpiDmaRequests};
+use alloc::boxed::Box;
+use drone_cortexm::{reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::{
+    dma::ch::DmaChMap,
+    spi::{traits::*, SpiI2Scfgr, SpiI2Spr, SpiPeriph},
+};
+use drone_stm32f4_dma_drv::{
+    config::{DataSize, DmaTransfer},
+    DmaBufFree, DmaBufFull, DmaChCfg, DmaDoubleBufRx, DmaDoubleBufTx, DmaItem, DmaRequest,
+    DmaRequestToken, DmaStChToken, DoubleBufError,
+};
+use drone_stm32f4_rcc_drv::{clktree::PllI2s, ConfiguredClk};
+
+/// A spi peripheral that can operate in i2s mode.
+pub trait I2sMap: SpiDmaRequests + SpiI2Scfgr + SpiI2Spr {}
+
+/// An i2s peripheral with an extension block (I2Sx_ext) for full duplex operation.
+pub trait I2sExtMap: I2sMap {
+    /// The register tokens of the extension block.
+    type Ext: I2sExtRegs;
+    /// The receive dma request of the extension.
+    type ExtRx: DmaRequestToken;
+    /// The transmit dma request of the extension.
+    type ExtTx: DmaRequestToken;
+}
+
+/// The register tokens of an i2s extension block, e.g. [`I2s2Ext`].
+pub trait I2sExtRegs: Send + 'static {
+    /// The address of the data register.
+    fn dr_addr(&self) -> u32;
+
+    /// Use the configuration `cfgr` of the i2s, but with the extension operating in `mode`.
+    fn configure(&self, cfgr: u32, mode: I2sMode);
+
+    /// Enable the receive dma request.
+    fn enable_rx_dma(&self);
+
+    /// Enable the extension.
+    fn enable(&self);
+
+    /// Disable the extension and its dma requests.
+    fn disable(&self);
+}
+
+/// Defines the register tokens of an i2s extension block and implements [`I2sExtRegs`].
+#[macro_export]
+macro_rules! i2s_ext {
+    ($doc:expr, $name:ident, $ext:ident, $cr2:ident, $dr:ident, $i2scfgr:ident) => {
+        #[doc = $doc]
+        pub struct $name {
+            pub $cr2: drone_stm32_map::reg::$ext::Cr2<drone_core::reg::tag::Srt>,
+            pub $dr: drone_stm32_map::reg::$ext::Dr<drone_core::reg::tag::Srt>,
+            pub $i2scfgr: drone_stm32_map::reg::$ext::I2Scfgr<drone_core::reg::tag::Srt>,
+        }
+
+        impl $crate::I2sExtRegs for $name {
+            fn dr_addr(&self) -> u32 {
+                use drone_cortexm::reg::prelude::*;
+                self.$dr.as_mut_ptr() as u32
+            }
+
+            fn configure(&self, cfgr: u32, mode: $crate::I2sMode) {
+                use drone_cortexm::reg::prelude::*;
+                self.$i2scfgr.store_bits(cfgr);
+                self.$i2scfgr.modify_reg(|r, v| {
+                    // Configuration mode.
+                    r.i2scfg().write(v, mode.bits());
+
+                    // Do not enable the extension before the dma is started.
+                    r.i2se().clear(v);
+                });
+            }
+
+            fn enable_rx_dma(&self) {
+                use drone_cortexm::reg::prelude::*;
+                self.$cr2.modify_reg(|r, v| r.rxdmaen().set(v));
+            }
+
+            fn enable(&self) {
+                use drone_cortexm::reg::prelude::*;
+                self.$i2scfgr.modify_reg(|r, v| r.i2se().set(v));
+            }
+
+            fn disable(&self) {
+                use drone_cortexm::reg::prelude::*;
+                self.$i2scfgr.modify_reg(|r, v| r.i2se().clear(v));
+                self.$cr2.modify_reg(|r, v| {
+                    r.txdmaen().clear(v);
+                    r.rxdmaen().clear(v);
+                });
+            }
+        }
+    };
+}
+
+/// I2s setup.
+pub struct I2sSetup<Spi: I2sMap> {
+    /// Spi peripheral.
+    pub spi: SpiPeriph<Spi>,
+    /// I2s clock, i.e. the plli2s output.
+    pub clk: ConfiguredClk<PllI2s>,
+    /// The i2s configuration mode.
+    pub mode: I2sMode,
+    /// The i2s standard.
+    pub standard: I2sStandard,
+    /// The data length and the channel length.
+    pub data_format: I2sDataFormat,
+    /// The steady state clock polarity.
+    pub clk_pol: ClkPol,
+    /// The audio sampling frequency in master mode.
+    pub audio_freq: u32,
+    /// Output the master clock on the `mck` pin in master mode, i.e. 256 times the audio sampling frequency.
+    pub mclk_output: bool,
+    ext_sd: bool,
+}
+
+impl<Spi: I2sMap> I2sSetup<Spi> {
+    /// Create a new i2s setup with sensible defaults.
+    /// The master clock is output if the `mck` pin is defined.
+    pub fn new<ExtSd: PinState, Mck: PinState>(
+        spi: SpiPeriph<Spi>,
+        _pins: I2sPins<Spi, Defined, Defined, Defined, ExtSd, Mck>,
+        clk: ConfiguredClk<PllI2s>,
+        mode: I2sMode,
+        audio_freq: u32,
+    ) -> Self {
+        Self {
+            spi,
+            clk,
+            mode,
+            standard: I2sStandard::Philips,
+            data_format: I2sDataFormat::Data16,
+            clk_pol: ClkPol::Low,
+            audio_freq,
+            mclk_output: Mck::DEFINED,
+            ext_sd: ExtSd::DEFINED,
+        }
+    }
+}
+
+/// The i2s configuration mode.
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2sMode {
+    /// Master transmit, generating the clocks.
+    MasterTx,
+    /// Master receive, generating the clocks.
+    MasterRx,
+    /// Slave transmit, clocked by an external master.
+    SlaveTx,
+    /// Slave receive, clocked by an external master.
+    SlaveRx,
+}
+
+impl I2sMode {
+    fn is_master(self) -> bool {
+        matches!(self, I2sMode::MasterTx | I2sMode::MasterRx)
+    }
+
+    fn is_tx(self) -> bool {
+        matches!(self, I2sMode::MasterTx | I2sMode::SlaveTx)
+    }
+
+    /// The value of the I2SCFG field.
+    pub fn bits(self) -> u32 {
+        match self {
+            I2sMode::SlaveTx => 0b00,
+            I2sMode::SlaveRx => 0b01,
+            I2sMode::MasterTx => 0b10,
+            I2sMode::MasterRx => 0b11,
+        }
+    }
+}
+
+/// The i2s standard.
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2sStandard {
+    /// I2s Philips standard.
+    Philips,
+    /// Msb justified (left justified) standard.
+    Msb,
+    /// Lsb justified (right justified) standard.
+    Lsb,
+    /// Pcm standard with short frame synchronization.
+    PcmShort,
+    /// Pcm standard with long frame synchronization.
+    PcmLong,
+}
+
+/// The i2s data and channel length.
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2sDataFormat {
+    /// 16-bit data in a 16-bit channel.
+    Data16,
+    /// 16-bit data extended to a 32-bit channel.
+    Data16Ext,
+    /// 24-bit data in a 32-bit channel.
+    Data24,
+    /// 32-bit data in a 32-bit channel.
+    Data32,
+}
+
+/// I2s driver.
+pub struct I2sDrv<Spi: I2sMap> {
+    spi: SpiDiverged<Spi>,
+    mode: I2sMode,
+    ext_sd: bool,
+}
+
+/// A transmitting i2s driver streaming samples from two buffers.
+pub struct I2sTx<Spi: I2sMap, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> {
+    drv: I2sDrv<Spi>,
+    dma: DmaDoubleBufTx<DmaCh, DmaInt, T>,
+}
+
+/// A receiving i2s driver streaming samples into two buffers.
+pub struct I2sRx<Spi: I2sMap, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> {
+    drv: I2sDrv<Spi>,
+    dma: DmaDoubleBufRx<DmaCh, DmaInt, T>,
+}
+
+/// A full duplex i2s driver, transmitting on the i2s and receiving on its extension.
+pub struct I2sDuplex<
+    Spi: I2sExtMap,
+    DmaTxCh: DmaChMap,
+    DmaTxInt: IntToken,
+    DmaRxCh: DmaChMap,
+    DmaRxInt: IntToken,
+    T: DmaItem,
+> {
+    drv: I2sDrv<Spi>,
+    ext: I2sExt<Spi>,
+    tx: DmaDoubleBufTx<DmaTxCh, DmaTxInt, T>,
+    rx: DmaDoubleBufRx<DmaRxCh, DmaRxInt, T>,
+}
+
+/// The i2s extension block, disabled when dropped.
+struct I2sExt<Spi: I2sExtMap> {
+    regs: Option<Spi::Ext>,
+}
+
+impl<Spi: I2sMap> I2sDrv<Spi> {
+    pub fn init(setup: I2sSetup<Spi>) -> I2sDrv<Spi> {
+        let drv = Self {
+            spi: setup.spi.into(),
+            mode: setup.mode,
+            ext_sd: setup.ext_sd,
+        };
+
+        // Enable spi clock.
+        drv.spi.rcc_busenr_spien.set_bit();
+
+        if setup.mode.is_master() {
+            let (div, odd) = i2s_div(setup.clk.f(), setup.audio_freq, setup.data_format, setup.mclk_output);
+            drv.spi.spi_i2spr.store_reg(|r, v| {
+                // Linear prescaler and odd factor.
+                r.i2sdiv().write(v, div);
+                if odd {
+                    r.odd().set(v);
+                }
+
+                // Master clock output.
+                if setup.mclk_output {
+                    r.mckoe().set(v);
+                }
+            });
+        }
+
+        drv.spi.spi_i2scfgr.store_reg(|r, v| {
+            // Select i2s mode.
+            r.i2smod().set(v);
+
+            // Configuration mode.
+            r.i2scfg().write(v, setup.mode.bits());
+
+            // Standard selection.
+            match setup.standard {
+                I2sStandard::Philips => r.i2sstd().write(v, 0b00),
+                I2sStandard::Msb => r.i2sstd().write(v, 0b01),
+                I2sStandard::Lsb => r.i2sstd().write(v, 0b10),
+                I2sStandard::PcmShort => r.i2sstd().write(v, 0b11),
+                I2sStandard::PcmLong => {
+                    r.i2sstd().write(v, 0b11);
+                    r.pcmsync().set(v);
+                }
+            };
+
+            // Data length and channel length.
+            match setup.data_format {
+                I2sDataFormat::Data16 => {}
+                I2sDataFormat::Data16Ext => r.chlen().set(v),
+                I2sDataFormat::Data24 => {
+                    r.datlen().write(v, 0b01);
+                    r.chlen().set(v);
+                }
+                I2sDataFormat::Data32 => {
+                    r.datlen().write(v, 0b10);
+                    r.chlen().set(v);
+                }
+            };
+
+            // Steady state clock polarity.
+            if setup.clk_pol == ClkPol::High {
+                r.ckpol().set(v);
+            }
+
+            // Do not enable i2s before the dma is started.
+        });
+
+        drv
+    }
+
+    /// Start transmitting samples from `bufs`, where both buffers must be filled with the first samples.
+    /// The samples are half-words, i.e. 24 and 32-bit data is transferred as two half-words with the most significant first.
+    pub fn into_tx<DmaCh: DmaRequest<Spi::Tx, DmaStCh>, DmaStCh: DmaStChToken, DmaInt: IntToken, T: DmaItem>(
+        self,
+        tx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        bufs: [Box<[T]>; 2],
+    ) -> I2sTx<Spi, DmaCh, DmaInt, T> {
+        assert!(self.mode.is_tx(), "The i2s is configured for reception");
+        assert_samples::<T>(&tx_cfg.dma_transfer);
+
+        let dma = unsafe { DmaDoubleBufTx::start(tx_cfg, self.spi.spi_dr.as_mut_ptr() as u32, bufs) };
+
+        // Enable dma request after the stream is enabled.
+        self.spi.spi_cr2.modify_reg(|r, v| r.txdmaen().set(v));
+        self.enable();
+
+        I2sTx { drv: self, dma }
+    }
+
+    /// Start receiving samples into `bufs`.
+    /// The samples are half-words, i.e. 24 and 32-bit data is transferred as two half-words with the most significant first.
+    pub fn into_rx<DmaCh: DmaRequest<Spi::Rx, DmaStCh>, DmaStCh: DmaStChToken, DmaInt: IntToken, T: DmaItem>(
+        self,
+        rx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        bufs: [Box<[T]>; 2],
+    ) -> I2sRx<Spi, DmaCh, DmaInt, T> {
+        assert!(!self.mode.is_tx(), "The i2s is configured for transmission");
+        assert_samples::<T>(&rx_cfg.dma_transfer);
+
+        let dma = unsafe { DmaDoubleBufRx::start(rx_cfg, self.spi.spi_dr.as_mut_ptr() as u32, bufs) };
+
+        // Enable dma request after the stream is enabled.
+        self.spi.spi_cr2.modify_reg(|r, v| r.rxdmaen().set(v));
+        self.enable();
+
+        I2sRx { drv: self, dma }
+    }
+
+    fn enable(&self) {
+        self.spi.spi_i2scfgr.modify_reg(|r, v| r.i2se().set(v));
+    }
+
+    fn disable(&self) {
+        self.spi.spi_i2scfgr.modify_reg(|r, v| r.i2se().clear(v));
+        self.spi.spi_cr2.modify_reg(|r, v| {
+            r.txdmaen().clear(v);
+            r.rxdmaen().clear(v);
+        });
+    }
+}
+
+impl<Spi: I2sExtMap> I2sDrv<Spi> {
+    /// Start full duplex operation, where the i2s transmits samples from `tx_bufs`
+    /// and the extension `ext` receives samples into `rx_bufs` using the same clock and word select.
+    /// Both transmit buffers must be filled with the first samples.
+    pub fn into_duplex<
+        DmaTxCh: DmaRequest<Spi::Tx, DmaTxStCh>,
+        DmaTxStCh: DmaStChToken,
+        DmaTxInt: IntToken,
+        DmaRxCh: DmaRequest<Spi::ExtRx, DmaRxStCh>,
+        DmaRxStCh: DmaStChToken,
+        DmaRxInt: IntToken,
+        T: DmaItem,
+    >(
+        self,
+        ext: Spi::Ext,
+        tx_cfg: DmaChCfg<DmaTxCh, DmaTxStCh, DmaTxInt>,
+        tx_bufs: [Box<[T]>; 2],
+        rx_cfg: DmaChCfg<DmaRxCh, DmaRxStCh, DmaRxInt>,
+        rx_bufs: [Box<[T]>; 2],
+    ) -> I2sDuplex<Spi, DmaTxCh, DmaTxInt, DmaRxCh, DmaRxInt, T> {
+        assert!(self.mode.is_tx(), "The i2s is configured for reception");
+        assert!(self.ext_sd, "The extension serial data pin is not defined");
+        assert_samples::<T>(&tx_cfg.dma_transfer);
+        assert_samples::<T>(&rx_cfg.dma_transfer);
+
+        // The extension is always a slave of the i2s, see RM0090 §28.4.3.
+        let ext = I2sExt::<Spi> { regs: Some(ext) };
+        let cfgr = self.spi.spi_i2scfgr.load_bits();
+        ext.regs().configure(cfgr, I2sMode::SlaveRx);
+
+        let rx = unsafe { DmaDoubleBufRx::start(rx_cfg, ext.regs().dr_addr(), rx_bufs) };
+        let tx = unsafe { DmaDoubleBufTx::start(tx_cfg, self.spi.spi_dr.as_mut_ptr() as u32, tx_bufs) };
+
+        // Enable dma requests after the streams are enabled.
+        ext.regs().enable_rx_dma();
+        self.spi.spi_cr2.modify_reg(|r, v| r.txdmaen().set(v));
+
+        // Enable the extension before the master starts the clock.
+        ext.regs().enable();
+        self.enable();
+
+        I2sDuplex { drv: self, ext, tx, rx }
+    }
+}
+
+impl<Spi: I2sMap> Drop for I2sDrv<Spi> {
+    fn drop(&mut self) {
+        self.disable();
+    }
+}
+
+impl<Spi: I2sMap, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> I2sTx<Spi, DmaCh, DmaInt, T> {
+    /// Wait for the next buffer to be transmitted, so that it can be filled with the next samples.
+    pub async fn next(&mut self) -> Result<DmaBufFree<'_, DmaCh, DmaInt, T>, DoubleBufError> {
+        self.dma.next().await
+    }
+
+    /// Stop the transmission and return the driver and the buffers.
+    pub fn stop(self) -> (I2sDrv<Spi>, [Box<[T]>; 2]) {
+        let Self { drv, dma } = self;
+        let bufs = dma.stop();
+        drv.disable();
+        (drv, bufs)
+    }
+}
+
+impl<Spi: I2sMap, DmaCh: DmaChMap, DmaInt: IntToken, T: DmaItem> I2sRx<Spi, DmaCh, DmaInt, T> {
+    /// Wait for the next buffer to be filled with received samples.
+    pub async fn next(&mut self) -> Result<DmaBufFull<'_, DmaCh, DmaInt, T>, DoubleBufError> {
+        self.dma.next().await
+    }
+
+    /// Stop the reception and return the driver and the buffers.
+    pub fn stop(self) -> (I2sDrv<Spi>, [Box<[T]>; 2]) {
+        let Self { drv, dma } = self;
+        let bufs = dma.stop();
+        drv.disable();
+        (drv, bufs)
+    }
+}
+
+impl<
+        Spi: I2sExtMap,
+        DmaTxCh: DmaChMap,
+        DmaTxInt: IntToken,
+        DmaRxCh: DmaChMap,
+        DmaRxInt: IntToken,
+        T: DmaItem,
+    > I2sDuplex<Spi, DmaTxCh, DmaTxInt, DmaRxCh, DmaRxInt, T>
+{
+    /// Wait for the next buffer to be transmitted, so that it can be filled with the next samples.
+    pub async fn next_tx(&mut self) -> Result<DmaBufFree<'_, DmaTxCh, DmaTxInt, T>, DoubleBufError> {
+        self.tx.next().await
+    }
+
+    /// Wait for the next buffer to be filled with received samples.
+    pub async fn next_rx(&mut self) -> Result<DmaBufFull<'_, DmaRxCh, DmaRxInt, T>, DoubleBufError> {
+        self.rx.next().await
+    }
+
+    /// Stop the transmission and reception and return the driver, the extension,
+    /// the transmit buffers and the receive buffers.
+    pub fn stop(self) -> (I2sDrv<Spi>, Spi::Ext, [Box<[T]>; 2], [Box<[T]>; 2]) {
+        let Self { drv, ext, tx, rx } = self;
+        let tx_bufs = tx.stop();
+        let rx_bufs = rx.stop();
+        drv.disable();
+        (drv, ext.release(), tx_bufs, rx_bufs)
+    }
+}
+
+impl<Spi: I2sExtMap> I2sExt<Spi> {
+    fn regs(&self) -> &Spi::Ext {
+        self.regs.as_ref().unwrap()
+    }
+
+    /// Disable the extension and return its register tokens.
+    fn release(mut self) -> Spi::Ext {
+        let regs = self.regs.take().unwrap();
+        regs.disable();
+        regs
+    }
+}
+
+impl<Spi: I2sExtMap> Drop for I2sExt<Spi> {
+    fn drop(&mut self) {
+        if let Some(regs) = &self.regs {
+            regs.disable();
+        }
+    }
+}
+
+/// Get the linear prescaler (I2SDIV) and odd factor (ODD) for the audio sampling frequency
+/// from the i2s clock frequency `f_i2sclk`, see RM0090 §28.4.4.
+fn i2s_div(
+    f_i2sclk: u32,
+    audio_freq: u32,
+    data_format: I2sDataFormat,
+    mclk_output: bool,
+) -> (u32, bool) {
+    // The number of i2s clock cycles per sample for each step of the prescaler.
+    let cycles = if mclk_output {
+        256
+    } else if data_format == I2sDataFormat::Data16 {
+        32
+    } else {
+        64
+    };
+    let f = cycles * audio_freq;
+    let div = (f_i2sclk + f / 2) / f;
+    assert!(
+        (4..=511).contains(&div),
+        "The audio sampling frequency cannot be derived from the i2s clock"
+    );
+    (div / 2, div % 2 == 1)
+}
+
+fn assert_samples<T: DmaItem>(transfer: &DmaTransfer) {
+    assert!(
+        T::SIZE == DataSize::HalfWord,
+        "The i2s samples must be half-words"
+    );
+    assert!(
+        transfer.fifo_threshold().is_none() || transfer.psize() == DataSize::HalfWord,
+        "The dma peripheral data size must be half-word"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The i2s clock for the PLLI2SN and PLLI2SR factors with a 1 MHz PLL input, i.e. an 8 MHz HSE.
+    fn f_i2sclk(plli2sn: u32, plli2sr: u32) -> u32 {
+        1_000_000 * plli2sn / plli2sr
+    }
+
+    /// The audio-frequency precision table for an 8 MHz HSE, see RM0090 §28.4.4,
+    /// as (audio frequency, PLLI2SN, PLLI2SR, I2SDIV, I2SODD).
+    const MCLK_DISABLED_16: [(u32, u32, u32, u32, bool); 8] = [
+        (8000, 192, 2, 187, true),
+        (16000, 192, 3, 62, true),
+        (22050, 290, 3, 68, true),
+        (32000, 256, 2, 62, true),
+        (44100, 302, 2, 53, true),
+        (48000, 192, 5, 12, true),
+        (96000, 384, 5, 12, true),
+        (192000, 424, 3, 11, true),
+    ];
+    const MCLK_DISABLED_32: [(u32, u32, u32, u32, bool); 8] = [
+        (8000, 192, 3, 62, true),
+        (16000, 256, 2, 62, true),
+        (22050, 302, 2, 53, true),
+        (32000, 256, 5, 12, true),
+        (44100, 429, 4, 19, false),
+        (48000, 384, 5, 12, true),
+        (96000, 424, 3, 11, true),
+        (192000, 258, 3, 3, true),
+    ];
+    const MCLK_ENABLED: [(u32, u32, u32, u32, bool); 7] = [
+        (8000, 256, 5, 12, true),
+        (16000, 213, 2, 13, false),
+        (22050, 429, 4, 9, true),
+        (32000, 213, 2, 6, true),
+        (44100, 271, 2, 6, false),
+        (48000, 258, 3, 3, true),
+        (96000, 344, 2, 3, true),
+    ];
+
+    #[test]
+    fn mclk_disabled_16bit() {
+        for &(audio_freq, n, r, div, odd) in MCLK_DISABLED_16.iter() {
+            assert_eq!(
+                i2s_div(f_i2sclk(n, r), audio_freq, I2sDataFormat::Data16, false),
+                (div, odd),
+                "{} Hz",
+                audio_freq
+            );
+        }
+    }
+
+    #[test]
+    fn mclk_disabled_32bit() {
+        for &(audio_freq, n, r, div, odd) in MCLK_DISABLED_32.iter() {
+            for &data_format in [I2sDataFormat::Data16Ext, I2sDataFormat::Data24, I2sDataFormat::Data32].iter() {
+                assert_eq!(
+                    i2s_div(f_i2sclk(n, r), audio_freq, data_format, false),
+                    (div, odd),
+                    "{} Hz",
+                    audio_freq
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mclk_enabled() {
+        for &(audio_freq, n, r, div, odd) in MCLK_ENABLED.iter() {
+            for &data_format in [I2sDataFormat::Data16, I2sDataFormat::Data32].iter() {
+                assert_eq!(
+                    i2s_div(f_i2sclk(n, r), audio_freq, data_format, true),
+                    (div, odd),
+                    "{} Hz",
+                    audio_freq
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn prescaler_too_small() {
+        i2s_div(f_i2sclk(192, 5), 192000, I2sDataFormat::Data32, true);
+    }
+
+    #[test]
+    #[should_panic]
+    fn prescaler_too_large() {
+        i2s_div(f_i2sclk(432, 2), 8000, I2sDataFormat::Data16, false);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{diverged::SpiDiverged, master::SpiErrors, DataFrame, FirstBit, Prescaler, SpiError, SpiMode, SpiWord};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::spi::{traits::*, SpiMap};
+use futures::future::{self, Either};
+
+/// Spi master driver without dma, where the frames are transferred from the spi interrupt.
+/// Useful for short register accesses, or when no dma streams are available.
+pub struct SpiIntMasterDrv<Spi: SpiMap, SpiInt: IntToken> {
+    pub(crate) spi: SpiDiverged<Spi>,
+    spi_int: SpiInt,
+    errors: Arc<SpiErrors>,
+}
+
+impl<Spi: SpiMap, SpiInt: IntToken> SpiIntMasterDrv<Spi, SpiInt> {
+    pub(crate) fn init(spi: SpiDiverged<Spi>, spi_int: SpiInt, errors: Arc<SpiErrors>) -> Self {
+        let master = Self { spi, spi_int, errors };
+        master.spi.enable_master();
+        master
+    }
+
+    /// Send to the currently selected slave.
+    /// The buffer items must match the configured data frame, i.e. `u8` for 8-bit and `u16` for 16-bit frames.
+    pub async fn write<Word: SpiWord>(&mut self, buf: &[Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
+        unsafe { self.xfer_impl(buf.as_ptr(), core::ptr::null_mut(), buf.len()).await }
+    }
+
+    /// Read from the currently selected slave.
+    pub async fn read<Word: SpiWord>(&mut self, buf: &mut [Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
+        unsafe { self.xfer_impl(core::ptr::null(), buf.as_mut_ptr(), buf.len()).await }
+    }
+
+    /// Send to and receive from the currently selected slave.
+    pub async fn xfer<Word: SpiWord>(&mut self, tx_buf: &[Word], rx_buf: &mut [Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
+        assert_eq!(tx_buf.len(), rx_buf.len());
+        unsafe { self.xfer_impl(tx_buf.as_ptr(), rx_buf.as_mut_ptr(), tx_buf.len()).await }
+    }
+
+    /// Change the spi mode, e.g. before selecting a chip that requires another mode than the previous one.
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        self.spi.set_mode(mode);
+    }
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    pub fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        self.spi.configure(prescaler, mode, first_bit);
+    }
+
+    /// The current spi mode.
+    pub fn mode(&self) -> SpiMode {
+        self.spi.mode()
+    }
+
+    /// The configured data frame.
+    pub fn data_frame(&self) -> DataFrame {
+        self.spi.data_frame()
+    }
+
+    /// Transfer `len` frames, where a null `tx` sends zeros and a null `rx` discards the received frames.
+    /// The next frame is sent when the previous frame is received, so the receive buffer cannot overrun.
+    ///
+    /// If the returned future is dropped before completion, the fiber is detached from the buffers.
+    async unsafe fn xfer_impl<Word: SpiWord>(
+        &mut self,
+        tx: *const Word,
+        rx: *mut Word,
+        len: usize,
+    ) -> Result<(), SpiError> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.spi.wait_for_idle();
+
+        self.errors.clear();
+
+        let spi_sr = self.spi.spi_sr;
+        let spi_dr = self.spi.spi_dr;
+        let spi_cr2 = self.spi.spi_cr2;
+        let detached = Arc::new(AtomicBool::new(false));
+        let fib_detached = Arc::clone(&detached);
+        // The buffer addresses are passed as integers, as raw pointers are not `Send`.
+        let tx = tx as usize;
+        let rx = rx as usize;
+        let mut index = 0;
+        let completed = self.spi_int.add_future(fib::new_fn(move || {
+            if fib_detached.load(Ordering::Acquire) {
+                // The future was dropped, and the buffers must not be accessed.
+                return fib::Complete(());
+            }
+            if !spi_sr.rxne().read_bit() {
+                return fib::Yielded(());
+            }
+            let frame = Word::from_frame(spi_dr.dr().read_bits());
+            if rx != 0 {
+                unsafe { (rx as *mut Word).add(index).write(frame) };
+            }
+            index += 1;
+            if index < len {
+                spi_dr.store_reg(|r, v| r.dr().write(v, unsafe { frame_at(tx, index) }));
+                fib::Yielded(())
+            } else {
+                // Disable receive buffer not empty interrupt.
+                spi_cr2.modify_reg(|r, v| r.rxneie().clear(v));
+                fib::Complete(())
+            }
+        }));
+        let guard = XferGuard { drv: self, detached };
+
+        // Enable receive buffer not empty interrupt.
+        guard.drv.spi.spi_cr2.modify_reg(|r, v| r.rxneie().set(v));
+
+        // Send the first frame, the remaining frames are sent from the interrupt.
+        guard.drv.spi.spi_dr.store_reg(|r, v| r.dr().write(v, frame_at::<Word>(tx, 0)));
+
+        // The error interrupt reports an overrun or a mode fault, which stops the transfer.
+        let errors = Arc::clone(&guard.drv.errors);
+        match future::select(completed, errors.wait()).await {
+            Either::Left(((), _)) => core::mem::forget(guard),
+            Either::Right(((), _)) => drop(guard),
+        }
+        let result = self.errors.take();
+        if let Err(SpiError::ModeFault) = result {
+            // The mode fault has disabled the spi.
+            self.spi.enable_master();
+        }
+        result
+    }
+}
+
+/// Stops an interrupt driven transfer when its future is dropped before completion.
+struct XferGuard<'a, Spi: SpiMap, SpiInt: IntToken> {
+    drv: &'a SpiIntMasterDrv<Spi, SpiInt>,
+    detached: Arc<AtomicBool>,
+}
+
+impl<Spi: SpiMap, SpiInt: IntToken> Drop for XferGuard<'_, Spi, SpiInt> {
+    fn drop(&mut self) {
+        // Disable receive buffer not empty interrupt.
+        self.drv.spi.spi_cr2.modify_reg(|r, v| r.rxneie().clear(v));
+
+        // Detach the fiber from the buffers, and let it complete.
+        self.detached.store(true, Ordering::Release);
+        self.drv.spi_int.set_pending();
+
+        // Wait for any ongoing frame, and discard it.
+        self.drv.spi.wait_for_idle();
+        self.drv.spi.spi_dr.load_val();
+        self.drv.spi.spi_sr.load_val();
+    }
+}
+
+/// The data register value for the item at `index` in the buffer at `tx`, or zero if there is no buffer.
+unsafe fn frame_at<Word: SpiWord>(tx: usize, index: usize) -> u32 {
+    if tx == 0 {
+        0
+    } else {
+        (tx as *const Word).add(index).read().into_frame()
+    }
+}
+
+impl<Spi: SpiMap, SpiInt: IntToken> Drop for SpiIntMasterDrv<Spi, SpiInt> {
+    fn drop(&mut self) {
+        self.spi.disable();
+    }
+}
@@ -2,23 +2,44 @@
 #![feature(prelude_import)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
+mod bidi_master;
+mod bus;
 pub mod chipctrl;
 mod diverged;
 mod drv;
+mod i2s;
+mod int_master;
 mod mappings;
 mod master;
+pub mod nor;
+mod polled_master;
+pub mod sdcard;
 mod setup;
+mod slave;
 mod pins;
 
-pub use self::drv::SpiDrv;
-pub use self::master::SpiMasterDrv;
-pub use self::setup::{SpiSetup, BaudRate, Prescaler, ClkPol, FirstBit};
+extern crate alloc;
+
+pub use self::bidi_master::SpiBidiMasterDrv;
+pub use self::bus::{SpiBus, SpiDevice, SpiDeviceConfig, SpiDeviceGuard};
+pub use self::drv::{SpiDmaRequests, SpiDrv};
+pub use self::i2s::{
+    I2sDataFormat, I2sDrv, I2sDuplex, I2sExtMap, I2sExtRegs, I2sMap, I2sMode, I2sRx, I2sSetup, I2sStandard, I2sTx,
+};
+pub use self::int_master::SpiIntMasterDrv;
+pub use self::mappings::*;
+pub use self::master::{SpiError, SpiMasterDrv};
+pub use self::polled_master::SpiPolledMasterDrv;
+pub use self::slave::{SpiSlaveDrv, SpiSlaveError, SpiTransaction};
+pub use self::setup::{
+    BaudRate, ClkPhase, ClkPol, DataFrame, DataLines, FirstBit, FrameFormat, Prescaler, SpiMode, SpiSetup, SpiWord,
+};
 pub use drone_stm32_map::periph::spi::SpiMap;
 pub use self::prelude::*;
-pub use self::pins::SpiPins;
+pub use self::pins::{I2sPins, NssPin, PinState, SpiPins};
 
 pub mod prelude {
-    pub use crate::drv::IntoMaster;
+    pub use crate::drv::{IntoMaster, IntoSlave};
     pub use super::setup::NewSpiSetup;
     pub use crate::pins::traits::*;
 }
@@ -1,15 +1,62 @@
-use crate::{spi_setup, master_drv_init, pins::{*, traits::*}};
+use crate::{i2s_ext, nss_pin, spi_setup, drv::SpiDmaRequests, i2s::{I2sExtMap, I2sMap}, pins::{*, traits::*}};
 use drone_stm32_map::periph::gpio::pin::*;
 use drone_stm32_map::periph::spi::Spi2;
-use drone_stm32_map::periph::dma::ch::{Dma1Ch3, Dma1Ch4};
-use drone_stm32f4_dma_drv::DmaStCh0;
+use drone_stm32f4_dma_drv::request::{Spi2Rx, Spi2Tx};
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+use drone_stm32f4_dma_drv::request::{I2s2ExtRx, I2s2ExtTx};
 use drone_stm32f4_gpio_drv::pin_impl;
 use drone_stm32f4_gpio_drv::prelude::*;
 use drone_stm32f4_rcc_drv::clktree::PClk2;
 
 spi_setup!(Spi2, PClk2);
 
-master_drv_init!(Spi2, Dma1Ch3, DmaStCh0, Dma1Ch4, DmaStCh0);
+impl SpiDmaRequests for Spi2 {
+    type Rx = Spi2Rx;
+    type Tx = Spi2Tx;
+}
+
+impl I2sMap for Spi2 {}
+
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+i2s_ext!(
+    "The register tokens of the I2S2ext extension block.",
+    I2s2Ext,
+    i2s2ext,
+    i2s2ext_cr2,
+    i2s2ext_dr,
+    i2s2ext_i2scfgr
+);
+
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+impl I2sExtMap for Spi2 {
+    type Ext = I2s2Ext;
+    type ExtRx = I2s2ExtRx;
+    type ExtTx = I2s2ExtTx;
+}
 
 pin_impl!(SckPinExt for SpiPins<Spi2, ...>.sck, GpioB10, AlternateMode<PinAf5>; Undefined, Miso, Mosi -> Defined, Miso, Mosi);
 pin_impl!(SckPinExt for SpiPins<Spi2, ...>.sck, GpioB13, AlternateMode<PinAf5>; Undefined, Miso, Mosi -> Defined, Miso, Mosi);
@@ -44,3 +91,61 @@ pin_impl!(MosiPinExt for SpiPins<Spi2, ...>.mosi, GpioC3, AlternateMode<PinAf5>;
     stm32_mcu = "stm32f469",
 ))]
 pin_impl!(MosiPinExt for SpiPins<Spi2, ...>.mosi, GpioI3, AlternateMode<PinAf5>; Sck, Miso, Undefined -> Sck, Miso, Defined);
+
+nss_pin!(Spi2, GpioB9, PinAf5);
+nss_pin!(Spi2, GpioB12, PinAf5);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+nss_pin!(Spi2, GpioI0, PinAf5);
+
+pin_impl!(CkPinExt for I2sPins<Spi2, ...>.ck, GpioB10, AlternateMode<PinAf5>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+pin_impl!(CkPinExt for I2sPins<Spi2, ...>.ck, GpioB13, AlternateMode<PinAf5>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+pin_impl!(CkPinExt for I2sPins<Spi2, ...>.ck, GpioD3, AlternateMode<PinAf5>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(CkPinExt for I2sPins<Spi2, ...>.ck, GpioI1, AlternateMode<PinAf5>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+
+pin_impl!(WsPinExt for I2sPins<Spi2, ...>.ws, GpioB9, AlternateMode<PinAf5>; Ck, Undefined, Sd, ExtSd, Mck -> Ck, Defined, Sd, ExtSd, Mck);
+pin_impl!(WsPinExt for I2sPins<Spi2, ...>.ws, GpioB12, AlternateMode<PinAf5>; Ck, Undefined, Sd, ExtSd, Mck -> Ck, Defined, Sd, ExtSd, Mck);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(WsPinExt for I2sPins<Spi2, ...>.ws, GpioI0, AlternateMode<PinAf5>; Ck, Undefined, Sd, ExtSd, Mck -> Ck, Defined, Sd, ExtSd, Mck);
+
+pin_impl!(SdPinExt for I2sPins<Spi2, ...>.sd, GpioB15, AlternateMode<PinAf5>; Ck, Ws, Undefined, ExtSd, Mck -> Ck, Ws, Defined, ExtSd, Mck);
+pin_impl!(SdPinExt for I2sPins<Spi2, ...>.sd, GpioC3, AlternateMode<PinAf5>; Ck, Ws, Undefined, ExtSd, Mck -> Ck, Ws, Defined, ExtSd, Mck);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(SdPinExt for I2sPins<Spi2, ...>.sd, GpioI3, AlternateMode<PinAf5>; Ck, Ws, Undefined, ExtSd, Mck -> Ck, Ws, Defined, ExtSd, Mck);
+
+pin_impl!(ExtSdPinExt for I2sPins<Spi2, ...>.ext_sd, GpioB14, AlternateMode<PinAf6>; Ck, Ws, Sd, Undefined, Mck -> Ck, Ws, Sd, Defined, Mck);
+pin_impl!(ExtSdPinExt for I2sPins<Spi2, ...>.ext_sd, GpioC2, AlternateMode<PinAf6>; Ck, Ws, Sd, Undefined, Mck -> Ck, Ws, Sd, Defined, Mck);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(ExtSdPinExt for I2sPins<Spi2, ...>.ext_sd, GpioI2, AlternateMode<PinAf6>; Ck, Ws, Sd, Undefined, Mck -> Ck, Ws, Sd, Defined, Mck);
+
+pin_impl!(MckPinExt for I2sPins<Spi2, ...>.mck, GpioC6, AlternateMode<PinAf5>; Ck, Ws, Sd, ExtSd, Undefined -> Ck, Ws, Sd, ExtSd, Defined);
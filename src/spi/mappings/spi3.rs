@@ -1,16 +1,75 @@
-use crate::{spi_setup, master_drv_init};
+use crate::{i2s_ext, spi_setup, drv::SpiDmaRequests, i2s::{I2sExtMap, I2sMap}, pins::{*, traits::*}};
+use drone_stm32_map::periph::gpio::pin::*;
 use drone_stm32_map::periph::spi::Spi3;
-use drone_stm32_map::periph::dma::ch::{Dma1Ch0, Dma1Ch2, Dma1Ch5, Dma1Ch7};
-use drone_stm32f4_dma_drv::DmaStCh0;
+use drone_stm32f4_dma_drv::request::{Spi3Rx, Spi3Tx};
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+use drone_stm32f4_dma_drv::request::{I2s3ExtRx, I2s3ExtTx};
+use drone_stm32f4_gpio_drv::pin_impl;
+use drone_stm32f4_gpio_drv::prelude::*;
 use drone_stm32f4_rcc_drv::clktree::PClk1;
-// use drone_stm32f4_gpio_drv::pin_impl;
-// use drone_stm32f4_gpio_drv::prelude::*;
 
 spi_setup!(Spi3, PClk1);
 
-master_drv_init!(Spi3, Dma1Ch0, DmaStCh0, Dma1Ch5, DmaStCh0);
-master_drv_init!(Spi3, Dma1Ch0, DmaStCh0, Dma1Ch7, DmaStCh0);
-master_drv_init!(Spi3, Dma1Ch2, DmaStCh0, Dma1Ch5, DmaStCh0);
-master_drv_init!(Spi3, Dma1Ch2, DmaStCh0, Dma1Ch7, DmaStCh0);
+impl SpiDmaRequests for Spi3 {
+    type Rx = Spi3Rx;
+    type Tx = Spi3Tx;
+}
 
-// TODO
+impl I2sMap for Spi3 {}
+
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+i2s_ext!(
+    "The register tokens of the I2S3ext extension block.",
+    I2s3Ext,
+    i2s3ext,
+    i2s3ext_cr2,
+    i2s3ext_dr,
+    i2s3ext_i2scfgr
+);
+
+#[cfg(any(
+    stm32_mcu = "stm32f401",
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f411",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f469",
+))]
+impl I2sExtMap for Spi3 {
+    type Ext = I2s3Ext;
+    type ExtRx = I2s3ExtRx;
+    type ExtTx = I2s3ExtTx;
+}
+
+// TODO: Spi pins.
+
+pin_impl!(CkPinExt for I2sPins<Spi3, ...>.ck, GpioB3, AlternateMode<PinAf6>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+pin_impl!(CkPinExt for I2sPins<Spi3, ...>.ck, GpioC10, AlternateMode<PinAf6>; Undefined, Ws, Sd, ExtSd, Mck -> Defined, Ws, Sd, ExtSd, Mck);
+
+pin_impl!(WsPinExt for I2sPins<Spi3, ...>.ws, GpioA4, AlternateMode<PinAf6>; Ck, Undefined, Sd, ExtSd, Mck -> Ck, Defined, Sd, ExtSd, Mck);
+pin_impl!(WsPinExt for I2sPins<Spi3, ...>.ws, GpioA15, AlternateMode<PinAf6>; Ck, Undefined, Sd, ExtSd, Mck -> Ck, Defined, Sd, ExtSd, Mck);
+
+pin_impl!(SdPinExt for I2sPins<Spi3, ...>.sd, GpioB5, AlternateMode<PinAf6>; Ck, Ws, Undefined, ExtSd, Mck -> Ck, Ws, Defined, ExtSd, Mck);
+pin_impl!(SdPinExt for I2sPins<Spi3, ...>.sd, GpioC12, AlternateMode<PinAf6>; Ck, Ws, Undefined, ExtSd, Mck -> Ck, Ws, Defined, ExtSd, Mck);
+
+pin_impl!(ExtSdPinExt for I2sPins<Spi3, ...>.ext_sd, GpioB4, AlternateMode<PinAf7>; Ck, Ws, Sd, Undefined, Mck -> Ck, Ws, Sd, Defined, Mck);
+pin_impl!(ExtSdPinExt for I2sPins<Spi3, ...>.ext_sd, GpioC11, AlternateMode<PinAf5>; Ck, Ws, Sd, Undefined, Mck -> Ck, Ws, Sd, Defined, Mck);
+
+pin_impl!(MckPinExt for I2sPins<Spi3, ...>.mck, GpioC7, AlternateMode<PinAf6>; Ck, Ws, Sd, ExtSd, Undefined -> Ck, Ws, Sd, ExtSd, Defined);
@@ -1,13 +1,16 @@
-// use crate::{spi_setup, master_drv_init, pins::{*, traits::*}};
+// use crate::{spi_setup, pins::{*, traits::*}};
+use crate::drv::SpiDmaRequests;
 use drone_stm32_map::periph::spi::Spi6;
-use drone_stm32_map::periph::dma::ch::{Dma2Ch6, Dma2Ch5};
-use drone_stm32f4_dma_drv::DmaStCh1;
+use drone_stm32f4_dma_drv::request::{Spi6Rx, Spi6Tx};
 use drone_stm32f4_rcc_drv::clktree::PClk2;
 // use drone_stm32f4_gpio_drv::pin_impl;
 // use drone_stm32f4_gpio_drv::prelude::*;
 
 spi_setup!(Spi6, PClk2);
 
-master_drv_init!(Spi6, Dma2Ch6, DmaStCh1, Dma2Ch5, DmaStCh1);
+impl SpiDmaRequests for Spi6 {
+    type Rx = Spi6Rx;
+    type Tx = Spi6Tx;
+}
 
 // TODO
@@ -1,10 +1,48 @@
-use crate::diverged::{DmaChDiverged, SpiDiverged};
-use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use crate::{
+    diverged::SpiDiverged,
+    DataFrame, FirstBit, Prescaler, SpiMode, SpiWord,
+};
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    mem::size_of_val,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+use displaydoc::Display;
+use drone_cortexm::{reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::{
     dma::ch::DmaChMap,
     spi::{traits::*, SpiMap},
 };
-use drone_stm32f4_dma_drv::{DmaChCfg, DmaStChToken};
+use drone_stm32f4_dma_drv::{config::DmaTransfer, DmaChCfg, DmaChDiverged, DmaChNum, DmaError, DmaStChToken};
+use futures::{
+    future::{self, Either},
+    task::AtomicWaker,
+};
+
+/// Spi master transfer error.
+#[derive(Display, Debug)]
+pub enum SpiError {
+    /// Crc mismatch, the received crc does not match the crc calculated from the received frames.
+    Crc,
+    /// Overrun, a frame was received before the previous frame was read.
+    Overrun,
+    /// Mode fault, the nss pin was pulled low by another master.
+    ModeFault,
+    /// Dma error: {0}
+    Dma(DmaError),
+}
+
+/// The errors reported by the spi error interrupt for the transfer in progress.
+#[derive(Default)]
+pub(crate) struct SpiErrors {
+    crc: AtomicBool,
+    overrun: AtomicBool,
+    mode_fault: AtomicBool,
+    /// The transfer waiting for an overrun or a mode fault.
+    waker: AtomicWaker,
+}
 
 pub struct SpiMasterDrv<
     Spi: SpiMap,
@@ -18,6 +56,8 @@ pub struct SpiMasterDrv<
     pub(crate) dma_rx_int: DmaRxInt,
     pub(crate) dma_tx: DmaChDiverged<DmaTx>,
     pub(crate) dma_tx_int: DmaTxInt,
+    dma_threshold: usize,
+    errors: Arc<SpiErrors>,
 }
 
 impl<
@@ -30,19 +70,26 @@ impl<
 {
     pub(crate) fn init<DmaRxStCh: DmaStChToken, DmaTxStCh: DmaStChToken>(
         spi: SpiDiverged<Spi>,
+        errors: Arc<SpiErrors>,
         miso_cfg: DmaChCfg<DmaRx, DmaRxStCh, DmaRxInt>,
         mosi_cfg: DmaChCfg<DmaTx, DmaTxStCh, DmaTxInt>,
-    ) -> Self {
+    ) -> Self
+    where
+        DmaRx: DmaChNum,
+        DmaTx: DmaChNum,
+    {
         let DmaChCfg {
             dma_ch: dma_rx,
             dma_int: dma_rx_int,
             dma_pl: dma_rx_pl,
+            dma_transfer: dma_rx_transfer,
             ..
         } = miso_cfg;
         let DmaChCfg {
             dma_ch: dma_tx,
             dma_int: dma_tx_int,
             dma_pl: dma_tx_pl,
+            dma_transfer: dma_tx_transfer,
             ..
         } = mosi_cfg;
         let master = Self {
@@ -51,111 +98,199 @@ impl<
             dma_rx_int,
             dma_tx: dma_tx.into(),
             dma_tx_int,
+            dma_threshold: 0,
+            errors,
         };
 
-        master.spi.spi_cr1.modify_reg(|r, v| {
-            // Master configuration.
-            r.mstr().set(v);
-
-            // Use software slave management, i.e. the app controls slave selection.
-            // The hardware NSS pin is free for other use.
-            r.ssm().set(v);
-
-            // Internal slave select (required for master operation when software slave management (SSM) is being used).
-            r.ssi().set(v);
+        master.spi.enable_master();
 
-            // Enable spi after being fully configured.
-            r.spe().set(v);
-        });
+        let data_frame = master.data_frame();
+        master.dma_rx.init_dma_rx(
+            master.spi.spi_dr.as_mut_ptr() as u32,
+            DmaRxStCh::NUM,
+            dma_rx_pl,
+            frame_transfer(data_frame, dma_rx_transfer),
+        );
 
-        master
-            .dma_rx
-            .init_dma_rx(master.spi.spi_dr.as_mut_ptr() as u32, DmaRxStCh::NUM, dma_rx_pl);
-        master.dma_rx.panic_on_err(master.dma_rx_int);
-
-        master
-            .dma_tx
-            .init_dma_tx(master.spi.spi_dr.as_mut_ptr() as u32, DmaTxStCh::NUM, dma_tx_pl);
-        master.dma_tx.panic_on_err(master.dma_tx_int);
+        master.dma_tx.init_dma_tx(
+            master.spi.spi_dr.as_mut_ptr() as u32,
+            DmaTxStCh::NUM,
+            dma_tx_pl,
+            frame_transfer(data_frame, dma_tx_transfer),
+        );
 
         master
     }
 
     /// Send to the currently selected slave.
-    pub async fn write(&mut self, buf: &[u8]) {
+    /// The buffer items must match the configured data frame, i.e. `u8` for 8-bit and `u16` for 16-bit frames.
+    ///
+    /// A failed DMA transfer is reported as an error, and the driver is then ready for another transfer.
+    /// If the crc calculation is enabled, the crc is sent after the last frame.
+    pub async fn write<Word: SpiWord>(&mut self, buf: &[Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
         if buf.is_empty() {
-            return;
+            return Ok(());
         }
 
         self.wait_for_idle();
 
+        if self.bypass_dma(buf.len()) {
+            self.spi.write_polled(buf);
+            return Ok(());
+        }
+
         unsafe {
             // Setup DMA transfer parameters.
-            self.dma_rx.setup_dummy_stream(buf.len());
+            self.dma_rx.setup_dummy_stream(size_of_val(buf));
             self.dma_tx.setup_stream(buf);
 
-            self.xfer_impl().await;
+            self.xfer_impl().await
         }
     }
 
     /// Read from the currently selected slave.
-    pub async fn read(&mut self, buf: &mut [u8]) {
+    /// If the crc calculation is enabled, the received crc is checked after the last frame.
+    pub async fn read<Word: SpiWord>(&mut self, buf: &mut [Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
         if buf.is_empty() {
-            return;
+            return Ok(());
         }
 
         self.wait_for_idle();
 
+        if self.bypass_dma(buf.len()) {
+            self.spi.read_polled(buf);
+            return Ok(());
+        }
+
         unsafe {
             self.dma_rx.setup_stream(buf);
-            self.dma_tx.setup_dummy_stream(buf.len());
+            self.dma_tx.setup_dummy_stream(size_of_val(buf));
 
-            self.xfer_impl().await;
+            self.xfer_impl().await
         }
     }
 
     /// Send to and receive from the currently selected slave.
-    pub async fn xfer(&mut self, tx_buf: &[u8], rx_buf: &mut [u8]) {
+    pub async fn xfer<Word: SpiWord>(&mut self, tx_buf: &[Word], rx_buf: &mut [Word]) -> Result<(), SpiError> {
+        self.spi.assert_frame::<Word>();
         assert_eq!(tx_buf.len(), rx_buf.len());
 
         if tx_buf.is_empty() {
-            return;
+            return Ok(());
         }
 
         self.wait_for_idle();
 
+        if self.bypass_dma(tx_buf.len()) {
+            self.spi.xfer_polled(tx_buf, rx_buf);
+            return Ok(());
+        }
+
         unsafe {
             self.dma_rx.setup_stream(rx_buf);
             self.dma_tx.setup_stream(tx_buf);
 
-            self.xfer_impl().await;
+            self.xfer_impl().await
         }
     }
 
-    fn wait_for_idle(&self) {
-        loop {
-            let spi_sr = self.spi.spi_sr;
-            let sr_val = spi_sr.load_val();
-            if spi_sr.txe().read(&sr_val) && !spi_sr.bsy().read(&sr_val) {
+    /// Transfer buffers shorter than `frames` by polling instead of dma.
+    /// The dma setup and the completion interrupt are slower than polling for a few frames.
+    /// The threshold is ignored if the crc calculation is enabled.
+    pub fn set_dma_threshold(&mut self, frames: usize) {
+        self.dma_threshold = frames;
+    }
+
+    /// The number of frames below which transfers are done by polling.
+    pub fn dma_threshold(&self) -> usize {
+        self.dma_threshold
+    }
+
+    /// Measure the polled and the dma transfer time for increasing lengths,
+    /// and use the shortest length where dma is faster as the dma threshold.
+    ///
+    /// Zeros are sent during the measurement, so no chip should be selected.
+    /// The transfer times are measured with the cycle count returned by `cycles`,
+    /// e.g. `|| stopwatch.elapsed()` for a running `dwt::Stopwatch`.
+    pub async fn calibrate_dma_threshold<Cycles: FnMut() -> u32>(
+        &mut self,
+        mut cycles: Cycles,
+    ) -> Result<usize, SpiError> {
+        const MAX_FRAMES: usize = 32;
+        assert!(!self.spi.crc_enabled(), "The crc calculation must be disabled");
+        let frame_bytes = self.data_frame().bytes();
+
+        self.wait_for_idle();
+        let mut threshold = MAX_FRAMES;
+        for frames in 1..=MAX_FRAMES {
+            let start = cycles();
+            for _ in 0..frames {
+                self.spi.transfer_frame(0);
+            }
+            self.wait_for_idle();
+            let polled = cycles().wrapping_sub(start);
+
+            let start = cycles();
+            unsafe {
+                self.dma_rx.setup_dummy_stream(frames * frame_bytes);
+                self.dma_tx.setup_dummy_stream(frames * frame_bytes);
+                self.xfer_impl().await?;
+            }
+            self.wait_for_idle();
+            let dma = cycles().wrapping_sub(start);
+
+            if dma < polled {
+                threshold = frames;
                 break;
             }
         }
+
+        self.dma_threshold = threshold;
+        Ok(threshold)
     }
 
-    async unsafe fn xfer_impl(&mut self) {
-        // Start listen for rx dma transfer to complete.
-        // Rx completion is guaranteed to always happen after tx has completed.
-        let dma_isr_tcif = self.dma_rx.dma_isr_tcif;
-        let dma_ifcr_ctcif = self.dma_rx.dma_ifcr_ctcif;
-        let dma_rx_tc = self.dma_rx_int.add_future(fib::new_fn(move || {
-            if dma_isr_tcif.read_bit() {
-                // Clear transfer completed interrupt flag.
-                dma_ifcr_ctcif.set_bit();
-                fib::Complete(())
-            } else {
-                fib::Yielded(())
-            }
-        }));
+    /// Change the spi mode, e.g. before selecting a chip that requires another mode than the previous one.
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        self.spi.set_mode(mode);
+    }
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    pub fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        self.spi.configure(prescaler, mode, first_bit);
+    }
+
+    /// The current spi mode.
+    pub fn mode(&self) -> SpiMode {
+        self.spi.mode()
+    }
+
+    /// The configured data frame.
+    pub fn data_frame(&self) -> DataFrame {
+        self.spi.data_frame()
+    }
+
+    fn bypass_dma(&self, len: usize) -> bool {
+        len < self.dma_threshold && !self.spi.crc_enabled()
+    }
+
+    pub(crate) fn wait_for_idle(&self) {
+        self.spi.wait_for_idle();
+    }
+
+    async unsafe fn xfer_impl(&mut self) -> Result<(), SpiError> {
+        let crc = self.spi.crc_enabled();
+        if crc {
+            self.spi.reset_crc();
+        }
+        self.errors.clear();
+
+        // Start listen for both dma transfers to complete.
+        // Rx completion is guaranteed to always happen after tx has completed,
+        // but an error may occur on either stream.
+        let dma_rx_tc = self.dma_rx.transfer_future(self.dma_rx_int);
+        let dma_tx_tc = self.dma_tx.transfer_future(self.dma_tx_int);
 
         // Clear any outstanding fifo error interrupt flag by settings its clear register.
         self.dma_rx.dma_ifcr_cfeif.set_bit();
@@ -167,8 +302,44 @@ impl<
             r.txdmaen().set(v);
         });
 
-        // Wait for DMA transfer to complete.
-        dma_rx_tc.await;
+        // Wait for DMA transfer to complete, or for an overrun or a mode fault that stops it.
+        let errors = Arc::clone(&self.errors);
+        let dma_tc = future::select(dma_rx_tc, dma_tx_tc);
+        let result = match future::select(dma_tc, errors.wait()).await {
+            Either::Left((Either::Left((Ok(()), dma_tx_tc)), _)) => dma_tx_tc.await.map_err(SpiError::from),
+            Either::Left((Either::Right((Ok(()), dma_rx_tc)), _)) => dma_rx_tc.await.map_err(SpiError::from),
+            Either::Left((Either::Left((Err(err), dma_tx_tc)), _)) => {
+                // Disabling the other stream sets its transfer complete flag without clearing it,
+                // so its fiber completes and does not linger to consume the next transfer's flag.
+                self.dma_tx.abort();
+                let _ = dma_tx_tc.await;
+                Err(err.into())
+            }
+            Either::Left((Either::Right((Err(err), dma_rx_tc)), _)) => {
+                self.dma_rx.abort();
+                let _ = dma_rx_tc.await;
+                Err(err.into())
+            }
+            Either::Right(((), dma_tc)) => {
+                // Disable both streams, so that both fibers complete.
+                self.dma_rx.abort();
+                self.dma_tx.abort();
+                match dma_tc.await {
+                    Either::Left((_, dma_tc)) | Either::Right((_, dma_tc)) => {
+                        let _ = dma_tc.await;
+                    }
+                }
+                self.errors.take()
+            }
+        };
+        // An error may also be reported while the streams complete.
+        let result = result.and_then(|()| self.errors.take());
+
+        if result.is_err() {
+            // Both fibers have completed, so the remaining flags can be cleared.
+            self.dma_rx.reset();
+            self.dma_tx.reset();
+        }
 
         // The peripheral automatically disables the DMA stream on completion without error,
         // but it does not clear the RXDMAEN/TXDMAEN flag in CR2.
@@ -178,6 +349,115 @@ impl<
             r.rxdmaen().clear(v);
             r.txdmaen().clear(v);
         });
+
+        if result.is_err() {
+            // Wait for any ongoing frame, and discard any received data.
+            // Reading DR followed by SR also clears an overrun (OVR) condition, see RM0090 §28.4.8.
+            while self.spi.spi_sr.bsy().read_bit() {}
+            self.spi.spi_dr.load_val();
+            self.spi.spi_sr.load_val();
+        }
+
+        if let Err(SpiError::ModeFault) = result {
+            // The mode fault has disabled the spi.
+            self.spi.enable_master();
+        }
+
+        result?;
+
+        if crc {
+            // The crc is received after the last frame, and must be read to clear RXNE, see RM0090 §28.3.6.
+            while !self.spi.spi_sr.rxne().read_bit() {}
+            self.spi.spi_dr.load_val();
+            if self.spi.spi_sr.crcerr().read_bit() {
+                self.spi.spi_sr.crcerr().clear_bit();
+                return Err(SpiError::Crc);
+            }
+            // The crc error may already have been cleared by the error interrupt.
+            if self.errors.take_crc() {
+                return Err(SpiError::Crc);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SpiErrors {
+    /// Discard the errors of a previous transfer.
+    pub(crate) fn clear(&self) {
+        self.crc.store(false, Ordering::Relaxed);
+        self.overrun.store(false, Ordering::Relaxed);
+        self.mode_fault.store(false, Ordering::Relaxed);
+    }
+
+    /// Report a crc mismatch, which is returned after the transfer.
+    pub(crate) fn report_crc(&self) {
+        self.crc.store(true, Ordering::Release);
+    }
+
+    /// Report an overrun, which stops the transfer.
+    pub(crate) fn report_overrun(&self) {
+        self.overrun.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Report a mode fault, which stops the transfer.
+    pub(crate) fn report_mode_fault(&self) {
+        self.mode_fault.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Take a reported crc mismatch.
+    pub(crate) fn take_crc(&self) -> bool {
+        self.crc.swap(false, Ordering::Acquire)
+    }
+
+    /// Take a reported overrun or mode fault.
+    pub(crate) fn take(&self) -> Result<(), SpiError> {
+        if self.mode_fault.swap(false, Ordering::Acquire) {
+            Err(SpiError::ModeFault)
+        } else if self.overrun.swap(false, Ordering::Acquire) {
+            Err(SpiError::Overrun)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait for an overrun or a mode fault to be reported.
+    pub(crate) fn wait(&self) -> impl Future<Output = ()> + Unpin + '_ {
+        future::poll_fn(move |cx| {
+            self.waker.register(cx.waker());
+            if self.overrun.load(Ordering::Acquire) || self.mode_fault.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl From<DmaError> for SpiError {
+    fn from(err: DmaError) -> Self {
+        SpiError::Dma(err)
+    }
+}
+
+/// Adjust the dma transfer to the data frame, i.e. the peripheral data size must match the data frame.
+/// In direct mode the memory data size is equal to the peripheral data size.
+///
+/// The memory data size must also match in fifo mode,
+/// as the streams count buffers and dummy transfers in frames.
+pub(crate) fn frame_transfer(data_frame: DataFrame, transfer: DmaTransfer) -> DmaTransfer {
+    let size = data_frame.dma_size();
+    if transfer.fifo_threshold().is_none() {
+        DmaTransfer::direct(size)
+    } else {
+        assert!(
+            transfer.psize() == size && transfer.msize() == size,
+            "The dma peripheral and memory data sizes do not match the data frame"
+        );
+        transfer
     }
 }
 
@@ -185,11 +465,6 @@ impl<Spi: SpiMap, DmaRx: DmaChMap, DmaRxInt: IntToken, DmaTx: DmaChMap, DmaTxInt
     for SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>
 {
     fn drop(&mut self) {
-        self.wait_for_idle();
-
-        self.spi.spi_cr1.modify_reg(|r, v| {
-            // Disable spi.
-            r.spe().clear(v);
-        });
+        self.spi.disable();
     }
 }
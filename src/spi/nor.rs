@@ -0,0 +1,718 @@
+use crate::{
+    chipctrl::{SpiChip, SpiLink, SpiMasterLink},
+    SpiError, SpiMasterDrv,
+};
+use core::convert::TryInto;
+use displaydoc::Display;
+use drone_cortexm::thr::prelude::*;
+use drone_stm32_map::periph::{dma::ch::DmaChMap, gpio::pin::GpioPinMap, spi::SpiMap};
+use drone_stm32f4_gpio_drv::prelude::*;
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const PAGE_PROGRAM: u8 = 0x02;
+const FAST_READ: u8 = 0x0B;
+const READ_SFDP: u8 = 0x5A;
+const READ_JEDEC_ID: u8 = 0x9F;
+const CHIP_ERASE: u8 = 0xC7;
+const DEEP_POWER_DOWN: u8 = 0xB9;
+const RELEASE_POWER_DOWN: u8 = 0xAB;
+
+/// Write in progress (WIP) bit in the status register.
+const STATUS_WIP: u8 = 1 << 0;
+
+/// The largest number of bytes in a single dma transfer.
+const MAX_TRANSFER: usize = 0xFFFF;
+
+/// The number of JEDEC identification attempts before the flash is considered absent.
+const ID_ATTEMPTS: usize = 16;
+
+/// The largest flash size that is addressable with 3-byte addresses.
+const MAX_CAPACITY: u32 = 1 << 24;
+
+/// Nor flash error.
+#[derive(Display, Debug)]
+pub enum NorError {
+    /// Spi error: {0}
+    Spi(SpiError),
+    /// The address range is outside the flash, or the address is not aligned to the erase size.
+    OutOfRange,
+    /// No flash responded to the JEDEC identification.
+    NotFound,
+}
+
+/// The JEDEC manufacturer and device identification.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+/// The flash geometry, discovered from the sfdp basic flash parameter table if available.
+#[derive(Copy, Clone, Debug)]
+pub struct NorGeometry {
+    /// The flash size in bytes.
+    pub capacity: u32,
+    /// The page program size in bytes.
+    pub page_size: u32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum NorErase {
+    /// 4KiB sector erase.
+    Sector4K,
+    /// 32KiB block erase.
+    Block32K,
+    /// 64KiB block erase.
+    Block64K,
+}
+
+impl NorErase {
+    /// The erase size in bytes.
+    pub const fn size(self) -> u32 {
+        match self {
+            NorErase::Sector4K => 4 * 1024,
+            NorErase::Block32K => 32 * 1024,
+            NorErase::Block64K => 64 * 1024,
+        }
+    }
+
+    fn opcode(self) -> u8 {
+        match self {
+            NorErase::Sector4K => 0x20,
+            NorErase::Block32K => 0x52,
+            NorErase::Block64K => 0xD8,
+        }
+    }
+}
+
+/// JEDEC spi nor flash driver, e.g. for W25Qxx and MX25 devices.
+/// Only 3-byte addressing is supported, i.e. the first 16MiB of larger devices.
+pub struct NorFlash<Link: SpiLink> {
+    link: Link,
+    jedec_id: JedecId,
+    geometry: NorGeometry,
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        CsPin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > NorFlash<SpiMasterLink<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, CsPin, PinType, PinPull>>
+{
+    /// Identify the flash on the spi master selected by `chip`, which must be a dedicated master for the flash.
+    /// The geometry is read from the sfdp tables, or derived from the JEDEC capacity code if sfdp is not supported.
+    pub async fn init(
+        spi: SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+        chip: SpiChip<CsPin, PinType, PinPull>,
+    ) -> Result<Self, NorError> {
+        Self::with_link(SpiMasterLink::new(spi, chip)).await
+    }
+
+    /// Release the spi master and the chip.
+    pub fn free(
+        self,
+    ) -> (
+        SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+        SpiChip<CsPin, PinType, PinPull>,
+    ) {
+        self.link.free()
+    }
+}
+
+impl<Link: SpiLink> NorFlash<Link> {
+    /// Identify the flash on `link`.
+    /// The geometry is read from the sfdp tables, or derived from the JEDEC capacity code if sfdp is not supported.
+    pub async fn with_link(link: Link) -> Result<Self, NorError> {
+        let mut flash = Self {
+            link,
+            jedec_id: JedecId {
+                manufacturer: 0,
+                memory_type: 0,
+                capacity: 0,
+            },
+            geometry: NorGeometry {
+                capacity: 0,
+                page_size: 256,
+            },
+        };
+
+        // The flash may be in deep power-down after a reset of the mcu,
+        // in which case it does not respond until tRES1 after the release.
+        flash.release_power_down().await?;
+
+        let mut id = [0; 3];
+        for _ in 0..ID_ATTEMPTS {
+            flash.command_read(&[READ_JEDEC_ID], &mut id).await?;
+            if id[0] != 0x00 && id[0] != 0xFF {
+                break;
+            }
+        }
+        if id[0] == 0x00 || id[0] == 0xFF {
+            return Err(NorError::NotFound);
+        }
+        flash.jedec_id = JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        };
+
+        flash.geometry = match flash.read_sfdp_geometry().await? {
+            Some(geometry) => geometry,
+            None => NorGeometry {
+                capacity: 1u32.checked_shl(id[2].into()).unwrap_or(MAX_CAPACITY),
+                page_size: 256,
+            },
+        };
+        flash.geometry.capacity = flash.geometry.capacity.min(MAX_CAPACITY);
+
+        Ok(flash)
+    }
+
+    /// The JEDEC identification of the flash.
+    pub fn jedec_id(&self) -> JedecId {
+        self.jedec_id
+    }
+
+    /// The flash geometry.
+    pub fn geometry(&self) -> NorGeometry {
+        self.geometry
+    }
+
+    /// Read `buf.len()` bytes starting at `addr` using the fast read command.
+    pub async fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), NorError> {
+        self.check_range(addr, buf.len())?;
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        self.command_read(&[FAST_READ, a2, a1, a0, 0], buf).await
+    }
+
+    /// Program `data` starting at `addr`, where the data is split at page boundaries.
+    /// The range must be erased before it is programmed.
+    pub async fn program(&mut self, addr: u32, data: &[u8]) -> Result<(), NorError> {
+        self.check_range(addr, data.len())?;
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let page_remaining = (self.geometry.page_size - addr % self.geometry.page_size) as usize;
+            let (chunk, rest) = data.split_at(data.len().min(page_remaining));
+
+            self.write_enable().await?;
+            let [_, a2, a1, a0] = addr.to_be_bytes();
+            self.command_write(&[PAGE_PROGRAM, a2, a1, a0], chunk).await?;
+            self.wait_ready().await?;
+
+            addr += chunk.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Erase the sector or block at `addr`, which must be aligned to the erase size.
+    pub async fn erase(&mut self, erase: NorErase, addr: u32) -> Result<(), NorError> {
+        if addr % erase.size() != 0 {
+            return Err(NorError::OutOfRange);
+        }
+        self.check_range(addr, erase.size() as usize)?;
+
+        self.write_enable().await?;
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        self.command(&[erase.opcode(), a2, a1, a0]).await?;
+        self.wait_ready().await
+    }
+
+    /// Erase the entire flash.
+    pub async fn erase_chip(&mut self) -> Result<(), NorError> {
+        self.write_enable().await?;
+        self.command(&[CHIP_ERASE]).await?;
+        self.wait_ready().await
+    }
+
+    /// Enter deep power-down, where all commands except release from deep power-down are ignored.
+    pub async fn deep_power_down(&mut self) -> Result<(), NorError> {
+        self.command(&[DEEP_POWER_DOWN]).await
+    }
+
+    /// Release from deep power-down.
+    /// The flash accepts new commands after tRES1, which is typically 3µs.
+    pub async fn release_power_down(&mut self) -> Result<(), NorError> {
+        self.command(&[RELEASE_POWER_DOWN]).await
+    }
+
+    /// Wait for any program or erase operation to complete by polling the write in progress bit.
+    pub async fn wait_ready(&mut self) -> Result<(), NorError> {
+        self.link.select();
+        let result = async {
+            self.link.write(&[READ_STATUS]).await?;
+
+            // The status register is output continuously while the chip is selected.
+            let mut status = [0];
+            loop {
+                self.link.read(&mut status).await?;
+                if status[0] & STATUS_WIP == 0 {
+                    return Ok::<(), SpiError>(());
+                }
+            }
+        }
+        .await;
+        self.link.deselect();
+        Ok(result?)
+    }
+
+    async fn read_sfdp_geometry(&mut self) -> Result<Option<NorGeometry>, NorError> {
+        // Sfdp header and the first parameter header, which is the basic flash parameter table, see JESD216.
+        let mut header = [0; 16];
+        self.read_sfdp(0, &mut header).await?;
+        if &header[0..4] != b"SFDP" || header[8] != 0x00 || header[15] != 0xFF {
+            return Ok(None);
+        }
+        let len = usize::from(header[11]).min(11);
+        let ptr = u32::from_le_bytes([header[12], header[13], header[14], 0]);
+        if len < 2 {
+            return Ok(None);
+        }
+
+        let mut table = [0; 11 * 4];
+        self.read_sfdp(ptr, &mut table[..len * 4]).await?;
+        let dword = |i: usize| u32::from_le_bytes(table[i * 4..i * 4 + 4].try_into().unwrap());
+
+        // The 2nd dword is the flash density in bits.
+        let density = dword(1);
+        let capacity_bits = if density & (1 << 31) == 0 {
+            u64::from(density) + 1
+        } else {
+            1u64.checked_shl(density & 0x7FFF_FFFF).unwrap_or(u64::MAX)
+        };
+        let capacity = (capacity_bits / 8).min(MAX_CAPACITY.into()) as u32;
+
+        // The 11th dword holds the page size as a power of two (JESD216A and later).
+        let page_size = if len >= 11 {
+            1 << ((dword(10) >> 4) & 0xF)
+        } else {
+            256
+        };
+
+        Ok(Some(NorGeometry {
+            capacity,
+            page_size,
+        }))
+    }
+
+    async fn read_sfdp(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), NorError> {
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        self.command_read(&[READ_SFDP, a2, a1, a0, 0], buf).await
+    }
+
+    async fn write_enable(&mut self) -> Result<(), NorError> {
+        self.command(&[WRITE_ENABLE]).await
+    }
+
+    async fn command(&mut self, cmd: &[u8]) -> Result<(), NorError> {
+        self.link.select();
+        let result = self.link.write(cmd).await;
+        self.link.deselect();
+        Ok(result?)
+    }
+
+    async fn command_read(&mut self, cmd: &[u8], buf: &mut [u8]) -> Result<(), NorError> {
+        self.link.select();
+        let result = async {
+            self.link.write(cmd).await?;
+            for chunk in buf.chunks_mut(MAX_TRANSFER) {
+                self.link.read(chunk).await?;
+            }
+            Ok::<(), SpiError>(())
+        }
+        .await;
+        self.link.deselect();
+        Ok(result?)
+    }
+
+    async fn command_write(&mut self, cmd: &[u8], data: &[u8]) -> Result<(), NorError> {
+        self.link.select();
+        let result = async {
+            self.link.write(cmd).await?;
+            self.link.write(data).await
+        }
+        .await;
+        self.link.deselect();
+        Ok(result?)
+    }
+
+    fn check_range(&self, addr: u32, len: usize) -> Result<(), NorError> {
+        if u64::from(addr) + len as u64 > u64::from(self.geometry.capacity) {
+            Err(NorError::OutOfRange)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl From<SpiError> for NorError {
+    fn from(err: SpiError) -> Self {
+        NorError::Spi(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chipctrl::block_on, FirstBit, Prescaler, SpiMode};
+    use alloc::{boxed::Box, vec, vec::Vec};
+    use core::{future::Future, pin::Pin};
+    use futures::future;
+
+    /// The number of status reads with the write in progress bit set after a page program.
+    const PROGRAM_POLLS: u32 = 2;
+    /// The number of status reads with the write in progress bit set after an erase.
+    const ERASE_POLLS: u32 = 5;
+
+    /// A simulated JEDEC flash, which executes the commands when it is deselected.
+    struct SimFlash {
+        mem: Vec<u8>,
+        sfdp: Vec<u8>,
+        jedec_id: [u8; 3],
+        page_size: usize,
+        /// The bytes written since the flash was selected.
+        cmd: Vec<u8>,
+        /// The bytes read since the flash was selected.
+        read_pos: usize,
+        write_enabled: bool,
+        /// The remaining status reads with the write in progress bit set.
+        busy: u32,
+        powered_down: bool,
+        /// The address and length of the page programs.
+        programs: Vec<(u32, usize)>,
+        status_reads: usize,
+    }
+
+    impl SimFlash {
+        fn new(capacity: usize, sfdp: Vec<u8>) -> Self {
+            Self {
+                mem: vec![0xFF; capacity],
+                sfdp,
+                jedec_id: [0xEF, 0x40, 0x11],
+                page_size: 256,
+                cmd: Vec::new(),
+                read_pos: 0,
+                write_enabled: false,
+                busy: 0,
+                powered_down: false,
+                programs: Vec::new(),
+                status_reads: 0,
+            }
+        }
+
+        fn addr(&self) -> usize {
+            usize::from(self.cmd[1]) << 16 | usize::from(self.cmd[2]) << 8 | usize::from(self.cmd[3])
+        }
+
+        /// The length of the command including address and dummy bytes, if the command has a response.
+        fn cmd_len(&self) -> Option<usize> {
+            match self.cmd.first() {
+                Some(&READ_STATUS) | Some(&READ_JEDEC_ID) => Some(1),
+                Some(&FAST_READ) | Some(&READ_SFDP) => Some(5),
+                _ => None,
+            }
+        }
+
+        fn output(&mut self) -> u8 {
+            let pos = self.read_pos;
+            self.read_pos += 1;
+            if self.powered_down {
+                return 0xFF;
+            }
+            match self.cmd[0] {
+                READ_STATUS => {
+                    self.status_reads += 1;
+                    if self.busy > 0 {
+                        self.busy -= 1;
+                        STATUS_WIP
+                    } else {
+                        0
+                    }
+                }
+                // The flash ignores all other commands while busy.
+                _ if self.busy > 0 => 0xFF,
+                READ_JEDEC_ID => self.jedec_id.get(pos).copied().unwrap_or(0xFF),
+                FAST_READ => self.mem[(self.addr() + pos) % self.mem.len()],
+                READ_SFDP => self.sfdp.get(self.addr() + pos).copied().unwrap_or(0xFF),
+                _ => 0xFF,
+            }
+        }
+
+        fn execute(&mut self) {
+            if self.powered_down {
+                if self.cmd == [RELEASE_POWER_DOWN] {
+                    self.powered_down = false;
+                }
+                return;
+            }
+            if self.cmd.is_empty() || self.busy > 0 {
+                return;
+            }
+            match self.cmd[0] {
+                WRITE_ENABLE => self.write_enabled = true,
+                PAGE_PROGRAM if self.write_enabled => {
+                    let addr = self.addr();
+                    let page = addr - addr % self.page_size;
+                    let data = &self.cmd[4..];
+                    for (i, &byte) in data.iter().enumerate() {
+                        // The address wraps to the start of the page at the page boundary.
+                        self.mem[page + (addr - page + i) % self.page_size] &= byte;
+                    }
+                    self.programs.push((addr as u32, data.len()));
+                    self.busy = PROGRAM_POLLS;
+                    self.write_enabled = false;
+                }
+                opcode @ 0x20 | opcode @ 0x52 | opcode @ 0xD8 if self.write_enabled => {
+                    let size = match opcode {
+                        0x20 => NorErase::Sector4K,
+                        0x52 => NorErase::Block32K,
+                        _ => NorErase::Block64K,
+                    }
+                    .size() as usize;
+                    let start = self.addr() - self.addr() % size;
+                    self.mem[start..start + size].iter_mut().for_each(|byte| *byte = 0xFF);
+                    self.busy = ERASE_POLLS;
+                    self.write_enabled = false;
+                }
+                CHIP_ERASE if self.write_enabled => {
+                    self.mem.iter_mut().for_each(|byte| *byte = 0xFF);
+                    self.busy = ERASE_POLLS;
+                    self.write_enabled = false;
+                }
+                DEEP_POWER_DOWN => self.powered_down = true,
+                _ => {}
+            }
+        }
+    }
+
+    impl SpiLink for SimFlash {
+        fn select(&mut self) {
+            self.cmd.clear();
+            self.read_pos = 0;
+        }
+
+        fn deselect(&mut self) {
+            self.execute();
+        }
+
+        fn configure(&mut self, _prescaler: Prescaler, _mode: SpiMode, _first_bit: FirstBit) {}
+
+        fn write<'a>(&'a mut self, buf: &'a [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            self.cmd.extend_from_slice(buf);
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            for byte in buf {
+                *byte = self.output();
+            }
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn xfer<'a>(
+            &'a mut self,
+            tx_buf: &'a [u8],
+            rx_buf: &'a mut [u8],
+        ) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            for (&tx, rx) in tx_buf.iter().zip(rx_buf.iter_mut()) {
+                *rx = match self.cmd_len() {
+                    // The command is complete, so the flash drives its response.
+                    Some(len) if self.cmd.len() >= len => self.output(),
+                    _ => {
+                        // The output is floating while the command is sent.
+                        self.cmd.push(tx);
+                        0xFF
+                    }
+                };
+            }
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    /// Sfdp tables with the basic flash parameter table `dwords` at 0x30.
+    fn sfdp(dwords: &[u32]) -> Vec<u8> {
+        let mut sfdp = vec![0xFF; 0x30];
+        sfdp[..8].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x06, 0x01, 0x00, 0xFF]);
+        sfdp[8..16].copy_from_slice(&[0x00, 0x06, 0x01, dwords.len() as u8, 0x30, 0x00, 0x00, 0xFF]);
+        for dword in dwords {
+            sfdp.extend_from_slice(&dword.to_le_bytes());
+        }
+        sfdp
+    }
+
+    /// A JESD216A basic flash parameter table with the flash `density` and a page size of 2^`page_shift`.
+    fn bfpt(density: u32, page_shift: u32) -> Vec<u8> {
+        let mut dwords = [0; 11];
+        dwords[1] = density;
+        dwords[10] = page_shift << 4;
+        sfdp(&dwords)
+    }
+
+    /// A 1Mbit flash.
+    fn sim_flash() -> SimFlash {
+        SimFlash::new(128 * 1024, bfpt(0x000F_FFFF, 8))
+    }
+
+    fn init(sim: SimFlash) -> NorFlash<SimFlash> {
+        block_on(NorFlash::with_link(sim)).unwrap()
+    }
+
+    #[test]
+    fn geometry_from_sfdp() {
+        let flash = init(sim_flash());
+        assert_eq!(
+            flash.jedec_id(),
+            JedecId {
+                manufacturer: 0xEF,
+                memory_type: 0x40,
+                capacity: 0x11,
+            }
+        );
+        assert_eq!(flash.geometry().capacity, 128 * 1024);
+        assert_eq!(flash.geometry().page_size, 256);
+    }
+
+    #[test]
+    fn sfdp_page_size() {
+        let flash = init(SimFlash::new(128 * 1024, bfpt(0x000F_FFFF, 9)));
+        assert_eq!(flash.geometry().page_size, 512);
+    }
+
+    #[test]
+    fn sfdp_without_page_size() {
+        // JESD216 tables have 9 dwords and no page size.
+        let flash = init(SimFlash::new(128 * 1024, sfdp(&[0, 0x00FF_FFFF, 0, 0, 0, 0, 0, 0, 0])));
+        assert_eq!(flash.geometry().capacity, 2 * 1024 * 1024);
+        assert_eq!(flash.geometry().page_size, 256);
+    }
+
+    #[test]
+    fn sfdp_power_of_two_density() {
+        // 2^33 bits is limited to the 3-byte address range.
+        let flash = init(SimFlash::new(128 * 1024, bfpt(0x8000_0021, 8)));
+        assert_eq!(flash.geometry().capacity, MAX_CAPACITY);
+    }
+
+    #[test]
+    fn no_sfdp() {
+        let flash = init(SimFlash::new(128 * 1024, Vec::new()));
+        assert_eq!(flash.geometry().capacity, 1 << 0x11);
+        assert_eq!(flash.geometry().page_size, 256);
+    }
+
+    #[test]
+    fn not_found() {
+        let mut sim = sim_flash();
+        sim.jedec_id = [0xFF; 3];
+        assert!(matches!(block_on(NorFlash::with_link(sim)), Err(NorError::NotFound)));
+    }
+
+    #[test]
+    fn program_splits_at_page_boundaries() {
+        let mut flash = init(sim_flash());
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        block_on(flash.program(0x1F0, &data)).unwrap();
+        assert_eq!(
+            flash.link.programs,
+            [(0x1F0, 16), (0x200, 256), (0x300, 256), (0x400, 72)]
+        );
+
+        let mut buf = vec![0; 600];
+        block_on(flash.read(0x1F0, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn program_out_of_range() {
+        let mut flash = init(sim_flash());
+        assert!(matches!(
+            block_on(flash.program(128 * 1024 - 1, &[0, 0])),
+            Err(NorError::OutOfRange)
+        ));
+        assert!(flash.link.programs.is_empty());
+    }
+
+    #[test]
+    fn erase_polls_write_in_progress() {
+        let mut flash = init(sim_flash());
+        block_on(flash.program(0x0FFF, &[0x00, 0x00])).unwrap();
+
+        flash.link.status_reads = 0;
+        block_on(flash.erase(NorErase::Sector4K, 0x1000)).unwrap();
+        assert_eq!(flash.link.status_reads, ERASE_POLLS as usize + 1);
+        assert_eq!(flash.link.busy, 0);
+
+        // The next sector is erased, and the previous sector is not.
+        let mut buf = [0; 2];
+        block_on(flash.read(0x0FFF, &mut buf)).unwrap();
+        assert_eq!(buf, [0x00, 0xFF]);
+
+        // The flash accepts the next program, as the erase has completed.
+        block_on(flash.program(0x1000, &[0x5A])).unwrap();
+        block_on(flash.read(0x1000, &mut buf)).unwrap();
+        assert_eq!(buf, [0x5A, 0xFF]);
+    }
+
+    #[test]
+    fn erase_unaligned() {
+        let mut flash = init(sim_flash());
+        assert!(matches!(
+            block_on(flash.erase(NorErase::Block32K, 0x1000)),
+            Err(NorError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn erase_chip() {
+        let mut flash = init(sim_flash());
+        block_on(flash.program(0, &[0; 4])).unwrap();
+        block_on(flash.erase_chip()).unwrap();
+        assert_eq!(flash.link.busy, 0);
+        assert!(flash.link.mem.iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn deep_power_down_and_release() {
+        let mut flash = init(sim_flash());
+        block_on(flash.program(0, &[0x12, 0x34])).unwrap();
+
+        block_on(flash.deep_power_down()).unwrap();
+        assert!(flash.link.powered_down);
+
+        // The flash ignores the read in deep power-down.
+        let mut buf = [0; 2];
+        block_on(flash.read(0, &mut buf)).unwrap();
+        assert_eq!(buf, [0xFF, 0xFF]);
+
+        block_on(flash.release_power_down()).unwrap();
+        assert!(!flash.link.powered_down);
+        block_on(flash.read(0, &mut buf)).unwrap();
+        assert_eq!(buf, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn init_releases_deep_power_down() {
+        let mut sim = sim_flash();
+        sim.powered_down = true;
+        let flash = init(sim);
+        assert_eq!(flash.geometry().capacity, 128 * 1024);
+    }
+
+    #[test]
+    fn sim_flash_full_duplex() {
+        let mut sim = sim_flash();
+        let mut rx = [0; 4];
+        sim.select();
+        block_on(sim.xfer(&[READ_JEDEC_ID, 0, 0, 0], &mut rx)).unwrap();
+        sim.deselect();
+        assert_eq!(rx, [0xFF, 0xEF, 0x40, 0x11]);
+    }
+}
@@ -28,6 +28,71 @@ impl<Spi: SpiMap> Default for SpiPins<Spi, Undefined, Undefined, Undefined> {
     }
 }
 
+/// Whether an optional pin is defined.
+pub trait PinState {
+    const DEFINED: bool;
+}
+
+impl PinState for Defined {
+    const DEFINED: bool = true;
+}
+
+impl PinState for Undefined {
+    const DEFINED: bool = false;
+}
+
+/// The pins of a spi peripheral in i2s mode.
+/// The extension serial data (`ext_sd`) pin is only required for full duplex,
+/// and the master clock (`mck`) pin is only required for master clock output.
+pub struct I2sPins<Spi: SpiMap, Ck, Ws, Sd, ExtSd, Mck> {
+    spi: PhantomData<Spi>,
+    ck: PhantomData<Ck>,
+    ws: PhantomData<Ws>,
+    sd: PhantomData<Sd>,
+    ext_sd: PhantomData<ExtSd>,
+    mck: PhantomData<Mck>,
+}
+
+impl<Spi: SpiMap, Ck, Ws, Sd, ExtSd, Mck> I2sPins<Spi, Ck, Ws, Sd, ExtSd, Mck> {
+    pub fn new() -> I2sPins<Spi, Ck, Ws, Sd, ExtSd, Mck> {
+        Self {
+            spi: PhantomData,
+            ck: PhantomData,
+            ws: PhantomData,
+            sd: PhantomData,
+            ext_sd: PhantomData,
+            mck: PhantomData,
+        }
+    }
+}
+
+impl<Spi: SpiMap> Default for I2sPins<Spi, Undefined, Undefined, Undefined, Undefined, Undefined> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pin that can be used as the hardware slave select (NSS) input of the spi peripheral.
+pub trait NssPin<Spi: SpiMap> {}
+
+#[macro_export]
+macro_rules! nss_pin {
+    ($spi:ident, $pin:ident, $af:ident) => {
+        impl<
+                PinType: drone_stm32f4_gpio_drv::PinTypeMap,
+                PinPull: drone_stm32f4_gpio_drv::PinPullMap,
+            > crate::pins::NssPin<$spi>
+            for drone_stm32f4_gpio_drv::GpioPin<
+                $pin,
+                drone_stm32f4_gpio_drv::AlternateMode<$af>,
+                PinType,
+                PinPull,
+            >
+        {
+        }
+    };
+}
+
 pub mod traits {
     use super::*;
     use drone_stm32f4_gpio_drv::pin_ext;
@@ -35,4 +100,10 @@ pub mod traits {
     pin_ext!(SckPinExt<Spi: SpiMap, ..., Sck, Miso, Mosi>.sck -> SpiPins<Spi, Defined, Miso, Mosi>);
     pin_ext!(MisoPinExt<Spi: SpiMap, ..., Sck, Miso, Mosi>.miso -> SpiPins<Spi, Sck, Defined, Mosi>);
     pin_ext!(MosiPinExt<Spi: SpiMap, ..., Sck, Miso, Mosi>.mosi -> SpiPins<Spi, Sck, Miso, Defined>);
+
+    pin_ext!(CkPinExt<Spi: SpiMap, ..., Ck, Ws, Sd, ExtSd, Mck>.ck -> I2sPins<Spi, Defined, Ws, Sd, ExtSd, Mck>);
+    pin_ext!(WsPinExt<Spi: SpiMap, ..., Ck, Ws, Sd, ExtSd, Mck>.ws -> I2sPins<Spi, Ck, Defined, Sd, ExtSd, Mck>);
+    pin_ext!(SdPinExt<Spi: SpiMap, ..., Ck, Ws, Sd, ExtSd, Mck>.sd -> I2sPins<Spi, Ck, Ws, Defined, ExtSd, Mck>);
+    pin_ext!(ExtSdPinExt<Spi: SpiMap, ..., Ck, Ws, Sd, ExtSd, Mck>.ext_sd -> I2sPins<Spi, Ck, Ws, Sd, Defined, Mck>);
+    pin_ext!(MckPinExt<Spi: SpiMap, ..., Ck, Ws, Sd, ExtSd, Mck>.mck -> I2sPins<Spi, Ck, Ws, Sd, ExtSd, Defined>);
 }
@@ -0,0 +1,65 @@
+use crate::{diverged::SpiDiverged, DataFrame, FirstBit, Prescaler, SpiMode, SpiWord};
+use drone_stm32_map::periph::spi::SpiMap;
+
+/// Spi master driver without dma, where the transfers block until completed.
+/// Useful for short register accesses, or when no dma streams are available.
+pub struct SpiPolledMasterDrv<Spi: SpiMap> {
+    pub(crate) spi: SpiDiverged<Spi>,
+}
+
+impl<Spi: SpiMap> SpiPolledMasterDrv<Spi> {
+    pub(crate) fn init(spi: SpiDiverged<Spi>) -> Self {
+        let master = Self { spi };
+        master.spi.enable_master();
+        master
+    }
+
+    /// Send to the currently selected slave.
+    /// The buffer items must match the configured data frame, i.e. `u8` for 8-bit and `u16` for 16-bit frames.
+    pub fn write<Word: SpiWord>(&mut self, buf: &[Word]) {
+        self.spi.assert_frame::<Word>();
+        self.spi.write_polled(buf);
+        self.spi.wait_for_idle();
+    }
+
+    /// Read from the currently selected slave.
+    pub fn read<Word: SpiWord>(&mut self, buf: &mut [Word]) {
+        self.spi.assert_frame::<Word>();
+        self.spi.read_polled(buf);
+        self.spi.wait_for_idle();
+    }
+
+    /// Send to and receive from the currently selected slave.
+    pub fn xfer<Word: SpiWord>(&mut self, tx_buf: &[Word], rx_buf: &mut [Word]) {
+        self.spi.assert_frame::<Word>();
+        assert_eq!(tx_buf.len(), rx_buf.len());
+        self.spi.xfer_polled(tx_buf, rx_buf);
+        self.spi.wait_for_idle();
+    }
+
+    /// Change the spi mode, e.g. before selecting a chip that requires another mode than the previous one.
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        self.spi.set_mode(mode);
+    }
+
+    /// Change the baud rate prescaler, the spi mode, and the bit transmission order.
+    pub fn configure(&mut self, prescaler: Prescaler, mode: SpiMode, first_bit: FirstBit) {
+        self.spi.configure(prescaler, mode, first_bit);
+    }
+
+    /// The current spi mode.
+    pub fn mode(&self) -> SpiMode {
+        self.spi.mode()
+    }
+
+    /// The configured data frame.
+    pub fn data_frame(&self) -> DataFrame {
+        self.spi.data_frame()
+    }
+}
+
+impl<Spi: SpiMap> Drop for SpiPolledMasterDrv<Spi> {
+    fn drop(&mut self) {
+        self.spi.disable();
+    }
+}
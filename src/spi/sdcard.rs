@@ -0,0 +1,1121 @@
+use crate::{
+    chipctrl::{SpiChip, SpiLink, SpiMasterLink},
+    FirstBit, Prescaler, SpiError, SpiMasterDrv, SpiMode,
+};
+use alloc::boxed::Box;
+use core::{future::Future, pin::Pin};
+use displaydoc::Display;
+use drone_cortexm::thr::prelude::*;
+use drone_stm32_map::periph::{dma::ch::DmaChMap, gpio::pin::GpioPinMap, spi::SpiMap};
+use drone_stm32f4_gpio_drv::prelude::*;
+
+/// The block size in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+const GO_IDLE_STATE: u8 = 0;
+const SEND_IF_COND: u8 = 8;
+const SEND_CSD: u8 = 9;
+const STOP_TRANSMISSION: u8 = 12;
+const SET_BLOCKLEN: u8 = 16;
+const READ_SINGLE_BLOCK: u8 = 17;
+const READ_MULTIPLE_BLOCK: u8 = 18;
+const WRITE_BLOCK: u8 = 24;
+const WRITE_MULTIPLE_BLOCK: u8 = 25;
+const APP_CMD: u8 = 55;
+const READ_OCR: u8 = 58;
+const CRC_ON_OFF: u8 = 59;
+const SD_SEND_OP_COND: u8 = 41;
+
+/// R1 in idle state.
+const R1_IDLE: u8 = 1 << 0;
+/// R1 illegal command.
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+
+/// Start block token for single block read/write and multiple block read.
+const TOKEN_START_BLOCK: u8 = 0xFE;
+/// Start block token for multiple block write.
+const TOKEN_START_MULTIPLE: u8 = 0xFC;
+/// Stop transmission token for multiple block write.
+const TOKEN_STOP_TRAN: u8 = 0xFD;
+
+/// The number of bytes to wait for a command response (Ncr).
+const RESPONSE_ATTEMPTS: usize = 8;
+/// The number of bytes to wait for a data token or for the card to become ready.
+const BUSY_ATTEMPTS: usize = 0x10_0000;
+/// The number of initialization (ACMD41) attempts.
+const INIT_ATTEMPTS: usize = 4000;
+
+/// The bytes sent while receiving, as the data line must be kept high.
+static FILL: [u8; BLOCK_SIZE] = [0xFF; BLOCK_SIZE];
+
+/// Sd card error.
+#[derive(Display, Debug)]
+pub enum SdError {
+    /// Spi error: {0}
+    Spi(SpiError),
+    /// The card did not respond or stayed busy.
+    Timeout,
+    /// The card rejected command {0} with the R1 status {1}.
+    Command(u8, u8),
+    /// Crc mismatch in a data block.
+    Crc,
+    /// The card returned the data error token {0}.
+    Read(u8),
+    /// The card rejected the written data with the data response {0}.
+    Write(u8),
+    /// The card is not a supported sd card.
+    Unsupported,
+    /// The block range is outside the card, or the buffer is not a multiple of the block size.
+    OutOfRange,
+}
+
+/// An async block device with 512 byte blocks, e.g. for use by a filesystem.
+pub trait BlockDevice {
+    type Error;
+
+    /// The number of blocks.
+    fn block_count(&self) -> u32;
+
+    /// Read the blocks starting at `block` into `buf`, which must be a multiple of the block size.
+    fn read_blocks<'a>(
+        &'a mut self,
+        block: u32,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + 'a>>;
+
+    /// Write `buf` to the blocks starting at `block`, where `buf` must be a multiple of the block size.
+    fn write_blocks<'a>(
+        &'a mut self,
+        block: u32,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + 'a>>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SdCardType {
+    /// Standard capacity card, version 1.
+    SdV1,
+    /// Standard capacity card, version 2.
+    SdV2,
+    /// High or extended capacity card (SDHC/SDXC), which is block addressed.
+    Sdhc,
+}
+
+/// Sd card driver over spi.
+pub struct SdCard<Link: SpiLink> {
+    bus: SdBus<Link>,
+    card_type: SdCardType,
+    block_count: u32,
+}
+
+/// The sd protocol on the spi link, for use while the card is selected.
+struct SdBus<Link: SpiLink> {
+    link: Link,
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+        CsPin: GpioPinMap,
+        PinType: PinTypeMap,
+        PinPull: PinPullMap,
+    > SdCard<SpiMasterLink<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt, CsPin, PinType, PinPull>>
+{
+    /// Initialize the card on the spi master selected by `chip`, which must be a dedicated master for the card.
+    /// The card is initialized with `init_prescaler`, which must give a baud rate of at most 400kHz,
+    /// after which the baud rate is changed to `prescaler`, which must give a baud rate of at most 25MHz.
+    pub async fn init(
+        spi: SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+        chip: SpiChip<CsPin, PinType, PinPull>,
+        init_prescaler: Prescaler,
+        prescaler: Prescaler,
+    ) -> Result<Self, SdError> {
+        Self::with_link(SpiMasterLink::new(spi, chip), init_prescaler, prescaler).await
+    }
+
+    /// Release the spi master and the chip.
+    pub fn free(
+        self,
+    ) -> (
+        SpiMasterDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>,
+        SpiChip<CsPin, PinType, PinPull>,
+    ) {
+        self.bus.link.free()
+    }
+}
+
+impl<Link: SpiLink> SdCard<Link> {
+    /// Initialize the card on `link`, with the prescalers as described for `init`.
+    pub async fn with_link(link: Link, init_prescaler: Prescaler, prescaler: Prescaler) -> Result<Self, SdError> {
+        let mut card = Self {
+            bus: SdBus { link },
+            card_type: SdCardType::SdV1,
+            block_count: 0,
+        };
+        card.bus.link.configure(init_prescaler, SpiMode::Mode0, FirstBit::Msb);
+
+        // At least 74 clock cycles with the card deselected to enter the native operating mode.
+        card.bus.link.write(&FILL[..10]).await?;
+
+        // Enter spi mode.
+        let mut r1 = 0;
+        for _ in 0..RESPONSE_ATTEMPTS {
+            r1 = card.command(GO_IDLE_STATE, 0).await?;
+            if r1 == R1_IDLE {
+                break;
+            }
+        }
+        if r1 != R1_IDLE {
+            return Err(SdError::Timeout);
+        }
+
+        // Check the voltage range and the version.
+        let mut r7 = [0; 4];
+        let r1 = card.command_read(SEND_IF_COND, 0x1AA, &mut r7).await?;
+        let v2 = r1 & R1_ILLEGAL_COMMAND == 0;
+        if v2 && (r7[2] & 0x0F != 0x01 || r7[3] != 0xAA) {
+            return Err(SdError::Unsupported);
+        }
+
+        // Enable the crc check of commands and data blocks.
+        card.command_ok(CRC_ON_OFF, 1).await?;
+
+        // Start initialization, with high capacity support (HCS) for version 2 cards.
+        let arg = if v2 { 1 << 30 } else { 0 };
+        let mut r1 = R1_IDLE;
+        for _ in 0..INIT_ATTEMPTS {
+            card.command(APP_CMD, 0).await?;
+            r1 = card.command(SD_SEND_OP_COND, arg).await?;
+            if r1 != R1_IDLE {
+                break;
+            }
+        }
+        match r1 {
+            0 => {}
+            R1_IDLE => return Err(SdError::Timeout),
+            _ if r1 & R1_ILLEGAL_COMMAND != 0 => return Err(SdError::Unsupported),
+            _ => return Err(SdError::Command(SD_SEND_OP_COND, r1)),
+        }
+
+        card.card_type = if v2 {
+            // Card capacity status (CCS) in the ocr.
+            let mut ocr = [0; 4];
+            card.command_read(READ_OCR, 0, &mut ocr).await?;
+            if ocr[0] & (1 << 6) != 0 {
+                SdCardType::Sdhc
+            } else {
+                SdCardType::SdV2
+            }
+        } else {
+            SdCardType::SdV1
+        };
+
+        if card.card_type != SdCardType::Sdhc {
+            card.command_ok(SET_BLOCKLEN, BLOCK_SIZE as u32).await?;
+        }
+
+        card.bus.link.configure(prescaler, SpiMode::Mode0, FirstBit::Msb);
+
+        let mut csd = [0; 16];
+        card.bus.link.select();
+        let result = card.bus.read_register(SEND_CSD, &mut csd).await;
+        card.bus.link.deselect();
+        result?;
+        card.release().await?;
+        card.block_count = csd_block_count(&csd);
+
+        Ok(card)
+    }
+
+    /// The card type.
+    pub fn card_type(&self) -> SdCardType {
+        self.card_type
+    }
+
+    /// The number of blocks on the card.
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    /// Read the blocks starting at `block` into `buf`, which must be a multiple of the block size.
+    pub async fn read(&mut self, block: u32, buf: &mut [u8]) -> Result<(), SdError> {
+        let count = self.check_range(block, buf.len())?;
+        let addr = self.block_addr(block);
+        self.bus.link.select();
+        let result = self.bus.read_blocks(count, addr, buf).await;
+        self.bus.link.deselect();
+        result?;
+        self.release().await
+    }
+
+    /// Write `buf` to the blocks starting at `block`, where `buf` must be a multiple of the block size.
+    pub async fn write(&mut self, block: u32, buf: &[u8]) -> Result<(), SdError> {
+        let count = self.check_range(block, buf.len())?;
+        let addr = self.block_addr(block);
+        self.bus.link.select();
+        let result = self.bus.write_blocks(count, addr, buf).await;
+        self.bus.link.deselect();
+        result?;
+        self.release().await
+    }
+
+    /// Send a command with the card selected and return the R1 status.
+    async fn command(&mut self, cmd: u8, arg: u32) -> Result<u8, SdError> {
+        self.bus.link.select();
+        let result = self.bus.command(cmd, arg).await;
+        self.bus.link.deselect();
+        let r1 = result?;
+        self.release().await?;
+        Ok(r1)
+    }
+
+    /// Send a command with the card selected, and fail if the card does not accept it.
+    async fn command_ok(&mut self, cmd: u8, arg: u32) -> Result<(), SdError> {
+        self.bus.link.select();
+        let result = self.bus.command_ok(cmd, arg).await;
+        self.bus.link.deselect();
+        result?;
+        self.release().await
+    }
+
+    /// Send a command with the card selected, and read the trailing bytes of the R3 or R7 response.
+    async fn command_read(&mut self, cmd: u8, arg: u32, buf: &mut [u8]) -> Result<u8, SdError> {
+        self.bus.link.select();
+        let result = self.bus.command_read(cmd, arg, buf).await;
+        self.bus.link.deselect();
+        let r1 = result?;
+        self.release().await?;
+        Ok(r1)
+    }
+
+    /// Clock a byte after the card is deselected, so that it releases the miso line.
+    async fn release(&mut self) -> Result<(), SdError> {
+        self.bus.link.write(&FILL[..1]).await?;
+        Ok(())
+    }
+
+    fn block_addr(&self, block: u32) -> u32 {
+        if self.card_type == SdCardType::Sdhc {
+            block
+        } else {
+            block * BLOCK_SIZE as u32
+        }
+    }
+
+    fn check_range(&self, block: u32, len: usize) -> Result<u32, SdError> {
+        let count = (len / BLOCK_SIZE) as u32;
+        if len == 0
+            || len % BLOCK_SIZE != 0
+            || u64::from(block) + u64::from(count) > u64::from(self.block_count)
+        {
+            Err(SdError::OutOfRange)
+        } else {
+            Ok(count)
+        }
+    }
+}
+
+impl<Link: SpiLink> SdBus<Link> {
+    /// Send a command and return the R1 status.
+    async fn command(&mut self, cmd: u8, arg: u32) -> Result<u8, SdError> {
+        if cmd == STOP_TRANSMISSION {
+            // The card is sending data and is not ready.
+        } else if cmd != GO_IDLE_STATE {
+            self.wait_ready().await?;
+        }
+
+        let [a3, a2, a1, a0] = arg.to_be_bytes();
+        let mut frame = [0x40 | cmd, a3, a2, a1, a0, 0];
+        frame[5] = crc7(&frame[..5]) << 1 | 1;
+        self.link.write(&frame).await?;
+
+        if cmd == STOP_TRANSMISSION {
+            // Discard the stuff byte.
+            self.read_byte().await?;
+        }
+
+        for _ in 0..RESPONSE_ATTEMPTS {
+            let r1 = self.read_byte().await?;
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+        Err(SdError::Timeout)
+    }
+
+    /// Send a command, and fail if the card does not accept it.
+    /// The card may still be in the idle state during initialization.
+    async fn command_ok(&mut self, cmd: u8, arg: u32) -> Result<(), SdError> {
+        match self.command(cmd, arg).await? {
+            r1 if r1 & !R1_IDLE == 0 => Ok(()),
+            r1 => Err(SdError::Command(cmd, r1)),
+        }
+    }
+
+    /// Send a command, and read the trailing bytes of the R3 or R7 response.
+    async fn command_read(&mut self, cmd: u8, arg: u32, buf: &mut [u8]) -> Result<u8, SdError> {
+        let r1 = self.command(cmd, arg).await?;
+        if r1 & R1_ILLEGAL_COMMAND == 0 {
+            self.read_bytes(buf).await?;
+        }
+        Ok(r1)
+    }
+
+    /// Send a command, and receive the register returned as a data block, e.g. the CSD.
+    async fn read_register(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), SdError> {
+        self.command_ok(cmd, 0).await?;
+        self.read_data(buf).await
+    }
+
+    /// Read `count` blocks starting at the card address `addr` into `buf`.
+    async fn read_blocks(&mut self, count: u32, addr: u32, buf: &mut [u8]) -> Result<(), SdError> {
+        if count == 1 {
+            self.command_ok(READ_SINGLE_BLOCK, addr).await?;
+            self.read_data(buf).await
+        } else {
+            self.command_ok(READ_MULTIPLE_BLOCK, addr).await?;
+            let mut result = Ok(());
+            for chunk in buf.chunks_mut(BLOCK_SIZE) {
+                result = self.read_data(chunk).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            // The card keeps sending blocks until it is stopped, also after a failed block.
+            let stop = self.stop_read().await;
+            result.and(stop)
+        }
+    }
+
+    /// Write `count` blocks from `buf` starting at the card address `addr`.
+    async fn write_blocks(&mut self, count: u32, addr: u32, buf: &[u8]) -> Result<(), SdError> {
+        if count == 1 {
+            self.command_ok(WRITE_BLOCK, addr).await?;
+            self.write_data(TOKEN_START_BLOCK, buf).await
+        } else {
+            self.command_ok(WRITE_MULTIPLE_BLOCK, addr).await?;
+            let mut result = Ok(());
+            for chunk in buf.chunks(BLOCK_SIZE) {
+                result = self.write_data(TOKEN_START_MULTIPLE, chunk).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            // The card keeps receiving blocks until it is stopped, also after a rejected block.
+            let stop = self.stop_write().await;
+            result.and(stop)
+        }
+    }
+
+    /// Stop a multiple block read with STOP_TRANSMISSION.
+    async fn stop_read(&mut self) -> Result<(), SdError> {
+        self.command_ok(STOP_TRANSMISSION, 0).await?;
+        self.wait_ready().await
+    }
+
+    /// Stop a multiple block write with the stop transmission token.
+    async fn stop_write(&mut self) -> Result<(), SdError> {
+        self.wait_ready().await?;
+        self.link.write(&[TOKEN_STOP_TRAN, 0xFF]).await?;
+        self.wait_ready().await
+    }
+
+    /// Receive a data block into `buf` and verify its crc.
+    async fn read_data(&mut self, buf: &mut [u8]) -> Result<(), SdError> {
+        let mut token = 0xFF;
+        for _ in 0..BUSY_ATTEMPTS {
+            token = self.read_byte().await?;
+            if token != 0xFF {
+                break;
+            }
+        }
+        match token {
+            TOKEN_START_BLOCK => {}
+            0xFF => return Err(SdError::Timeout),
+            _ => return Err(SdError::Read(token)),
+        }
+
+        self.read_bytes(buf).await?;
+        let mut crc = [0; 2];
+        self.read_bytes(&mut crc).await?;
+        if u16::from_be_bytes(crc) != crc16(buf) {
+            return Err(SdError::Crc);
+        }
+        Ok(())
+    }
+
+    /// Send a data block with its crc and wait for the card to program it.
+    async fn write_data(&mut self, token: u8, buf: &[u8]) -> Result<(), SdError> {
+        self.wait_ready().await?;
+        self.link.write(&[token]).await?;
+        self.link.write(buf).await?;
+        self.link.write(&crc16(buf).to_be_bytes()).await?;
+
+        let response = self.read_byte().await? & 0x1F;
+        match response {
+            0x05 => {}
+            0x0B => return Err(SdError::Crc),
+            _ => return Err(SdError::Write(response)),
+        }
+        self.wait_ready().await
+    }
+
+    /// Wait while the card holds the miso line low.
+    async fn wait_ready(&mut self) -> Result<(), SdError> {
+        for _ in 0..BUSY_ATTEMPTS {
+            if self.read_byte().await? == 0xFF {
+                return Ok(());
+            }
+        }
+        Err(SdError::Timeout)
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, SdError> {
+        let mut byte = [0];
+        self.read_bytes(&mut byte).await?;
+        Ok(byte[0])
+    }
+
+    async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), SdError> {
+        self.link.xfer(&FILL[..buf.len()], buf).await?;
+        Ok(())
+    }
+}
+
+impl<Link: SpiLink> BlockDevice for SdCard<Link> {
+    type Error = SdError;
+
+    fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn read_blocks<'a>(
+        &'a mut self,
+        block: u32,
+        buf: &'a mut [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), SdError>> + 'a>> {
+        Box::pin(self.read(block, buf))
+    }
+
+    fn write_blocks<'a>(
+        &'a mut self,
+        block: u32,
+        buf: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<(), SdError>> + 'a>> {
+        Box::pin(self.write(block, buf))
+    }
+}
+
+impl From<SpiError> for SdError {
+    fn from(err: SpiError) -> Self {
+        SdError::Spi(err)
+    }
+}
+
+/// The number of blocks from the card specific data (CSD) register.
+fn csd_block_count(csd: &[u8; 16]) -> u32 {
+    if csd[0] >> 6 == 1 {
+        // Csd version 2.0, where the capacity is (C_SIZE + 1) * 512KiB.
+        let c_size = u32::from(csd[7] & 0x3F) << 16 | u32::from(csd[8]) << 8 | u32::from(csd[9]);
+        (c_size + 1) * 1024
+    } else {
+        // Csd version 1.0, where the capacity is (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN.
+        let read_bl_len = u32::from(csd[5] & 0x0F);
+        let c_size = u32::from(csd[6] & 0x03) << 10 | u32::from(csd[7]) << 2 | u32::from(csd[8]) >> 6;
+        let c_size_mult = u32::from(csd[9] & 0x03) << 1 | u32::from(csd[10]) >> 7;
+        ((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u32
+    }
+}
+
+/// Crc7 of a command, with the polynomial x^7 + x^3 + 1.
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let feedback = (byte >> i ^ crc >> 6) & 1;
+            crc = crc << 1 & 0x7F;
+            if feedback != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// Crc16 of a data block, with the CCITT polynomial x^16 + x^12 + x^5 + 1.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chipctrl::block_on;
+    use alloc::{collections::VecDeque, vec, vec::Vec};
+    use futures::future;
+
+    /// The number of bytes the card holds the miso line low after a block is written.
+    const PROGRAM_BUSY: u32 = 3;
+    /// R1 crc error.
+    const R1_CRC_ERROR: u8 = 1 << 3;
+    /// R1 address error.
+    const R1_ADDRESS_ERROR: u8 = 1 << 5;
+
+    /// A simulated sd card in spi mode, which exchanges a byte for each byte clocked by the master.
+    struct SimCard {
+        /// Whether the card responds at all.
+        present: bool,
+        /// Version 2 card, i.e. supports SEND_IF_COND.
+        v2: bool,
+        /// High capacity card, i.e. block addressed.
+        sdhc: bool,
+        csd: [u8; 16],
+        mem: Vec<u8>,
+        selected: bool,
+        spi_mode: bool,
+        idle: bool,
+        app_cmd: bool,
+        crc_on: bool,
+        /// The number of ACMD41 responses with the idle bit set before the card is ready.
+        init_polls: u32,
+        /// The command being received.
+        cmd: Vec<u8>,
+        /// The bytes to output on miso.
+        out: VecDeque<u8>,
+        /// The remaining bytes with the miso line held low.
+        busy: u32,
+        /// The next block of a multiple block read.
+        read_next: Option<usize>,
+        /// The next block of a write, the data block being received, and whether it is a multiple block write.
+        write_next: Option<(usize, bool)>,
+        data: Vec<u8>,
+        /// Return the data error token instead of the block.
+        read_error: Option<(usize, u8)>,
+        /// Return a corrupt crc with the block.
+        corrupt_crc: Option<usize>,
+        /// Reject the written block with the data response.
+        write_error: Option<(usize, u8)>,
+        /// The received commands and their arguments.
+        commands: Vec<(u8, u32)>,
+        prescalers: Vec<Prescaler>,
+    }
+
+    impl SimCard {
+        /// A SDHC card with 1024 blocks.
+        fn sdhc() -> Self {
+            let mut csd = [0; 16];
+            // Csd version 2.0 with C_SIZE 0.
+            csd[0] = 0x40;
+            Self::new(true, true, csd, 1024)
+        }
+
+        /// A version 2 SDSC card with 64 blocks.
+        fn sdsc() -> Self {
+            Self::new(true, false, csd_v1(), 64)
+        }
+
+        /// A version 1 SDSC card with 64 blocks.
+        fn sd_v1() -> Self {
+            Self::new(false, false, csd_v1(), 64)
+        }
+
+        fn new(v2: bool, sdhc: bool, csd: [u8; 16], blocks: usize) -> Self {
+            Self {
+                present: true,
+                v2,
+                sdhc,
+                csd,
+                mem: vec![0; blocks * BLOCK_SIZE],
+                selected: false,
+                spi_mode: false,
+                idle: true,
+                app_cmd: false,
+                crc_on: false,
+                init_polls: 3,
+                cmd: Vec::new(),
+                out: VecDeque::new(),
+                busy: 0,
+                read_next: None,
+                write_next: None,
+                data: Vec::new(),
+                read_error: None,
+                corrupt_crc: None,
+                write_error: None,
+                commands: Vec::new(),
+                prescalers: Vec::new(),
+            }
+        }
+
+        fn exchange(&mut self, mosi: u8) -> u8 {
+            if !self.present || !self.selected {
+                return 0xFF;
+            }
+            let miso = if let Some(byte) = self.out.pop_front() {
+                byte
+            } else if self.busy > 0 {
+                self.busy -= 1;
+                0x00
+            } else if let Some(block) = self.read_next {
+                self.read_next = Some(block + 1);
+                self.send_block(block);
+                0xFF
+            } else {
+                0xFF
+            };
+
+            if let Some((block, multiple)) = self.write_next {
+                self.receive_data(mosi, block, multiple);
+            } else if !self.cmd.is_empty() || mosi & 0xC0 == 0x40 {
+                self.cmd.push(mosi);
+                if self.cmd.len() == 6 {
+                    self.execute();
+                    self.cmd.clear();
+                }
+            }
+            miso
+        }
+
+        fn r1(&self, flags: u8) -> u8 {
+            if self.idle {
+                flags | R1_IDLE
+            } else {
+                flags
+            }
+        }
+
+        fn respond(&mut self, bytes: &[u8]) {
+            // A byte before the response (Ncr).
+            self.out.push_back(0xFF);
+            self.out.extend(bytes);
+        }
+
+        fn send_data(&mut self, data: &[u8], corrupt: bool) {
+            let crc = crc16(data) ^ if corrupt { 1 } else { 0 };
+            self.out.push_back(0xFF);
+            self.out.push_back(TOKEN_START_BLOCK);
+            self.out.extend(data);
+            self.out.extend(&crc.to_be_bytes());
+        }
+
+        fn send_block(&mut self, block: usize) {
+            match self.read_error {
+                Some((error_block, token)) if error_block == block => {
+                    self.out.push_back(0xFF);
+                    self.out.push_back(token);
+                }
+                _ => {
+                    let data = self.mem[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].to_vec();
+                    self.send_data(&data, self.corrupt_crc == Some(block));
+                }
+            }
+        }
+
+        /// The block of the address argument, or `None` if the address is misaligned.
+        fn block(&self, arg: u32) -> Option<usize> {
+            if self.sdhc {
+                Some(arg as usize)
+            } else if arg as usize % BLOCK_SIZE == 0 {
+                Some(arg as usize / BLOCK_SIZE)
+            } else {
+                None
+            }
+        }
+
+        fn execute(&mut self) {
+            let cmd = self.cmd[0] & 0x3F;
+            let arg = u32::from_be_bytes([self.cmd[1], self.cmd[2], self.cmd[3], self.cmd[4]]);
+            self.commands.push((cmd, arg));
+            let app_cmd = core::mem::replace(&mut self.app_cmd, false);
+
+            // The crc is always checked for GO_IDLE_STATE and SEND_IF_COND.
+            let crc_checked = self.crc_on || cmd == GO_IDLE_STATE || cmd == SEND_IF_COND;
+            if crc_checked && self.cmd[5] != crc7(&self.cmd[..5]) << 1 | 1 {
+                let r1 = self.r1(R1_CRC_ERROR);
+                self.respond(&[r1]);
+                return;
+            }
+            if !self.spi_mode && cmd != GO_IDLE_STATE {
+                return;
+            }
+
+            match cmd {
+                GO_IDLE_STATE => {
+                    self.spi_mode = true;
+                    self.idle = true;
+                    self.respond(&[R1_IDLE]);
+                }
+                SEND_IF_COND if self.v2 => {
+                    let r1 = self.r1(0);
+                    self.respond(&[r1, 0x00, 0x00, (arg >> 8) as u8 & 0x0F, arg as u8]);
+                }
+                CRC_ON_OFF => {
+                    self.crc_on = arg & 1 != 0;
+                    let r1 = self.r1(0);
+                    self.respond(&[r1]);
+                }
+                APP_CMD => {
+                    self.app_cmd = true;
+                    let r1 = self.r1(0);
+                    self.respond(&[r1]);
+                }
+                SD_SEND_OP_COND if app_cmd => {
+                    // A high capacity card does not leave the idle state without host capacity support.
+                    if self.init_polls > 0 || (self.sdhc && arg & 1 << 30 == 0) {
+                        self.init_polls = self.init_polls.saturating_sub(1);
+                    } else {
+                        self.idle = false;
+                    }
+                    let r1 = self.r1(0);
+                    self.respond(&[r1]);
+                }
+                READ_OCR if self.v2 => {
+                    let ocr0 = if self.sdhc { 0xC0 } else { 0x80 };
+                    let r1 = self.r1(0);
+                    self.respond(&[r1, ocr0, 0xFF, 0x80, 0x00]);
+                }
+                SET_BLOCKLEN if arg as usize == BLOCK_SIZE => {
+                    let r1 = self.r1(0);
+                    self.respond(&[r1]);
+                }
+                SEND_CSD => {
+                    self.respond(&[0]);
+                    let csd = self.csd;
+                    self.send_data(&csd, false);
+                }
+                READ_SINGLE_BLOCK | READ_MULTIPLE_BLOCK | WRITE_BLOCK | WRITE_MULTIPLE_BLOCK => {
+                    let block = match self.block(arg) {
+                        Some(block) => block,
+                        None => {
+                            self.respond(&[R1_ADDRESS_ERROR]);
+                            return;
+                        }
+                    };
+                    self.respond(&[0]);
+                    match cmd {
+                        READ_SINGLE_BLOCK => self.send_block(block),
+                        READ_MULTIPLE_BLOCK => self.read_next = Some(block),
+                        WRITE_BLOCK => self.write_next = Some((block, false)),
+                        _ => self.write_next = Some((block, true)),
+                    }
+                }
+                STOP_TRANSMISSION => {
+                    self.read_next = None;
+                    self.out.clear();
+                    // The stuff byte, followed by the response and busy.
+                    self.out.push_back(0xFF);
+                    self.out.push_back(0);
+                    self.busy = PROGRAM_BUSY;
+                }
+                _ => {
+                    let r1 = self.r1(R1_ILLEGAL_COMMAND);
+                    self.respond(&[r1]);
+                }
+            }
+        }
+
+        fn receive_data(&mut self, mosi: u8, block: usize, multiple: bool) {
+            if self.data.is_empty() {
+                match mosi {
+                    TOKEN_STOP_TRAN if multiple => {
+                        self.write_next = None;
+                        self.busy = PROGRAM_BUSY;
+                    }
+                    TOKEN_START_BLOCK if !multiple => self.data.push(mosi),
+                    TOKEN_START_MULTIPLE if multiple => self.data.push(mosi),
+                    _ => {}
+                }
+                return;
+            }
+
+            self.data.push(mosi);
+            if self.data.len() < 1 + BLOCK_SIZE + 2 {
+                return;
+            }
+            let data = core::mem::take(&mut self.data);
+            let block_data = &data[1..=BLOCK_SIZE];
+            let crc = u16::from_be_bytes([data[BLOCK_SIZE + 1], data[BLOCK_SIZE + 2]]);
+            let response = match self.write_error {
+                Some((error_block, response)) if error_block == block => response,
+                _ if self.crc_on && crc != crc16(block_data) => 0x0B,
+                _ => {
+                    self.mem[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(block_data);
+                    0x05
+                }
+            };
+            self.out.push_back(0xE0 | response);
+            if response == 0x05 {
+                self.busy = PROGRAM_BUSY;
+            }
+            // A multiple block write continues until the stop transmission token.
+            self.write_next = if multiple { Some((block + 1, true)) } else { None };
+        }
+    }
+
+    impl SpiLink for SimCard {
+        fn select(&mut self) {
+            self.selected = true;
+        }
+
+        fn deselect(&mut self) {
+            self.selected = false;
+            self.out.clear();
+        }
+
+        fn configure(&mut self, prescaler: Prescaler, _mode: SpiMode, _first_bit: FirstBit) {
+            self.prescalers.push(prescaler);
+        }
+
+        fn write<'a>(&'a mut self, buf: &'a [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            for &byte in buf {
+                self.exchange(byte);
+            }
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            for byte in buf {
+                *byte = self.exchange(0x00);
+            }
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn xfer<'a>(
+            &'a mut self,
+            tx_buf: &'a [u8],
+            rx_buf: &'a mut [u8],
+        ) -> Pin<Box<dyn Future<Output = Result<(), SpiError>> + 'a>> {
+            for (&tx, rx) in tx_buf.iter().zip(rx_buf) {
+                *rx = self.exchange(tx);
+            }
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    /// A csd version 1.0 with 64 blocks, i.e. READ_BL_LEN 9, C_SIZE 15 and C_SIZE_MULT 0.
+    fn csd_v1() -> [u8; 16] {
+        let mut csd = [0; 16];
+        csd[5] = 0x09;
+        csd[7] = 15 >> 2;
+        csd[8] = (15 & 0x03) << 6;
+        csd
+    }
+
+    fn init(sim: SimCard) -> SdCard<SimCard> {
+        block_on(SdCard::with_link(sim, Prescaler::Div256, Prescaler::Div4)).unwrap()
+    }
+
+    /// The block `block` filled with a pattern.
+    fn pattern(block: usize) -> Vec<u8> {
+        (0..BLOCK_SIZE).map(|i| (i + block * 7) as u8).collect()
+    }
+
+    #[test]
+    fn crc7_of_commands() {
+        // The well-known crc bytes of CMD0, CMD8 and CMD17.
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]) << 1 | 1, 0x95);
+        assert_eq!(crc7(&[0x48, 0, 0, 0x01, 0xAA]) << 1 | 1, 0x87);
+        assert_eq!(crc7(&[0x51, 0, 0, 0, 0]) << 1 | 1, 0x55);
+    }
+
+    #[test]
+    fn crc16_of_blocks() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(crc16(&[0xFF; BLOCK_SIZE]), 0x7FA1);
+    }
+
+    #[test]
+    fn csd_capacity() {
+        assert_eq!(csd_block_count(&csd_v1()), 64);
+        let mut csd = [0; 16];
+        csd[0] = 0x40;
+        csd[9] = 0x01;
+        assert_eq!(csd_block_count(&csd), 2 * 1024);
+    }
+
+    #[test]
+    fn init_sdhc() {
+        let card = init(SimCard::sdhc());
+        assert_eq!(card.card_type(), SdCardType::Sdhc);
+        assert_eq!(card.block_count(), 1024);
+
+        let sim = &card.bus.link;
+        assert!(sim.crc_on);
+        let acmd41 = (SD_SEND_OP_COND, 1 << 30);
+        assert_eq!(
+            sim.commands,
+            [
+                (GO_IDLE_STATE, 0),
+                (SEND_IF_COND, 0x1AA),
+                (CRC_ON_OFF, 1),
+                (APP_CMD, 0),
+                acmd41,
+                (APP_CMD, 0),
+                acmd41,
+                (APP_CMD, 0),
+                acmd41,
+                (APP_CMD, 0),
+                acmd41,
+                (READ_OCR, 0),
+                (SEND_CSD, 0),
+            ]
+        );
+        assert!(sim.prescalers.len() == 2);
+        assert!(sim.prescalers[0] == Prescaler::Div256 && sim.prescalers[1] == Prescaler::Div4);
+    }
+
+    #[test]
+    fn init_sdsc_v2() {
+        let card = init(SimCard::sdsc());
+        assert_eq!(card.card_type(), SdCardType::SdV2);
+        assert_eq!(card.block_count(), 64);
+        assert!(card.bus.link.commands.contains(&(SET_BLOCKLEN, BLOCK_SIZE as u32)));
+    }
+
+    #[test]
+    fn init_sd_v1() {
+        let card = init(SimCard::sd_v1());
+        assert_eq!(card.card_type(), SdCardType::SdV1);
+        assert_eq!(card.block_count(), 64);
+
+        // No high capacity support is requested from a version 1 card.
+        let commands = &card.bus.link.commands;
+        assert!(commands.contains(&(SD_SEND_OP_COND, 0)));
+        assert!(!commands.contains(&(READ_OCR, 0)));
+        assert!(commands.contains(&(SET_BLOCKLEN, BLOCK_SIZE as u32)));
+    }
+
+    #[test]
+    fn init_no_card() {
+        let mut sim = SimCard::sdhc();
+        sim.present = false;
+        let result = block_on(SdCard::with_link(sim, Prescaler::Div256, Prescaler::Div4));
+        assert!(matches!(result, Err(SdError::Timeout)));
+    }
+
+    #[test]
+    fn sdsc_byte_addressing() {
+        let mut card = init(SimCard::sdsc());
+        card.bus.link.mem[3 * BLOCK_SIZE..4 * BLOCK_SIZE].copy_from_slice(&pattern(3));
+        let mut buf = [0; BLOCK_SIZE];
+        block_on(card.read(3, &mut buf)).unwrap();
+        assert_eq!(card.bus.link.commands.last(), Some(&(READ_SINGLE_BLOCK, 3 * BLOCK_SIZE as u32)));
+        assert_eq!(&buf[..], &pattern(3)[..]);
+    }
+
+    #[test]
+    fn sdhc_block_addressing() {
+        let mut card = init(SimCard::sdhc());
+        card.bus.link.mem[3 * BLOCK_SIZE..4 * BLOCK_SIZE].copy_from_slice(&pattern(3));
+        let mut buf = [0; BLOCK_SIZE];
+        block_on(card.read(3, &mut buf)).unwrap();
+        assert_eq!(card.bus.link.commands.last(), Some(&(READ_SINGLE_BLOCK, 3)));
+        assert_eq!(&buf[..], &pattern(3)[..]);
+    }
+
+    #[test]
+    fn read_multiple_blocks() {
+        let mut card = init(SimCard::sdhc());
+        for block in 5..8 {
+            card.bus.link.mem[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(&pattern(block));
+        }
+        let mut buf = vec![0; 3 * BLOCK_SIZE];
+        block_on(card.read(5, &mut buf)).unwrap();
+        for (i, chunk) in buf.chunks(BLOCK_SIZE).enumerate() {
+            assert_eq!(chunk, &pattern(5 + i)[..]);
+        }
+        let commands = &card.bus.link.commands;
+        assert_eq!(commands[commands.len() - 2..], [(READ_MULTIPLE_BLOCK, 5), (STOP_TRANSMISSION, 0)]);
+    }
+
+    #[test]
+    fn write_multiple_blocks() {
+        let mut card = init(SimCard::sdsc());
+        let mut buf = Vec::new();
+        for block in 10..13 {
+            buf.extend(pattern(block));
+        }
+        block_on(card.write(10, &buf)).unwrap();
+        assert_eq!(
+            card.bus.link.commands.last(),
+            Some(&(WRITE_MULTIPLE_BLOCK, 10 * BLOCK_SIZE as u32))
+        );
+        assert_eq!(&card.bus.link.mem[10 * BLOCK_SIZE..13 * BLOCK_SIZE], &buf[..]);
+
+        block_on(card.write(20, &pattern(20))).unwrap();
+        assert_eq!(card.bus.link.commands.last(), Some(&(WRITE_BLOCK, 20 * BLOCK_SIZE as u32)));
+        assert_eq!(&card.bus.link.mem[20 * BLOCK_SIZE..21 * BLOCK_SIZE], &pattern(20)[..]);
+
+        // The card is ready for the next command.
+        let mut read = vec![0; 3 * BLOCK_SIZE];
+        block_on(card.read(10, &mut read)).unwrap();
+        assert_eq!(read, buf);
+    }
+
+    #[test]
+    fn read_error_token() {
+        let mut card = init(SimCard::sdhc());
+        card.bus.link.read_error = Some((6, 0x08));
+        let mut buf = vec![0; 3 * BLOCK_SIZE];
+        assert!(matches!(block_on(card.read(5, &mut buf)), Err(SdError::Read(0x08))));
+    }
+
+    #[test]
+    fn read_crc_mismatch() {
+        let mut card = init(SimCard::sdhc());
+        card.bus.link.corrupt_crc = Some(2);
+        let mut buf = [0; BLOCK_SIZE];
+        assert!(matches!(block_on(card.read(2, &mut buf)), Err(SdError::Crc)));
+    }
+
+    #[test]
+    fn write_rejected() {
+        let mut card = init(SimCard::sdhc());
+        card.bus.link.write_error = Some((11, 0x0D));
+        let buf = vec![0; 3 * BLOCK_SIZE];
+        assert!(matches!(block_on(card.write(10, &buf)), Err(SdError::Write(0x0D))));
+
+        card.bus.link.write_error = Some((20, 0x0B));
+        assert!(matches!(block_on(card.write(20, &pattern(20))), Err(SdError::Crc)));
+    }
+
+    #[test]
+    fn command_after_failed_multiple_blocks() {
+        let mut card = init(SimCard::sdhc());
+        for block in 5..8 {
+            card.bus.link.mem[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(&pattern(block));
+        }
+        let mut buf = vec![0; 3 * BLOCK_SIZE];
+
+        card.bus.link.read_error = Some((5, 0x08));
+        assert!(matches!(block_on(card.read(5, &mut buf)), Err(SdError::Read(0x08))));
+        assert_eq!(card.bus.link.commands.last(), Some(&(STOP_TRANSMISSION, 0)));
+        card.bus.link.read_error = None;
+        block_on(card.read(5, &mut buf)).unwrap();
+        assert_eq!(&buf[..BLOCK_SIZE], &pattern(5)[..]);
+
+        card.bus.link.write_error = Some((11, 0x0D));
+        assert!(matches!(block_on(card.write(10, &buf)), Err(SdError::Write(0x0D))));
+        card.bus.link.write_error = None;
+        block_on(card.write(20, &pattern(20))).unwrap();
+        assert_eq!(card.bus.link.commands.last(), Some(&(WRITE_BLOCK, 20)));
+        assert_eq!(&card.bus.link.mem[20 * BLOCK_SIZE..21 * BLOCK_SIZE], &pattern(20)[..]);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut card = init(SimCard::sdsc());
+        let mut buf = vec![0; 2 * BLOCK_SIZE];
+        assert!(matches!(block_on(card.read(63, &mut buf)), Err(SdError::OutOfRange)));
+        assert!(matches!(block_on(card.read(0, &mut buf[..100])), Err(SdError::OutOfRange)));
+    }
+}
@@ -1,6 +1,7 @@
 use drone_cortexm::thr::IntToken;
 use drone_stm32f4_rcc_drv::{ConfiguredClk, clktree::PClkToken};
 use drone_stm32_map::periph::spi::SpiPeriph;
+use drone_stm32f4_dma_drv::{config::DataSize, DmaItem};
 
 pub use crate::{SpiMap, pins::*};
 
@@ -15,8 +16,18 @@ pub struct SpiSetup<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> {
     pub baud_rate: BaudRate,
     /// The clock polarity.
     pub clk_pol: ClkPol,
+    /// The clock phase.
+    pub clk_phase: ClkPhase,
     /// The bit transmission order.
     pub first_bit: FirstBit,
+    /// The data frame size.
+    pub data_frame: DataFrame,
+    /// The frame format.
+    pub frame_format: FrameFormat,
+    /// The crc polynomial, or `None` to disable the hardware crc calculation.
+    pub crc_polynomial: Option<u16>,
+    /// The data line configuration, set by [`NewSpiSetup::new_three_wire`] which checks the pins.
+    pub(crate) data_lines: DataLines,
 }
 
 pub trait NewSpiSetup<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> {
@@ -28,6 +39,16 @@ pub trait NewSpiSetup<Spi: SpiMap, SpiInt: IntToken, Clk: PClkToken> {
         clk: ConfiguredClk<Clk>,
         baud_rate: BaudRate,
     ) -> Self;
+
+    /// Create a new spi setup for a single bidirectional data line on the `mosi` pin (three-wire),
+    /// i.e. without the `miso` pin.
+    fn new_three_wire(
+        spi: SpiPeriph<Spi>,
+        spi_int: SpiInt,
+        pins: SpiPins<Spi, Defined, Undefined, Defined>,
+        clk: ConfiguredClk<Clk>,
+        baud_rate: BaudRate,
+    ) -> Self;
 }
 
 pub enum BaudRate {
@@ -53,12 +74,174 @@ pub enum ClkPol {
     High,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum ClkPhase {
+    /// The first clock transition is the first data capture edge.
+    First,
+    /// The second clock transition is the first data capture edge.
+    Second,
+}
+
+/// The spi mode, i.e. the combination of clock polarity and phase.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SpiMode {
+    /// CPOL=0, CPHA=0.
+    Mode0,
+    /// CPOL=0, CPHA=1.
+    Mode1,
+    /// CPOL=1, CPHA=0.
+    Mode2,
+    /// CPOL=1, CPHA=1.
+    Mode3,
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum FirstBit {
     Msb,
     Lsb,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum DataFrame {
+    /// 8-bit data frames transferred from/to `u8` buffers.
+    Bits8,
+    /// 16-bit data frames transferred from/to `u16` buffers.
+    Bits16,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum FrameFormat {
+    /// Motorola frame format.
+    Motorola,
+    /// TI frame format, where the clock polarity and phase are ignored.
+    Ti,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum DataLines {
+    /// Separate `miso` and `mosi` data lines (full duplex).
+    FullDuplex,
+    /// A single bidirectional data line on the `mosi` pin (half duplex three-wire).
+    Bidirectional,
+}
+
+/// A data item that can be transferred in a spi data frame.
+pub trait SpiWord: DmaItem {
+    /// The data frame size of the item.
+    const FRAME: DataFrame;
+
+    /// The data register value for the item.
+    fn into_frame(self) -> u32;
+
+    /// The item from a data register value.
+    fn from_frame(frame: u32) -> Self;
+}
+
+impl SpiWord for u8 {
+    const FRAME: DataFrame = DataFrame::Bits8;
+
+    fn into_frame(self) -> u32 {
+        self.into()
+    }
+
+    fn from_frame(frame: u32) -> Self {
+        frame as u8
+    }
+}
+
+impl SpiWord for u16 {
+    const FRAME: DataFrame = DataFrame::Bits16;
+
+    fn into_frame(self) -> u32 {
+        self.into()
+    }
+
+    fn from_frame(frame: u32) -> Self {
+        frame as u16
+    }
+}
+
+impl SpiMode {
+    /// Create the mode from the clock polarity and phase.
+    pub const fn new(clk_pol: ClkPol, clk_phase: ClkPhase) -> Self {
+        match (clk_pol, clk_phase) {
+            (ClkPol::Low, ClkPhase::First) => SpiMode::Mode0,
+            (ClkPol::Low, ClkPhase::Second) => SpiMode::Mode1,
+            (ClkPol::High, ClkPhase::First) => SpiMode::Mode2,
+            (ClkPol::High, ClkPhase::Second) => SpiMode::Mode3,
+        }
+    }
+
+    /// The clock polarity.
+    pub const fn clk_pol(self) -> ClkPol {
+        match self {
+            SpiMode::Mode0 | SpiMode::Mode1 => ClkPol::Low,
+            SpiMode::Mode2 | SpiMode::Mode3 => ClkPol::High,
+        }
+    }
+
+    /// The clock phase.
+    pub const fn clk_phase(self) -> ClkPhase {
+        match self {
+            SpiMode::Mode0 | SpiMode::Mode2 => ClkPhase::First,
+            SpiMode::Mode1 | SpiMode::Mode3 => ClkPhase::Second,
+        }
+    }
+}
+
+impl Prescaler {
+    /// The baud rate control (BR) value in CR1.
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            Prescaler::Div2 => 0b000,
+            Prescaler::Div4 => 0b001,
+            Prescaler::Div8 => 0b010,
+            Prescaler::Div16 => 0b011,
+            Prescaler::Div32 => 0b100,
+            Prescaler::Div64 => 0b101,
+            Prescaler::Div128 => 0b110,
+            Prescaler::Div256 => 0b111,
+        }
+    }
+
+    /// The prescaler from the baud rate control (BR) value in CR1.
+    pub(crate) fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b000 => Prescaler::Div2,
+            0b001 => Prescaler::Div4,
+            0b010 => Prescaler::Div8,
+            0b011 => Prescaler::Div16,
+            0b100 => Prescaler::Div32,
+            0b101 => Prescaler::Div64,
+            0b110 => Prescaler::Div128,
+            _ => Prescaler::Div256,
+        }
+    }
+
+    /// The peripheral clock division factor.
+    pub(crate) fn div(self) -> u32 {
+        2 << self.bits()
+    }
+}
+
+impl DataFrame {
+    /// The dma data size matching the data frame.
+    pub const fn dma_size(self) -> DataSize {
+        match self {
+            DataFrame::Bits8 => DataSize::Byte,
+            DataFrame::Bits16 => DataSize::HalfWord,
+        }
+    }
+
+    /// The number of bytes in a data frame.
+    pub(crate) const fn bytes(self) -> usize {
+        match self {
+            DataFrame::Bits8 => 1,
+            DataFrame::Bits16 => 2,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! spi_setup {
     ($spi:ident, $pclk:ident) => {
@@ -84,9 +267,71 @@ macro_rules! spi_setup {
                     clk,
                     baud_rate,
                     clk_pol: crate::ClkPol::Low,
+                    clk_phase: crate::ClkPhase::First,
                     first_bit: crate::FirstBit::Msb,
+                    data_frame: crate::DataFrame::Bits8,
+                    frame_format: crate::FrameFormat::Motorola,
+                    crc_polynomial: None,
+                    data_lines: crate::DataLines::FullDuplex,
+                }
+            }
+
+            fn new_three_wire(
+                spi: drone_stm32_map::periph::spi::SpiPeriph<$spi>,
+                spi_int: SpiInt,
+                _pins: crate::pins::SpiPins<
+                    $spi,
+                    crate::pins::Defined,
+                    crate::pins::Undefined,
+                    crate::pins::Defined,
+                >,
+                clk: drone_stm32f4_rcc_drv::ConfiguredClk<$pclk>,
+                baud_rate: crate::BaudRate,
+            ) -> Self {
+                // The pins only exist as a type, so the three-wire pins are checked by the signature above.
+                Self {
+                    data_lines: crate::DataLines::Bidirectional,
+                    ..Self::new(spi, spi_int, crate::pins::SpiPins::new(), clk, baud_rate)
                 }
             }
         }
     };
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESCALERS: [Prescaler; 8] = [
+        Prescaler::Div2,
+        Prescaler::Div4,
+        Prescaler::Div8,
+        Prescaler::Div16,
+        Prescaler::Div32,
+        Prescaler::Div64,
+        Prescaler::Div128,
+        Prescaler::Div256,
+    ];
+
+    #[test]
+    fn prescaler_bits() {
+        for (i, &prescaler) in PRESCALERS.iter().enumerate() {
+            assert_eq!(prescaler.bits(), i as u32);
+            assert!(Prescaler::from_bits(prescaler.bits()) == prescaler);
+            assert_eq!(prescaler.div(), 2 << i);
+        }
+    }
+
+    #[test]
+    fn spi_mode_clk() {
+        for &(clk_pol, clk_phase) in &[
+            (ClkPol::Low, ClkPhase::First),
+            (ClkPol::Low, ClkPhase::Second),
+            (ClkPol::High, ClkPhase::First),
+            (ClkPol::High, ClkPhase::Second),
+        ] {
+            let mode = SpiMode::new(clk_pol, clk_phase);
+            assert!(mode.clk_pol() == clk_pol && mode.clk_phase() == clk_phase);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    diverged::SpiDiverged,
+    master::frame_transfer,
+    DataFrame, SpiWord,
+};
+use displaydoc::Display;
+use drone_cortexm::{reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::periph::{
+    dma::ch::DmaChMap,
+    spi::{traits::*, SpiMap},
+};
+use drone_stm32f4_dma_drv::{DmaChCfg, DmaChDiverged, DmaChNum, DmaError, DmaStChToken};
+use futures::{prelude::*, stream};
+
+/// Spi slave transaction error.
+#[derive(Display, Debug)]
+pub enum SpiSlaveError {
+    /// Overrun, the master transferred more frames than fit in the receive buffer.
+    Overrun,
+    /// Underrun, the master transferred more frames than available in the transmit buffer.
+    Underrun,
+    /// Dma error: {0}
+    Dma(DmaError),
+}
+
+/// A completed spi slave transaction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpiTransaction {
+    /// The number of frames received into the receive buffer.
+    pub received: usize,
+    /// The number of frames taken from the transmit buffer.
+    pub transmitted: usize,
+}
+
+pub struct SpiSlaveDrv<
+    Spi: SpiMap,
+    DmaRx: DmaChMap,
+    DmaRxInt: IntToken,
+    DmaTx: DmaChMap,
+    DmaTxInt: IntToken,
+> {
+    pub(crate) spi: SpiDiverged<Spi>,
+    pub(crate) dma_rx: DmaChDiverged<DmaRx>,
+    pub(crate) dma_rx_int: DmaRxInt,
+    pub(crate) dma_tx: DmaChDiverged<DmaTx>,
+    pub(crate) dma_tx_int: DmaTxInt,
+}
+
+impl<
+        Spi: SpiMap,
+        DmaRx: DmaChMap,
+        DmaRxInt: IntToken,
+        DmaTx: DmaChMap,
+        DmaTxInt: IntToken,
+    > SpiSlaveDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>
+{
+    pub(crate) fn init<DmaRxStCh: DmaStChToken, DmaTxStCh: DmaStChToken>(
+        spi: SpiDiverged<Spi>,
+        miso_cfg: DmaChCfg<DmaRx, DmaRxStCh, DmaRxInt>,
+        mosi_cfg: DmaChCfg<DmaTx, DmaTxStCh, DmaTxInt>,
+    ) -> Self
+    where
+        DmaRx: DmaChNum,
+        DmaTx: DmaChNum,
+    {
+        let DmaChCfg {
+            dma_ch: dma_rx,
+            dma_int: dma_rx_int,
+            dma_pl: dma_rx_pl,
+            dma_transfer: dma_rx_transfer,
+            ..
+        } = miso_cfg;
+        let DmaChCfg {
+            dma_ch: dma_tx,
+            dma_int: dma_tx_int,
+            dma_pl: dma_tx_pl,
+            dma_transfer: dma_tx_transfer,
+            ..
+        } = mosi_cfg;
+        let slave = Self {
+            spi,
+            dma_rx: dma_rx.into(),
+            dma_rx_int,
+            dma_tx: dma_tx.into(),
+            dma_tx_int,
+        };
+
+        // Overrun is reported by the transactions instead of the error interrupt.
+        slave.spi.spi_cr2.modify_reg(|r, v| r.errie().clear(v));
+
+        slave.spi.spi_cr1.modify_reg(|r, v| {
+            // Slave configuration.
+            r.mstr().clear(v);
+
+            // Use hardware slave management, i.e. the slave is selected by the NSS pin.
+            r.ssm().clear(v);
+
+            // Enable spi after being fully configured.
+            r.spe().set(v);
+        });
+
+        let data_frame = slave.data_frame();
+        slave.dma_rx.init_dma_rx(
+            slave.spi.spi_dr.as_mut_ptr() as u32,
+            DmaRxStCh::NUM,
+            dma_rx_pl,
+            frame_transfer(data_frame, dma_rx_transfer),
+        );
+
+        slave.dma_tx.init_dma_tx(
+            slave.spi.spi_dr.as_mut_ptr() as u32,
+            DmaTxStCh::NUM,
+            dma_tx_pl,
+            frame_transfer(data_frame, dma_tx_transfer),
+        );
+
+        slave
+    }
+
+    /// Creates a stream of transactions, each delimited by the master deselecting the slave.
+    ///
+    /// `nss` must yield on each rising edge on the NSS pin, e.g. `line.saturating_pulse_stream()` on a rising edge
+    /// exti line for the NSS pin, and the returned stream ends when `nss` ends.
+    /// `tx_buf` is transmitted and `rx_buf` is received during each transaction.
+    /// After each successful transaction `respond` is called with the received frames and `tx_buf`,
+    /// so that the response for the next transaction can be prepared.
+    ///
+    /// The buffers are armed when the stream is polled, so the master must not select the slave
+    /// before the stream is polled for the next transaction.
+    /// The buffer items must match the configured data frame, i.e. `u8` for 8-bit and `u16` for 16-bit frames.
+    pub fn transactions<'a, Word, Nss, Respond>(
+        &'a mut self,
+        nss: Nss,
+        tx_buf: &'a mut [Word],
+        rx_buf: &'a mut [Word],
+        respond: Respond,
+    ) -> impl Stream<Item = Result<SpiTransaction, SpiSlaveError>> + 'a
+    where
+        Word: SpiWord + 'a,
+        Nss: Stream + Unpin + 'a,
+        Respond: FnMut(&[Word], &mut [Word]) + 'a,
+    {
+        assert!(
+            Word::FRAME == self.data_frame(),
+            "The buffer items do not match the data frame"
+        );
+        assert!(!tx_buf.is_empty() && !rx_buf.is_empty());
+
+        stream::unfold(
+            (self, nss, tx_buf, rx_buf, respond),
+            |(slave, mut nss, tx_buf, rx_buf, mut respond)| async move {
+                let res = slave.transaction(tx_buf, rx_buf, &mut nss).await?;
+                if let Ok(SpiTransaction { received, .. }) = res {
+                    respond(&rx_buf[..received], tx_buf);
+                }
+                Some((res, (slave, nss, tx_buf, rx_buf, respond)))
+            },
+        )
+    }
+
+    /// Run a single transaction that completes on the next item from `nss`,
+    /// or `None` if `nss` has ended.
+    async fn transaction<Word: SpiWord, Nss: Stream + Unpin>(
+        &mut self,
+        tx_buf: &[Word],
+        rx_buf: &mut [Word],
+        nss: &mut Nss,
+    ) -> Option<Result<SpiTransaction, SpiSlaveError>> {
+        // Discard the edges from before the transaction, e.g. while the previous response was prepared.
+        while let Some(Some(_)) = nss.next().now_or_never() {}
+
+        let dma_rx_tc = self.dma_rx.transfer_future(self.dma_rx_int);
+        let dma_tx_tc = self.dma_tx.transfer_future(self.dma_tx_int);
+
+        unsafe {
+            self.dma_rx.setup_stream(rx_buf);
+            self.dma_tx.setup_stream(tx_buf);
+        }
+
+        // Clear any outstanding fifo error interrupt flag by settings its clear register.
+        self.dma_rx.dma_ifcr_cfeif.set_bit();
+        self.dma_tx.dma_ifcr_cfeif.set_bit();
+
+        // Start transfer on DMA channel.
+        // The first frame is loaded into the transmit buffer before the master starts clocking.
+        self.spi.spi_cr2.modify_reg(|r, v| {
+            r.rxdmaen().set(v);
+            r.txdmaen().set(v);
+        });
+
+        // Wait for the master to deselect the slave.
+        let deselected = nss.next().await.is_some();
+
+        let rx_remaining = self.dma_rx.dma_cndtr.ndt().read_bits() as usize;
+        let tx_remaining = self.dma_tx.dma_cndtr.ndt().read_bits() as usize;
+
+        // Disable both streams.
+        // A stream disabled by software sets its transfer complete flag,
+        // so this completes the fibers listening on streams that did not complete.
+        // The flags are cleared only after both fibers have completed.
+        self.dma_rx.abort();
+        self.dma_tx.abort();
+        let dma_rx_res = dma_rx_tc.await;
+        let dma_tx_res = dma_tx_tc.await;
+        self.dma_rx.clear_flags();
+        self.dma_tx.clear_flags();
+
+        // Stop transfer on DMA channel.
+        self.spi.spi_cr2.modify_reg(|r, v| {
+            r.rxdmaen().clear(v);
+            r.txdmaen().clear(v);
+        });
+
+        // Discard any received data and any frame preloaded for transmission.
+        // Reading DR followed by SR also clears an overrun (OVR) condition, see RM0090 §28.4.8.
+        let overrun = self.spi.spi_sr.ovr().read_bit();
+        self.spi.spi_dr.load_val();
+        self.spi.spi_sr.load_val();
+        self.spi.spi_cr1.modify_reg(|r, v| r.spe().clear(v));
+        self.spi.spi_cr1.modify_reg(|r, v| r.spe().set(v));
+
+        if !deselected {
+            return None;
+        }
+        if let Err(err) = dma_rx_res.and(dma_tx_res) {
+            return Some(Err(SpiSlaveError::Dma(err)));
+        }
+
+        let word_size = core::mem::size_of::<Word>();
+        let received = (rx_buf.len() * word_size / self.dma_rx.psize_bytes()) - rx_remaining;
+        let transmitted = (tx_buf.len() * word_size / self.dma_tx.psize_bytes()) - tx_remaining;
+        Some(if overrun {
+            Err(SpiSlaveError::Overrun)
+        } else if received > transmitted {
+            Err(SpiSlaveError::Underrun)
+        } else {
+            Ok(SpiTransaction {
+                received,
+                transmitted,
+            })
+        })
+    }
+
+    /// The configured data frame.
+    pub fn data_frame(&self) -> DataFrame {
+        self.spi.data_frame()
+    }
+}
+
+impl<Spi: SpiMap, DmaRx: DmaChMap, DmaRxInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToken> Drop
+    for SpiSlaveDrv<Spi, DmaRx, DmaRxInt, DmaTx, DmaTxInt>
+{
+    fn drop(&mut self) {
+        self.spi.spi_cr1.modify_reg(|r, v| {
+            // Disable spi.
+            r.spe().clear(v);
+        });
+    }
+}
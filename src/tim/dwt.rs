@@ -0,0 +1,26 @@
+use drone_core::reg::prelude::*;
+use drone_cortexm::map::reg::dwt;
+
+use crate::TimerCounter;
+
+/// The DWT cycle counter as a timer counter, counting cpu cycles.
+/// The counter must be enabled, i.e. `DEMCR.TRCENA` and `DWT_CTRL.CYCCNTENA` must be set.
+#[derive(Clone, Copy)]
+pub struct DwtCounter {
+    dwt_cyccnt: dwt::Cyccnt<Crt>,
+}
+
+impl DwtCounter {
+    /// Create a counter from the `DWT_CYCCNT` register token.
+    pub fn new(dwt_cyccnt: dwt::Cyccnt<Srt>) -> Self {
+        Self {
+            dwt_cyccnt: dwt_cyccnt.into_copy(),
+        }
+    }
+}
+
+impl TimerCounter for DwtCounter {
+    fn value(&self) -> u32 {
+        self.dwt_cyccnt.load_bits()
+    }
+}
@@ -106,6 +106,15 @@ impl<Tim: GeneralTimMap, Int: IntToken, Ch: GeneralTimCh<Tim>> TimerCompareCh
     }
 }
 
+impl<Tim: GeneralTimMap, Int: IntToken, Ch: GeneralTimCh<Tim>> TimerCounter
+    for GeneralTimChDrv<Tim, Int, Ch, OutputCompareMode>
+{
+    /// Get the counter value of the timer that the channel compares against.
+    fn value(&self) -> u32 {
+        self.tim.tim_cnt.cnt().read_bits() as u32
+    }
+}
+
 impl<Tim: GeneralTimMap, Int: IntToken, Ch: GeneralTimCh<Tim>> TimerCompareStop
     for GeneralTimChDrv<Tim, Int, Ch, OutputCompareMode>
 {
@@ -3,6 +3,7 @@
 
 extern crate alloc;
 
+mod dwt;
 mod gen;
 mod gen_ch;
 mod gen_cnt;
@@ -11,6 +12,7 @@ mod mappings;
 mod shared;
 mod traits;
 
+pub use self::dwt::DwtCounter;
 pub use self::gen::{
     ConfigureTimCh1, ConfigureTimCh2, ConfigureTimCh3, ConfigureTimCh4, GeneralTimCfg,
     GeneralTimSetup, NewGeneralTimSetup,
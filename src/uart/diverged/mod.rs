@@ -1,4 +1,3 @@
-mod dma_ch;
 mod uart;
 
-pub(crate) use self::{dma_ch::DmaChDiverged, uart::UartDiverged};
+pub(crate) use self::uart::UartDiverged;
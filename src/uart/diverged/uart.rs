@@ -1,5 +1,7 @@
+use crate::{drv::uart_brr, BaudRate, Parity, ReconfigError, StopBits};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use drone_cortexm::reg::prelude::*;
-use drone_stm32_map::periph::uart::{UartMap, UartPeriph};
+use drone_stm32_map::periph::uart::{traits::*, UartMap, UartPeriph};
 
 #[allow(dead_code)]
 pub(crate) struct UartDiverged<Uart: UartMap> {
@@ -7,14 +9,20 @@ pub(crate) struct UartDiverged<Uart: UartMap> {
     pub(crate) rcc_busrstr_uartrst: Uart::SRccBusrstrUartrst,
     pub(crate) rcc_bussmenr_uartsmen: Uart::SRccBussmenrUartsmen,
     pub(crate) uart_sr: Uart::CUartSr,
-    pub(crate) uart_dr: Uart::SUartDr,
+    pub(crate) uart_dr: Uart::CUartDr,
     pub(crate) uart_brr: Uart::SUartBrr,
-    pub(crate) uart_cr1: Uart::SUartCr1,
+    /// Shared by the interrupt fibers and both directions, so its bits are only written through the bit-band alias.
+    pub(crate) uart_cr1: Uart::CUartCr1,
     pub(crate) uart_cr2: Uart::SUartCr2,
-    pub(crate) uart_cr3: Uart::SUartCr3,
+    /// Written through the bit-band alias once configured, like `uart_cr1`.
+    pub(crate) uart_cr3: Uart::CUartCr3,
     pub(crate) uart_gtpr: Uart::SUartGtprOpt,
+    /// The number of running rx and tx sessions, or `RECONFIGURING` while the uart is reconfigured.
+    sessions: AtomicUsize,
 }
 
+const RECONFIGURING: usize = usize::MAX;
+
 impl<Uart: UartMap> From<UartPeriph<Uart>> for UartDiverged<Uart> {
     fn from(periph: UartPeriph<Uart>) -> Self {
         let UartPeriph {
@@ -34,12 +42,108 @@ impl<Uart: UartMap> From<UartPeriph<Uart>> for UartDiverged<Uart> {
             rcc_busrstr_uartrst,
             rcc_bussmenr_uartsmen,
             uart_sr: uart_sr.into_copy(),
-            uart_dr,
+            uart_dr: uart_dr.into_copy(),
             uart_brr,
-            uart_cr1,
+            uart_cr1: uart_cr1.into_copy(),
             uart_cr2,
-            uart_cr3,
+            uart_cr3: uart_cr3.into_copy(),
             uart_gtpr,
+            sessions: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<Uart: UartMap> UartDiverged<Uart> {
+    /// Start an rx or tx session, which holds off any reconfiguration until it is ended.
+    pub(crate) fn start_session(&self) {
+        let mut sessions = self.sessions.load(Ordering::Acquire);
+        loop {
+            assert_ne!(sessions, RECONFIGURING, "The uart is being reconfigured");
+            match self.sessions.compare_exchange_weak(sessions, sessions + 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(current) => sessions = current,
+            }
+        }
+    }
+
+    /// End a session started by `start_session()`.
+    pub(crate) fn end_session(&self) {
+        self.sessions.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Change the parity, the stop bits, and the baud rate while neither the receiver nor the transmitter is running.
+    pub(crate) fn reconfigure(
+        &self,
+        f_pclk: u32,
+        baud_rate: BaudRate,
+        parity: Parity,
+        stop_bits: StopBits,
+    ) -> Result<(), ReconfigError> {
+        self.lock(|| self.configure(f_pclk, baud_rate, parity, stop_bits))
+    }
+
+    /// Change the baud rate while neither the receiver nor the transmitter is running.
+    pub(crate) fn reconfigure_baud_rate(&self, f_pclk: u32, baud_rate: BaudRate) -> Result<(), ReconfigError> {
+        self.lock(|| self.set_baud_rate(f_pclk, baud_rate))
+    }
+
+    /// Set the parity, the stop bits, and the baud rate.
+    pub(crate) fn configure(&self, f_pclk: u32, baud_rate: BaudRate, parity: Parity, stop_bits: StopBits) {
+        self.assert_disabled();
+        // Parity.
+        if parity != Parity::None {
+            // Enable parity.
+            self.uart_cr1.pce().set_bit_band();
+        } else {
+            self.uart_cr1.pce().clear_bit_band();
+        }
+        if parity == Parity::Odd {
+            // Parity selection: odd.
+            self.uart_cr1.ps().set_bit_band();
+        } else {
+            self.uart_cr1.ps().clear_bit_band();
         }
+        self.uart_cr2.modify_reg(|r, v| {
+            // Stop bits.
+            r.stop().write(
+                v,
+                match stop_bits {
+                    StopBits::One => 0,
+                    StopBits::Half => 1,
+                    StopBits::Two => 2,
+                    StopBits::OneHalf => 3,
+                },
+            );
+        });
+        self.set_baud_rate(f_pclk, baud_rate);
+    }
+
+    /// Set the baud rate for the configured oversampling mode.
+    fn set_baud_rate(&self, f_pclk: u32, baud_rate: BaudRate) {
+        self.assert_disabled();
+        let oversampling = if self.uart_cr1.over8().read_bit() { 8 } else { 16 };
+        self.uart_brr.store_reg(|r, v| {
+            // Baud rate.
+            let (div_man, div_frac) = uart_brr(f_pclk, baud_rate, oversampling);
+            r.div_mantissa().write(v, div_man);
+            r.div_fraction().write(v, div_frac);
+        });
+    }
+
+    /// Disable the uart while `f` changes its configuration, unless a session is running.
+    fn lock(&self, f: impl FnOnce()) -> Result<(), ReconfigError> {
+        self.sessions
+            .compare_exchange(0, RECONFIGURING, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| ReconfigError::Busy)?;
+        self.uart_cr1.ue().clear_bit_band();
+        f();
+        self.uart_cr1.ue().set_bit_band();
+        self.sessions.store(0, Ordering::Release);
+        Ok(())
+    }
+
+    /// The frame format must not be changed during a transfer, see RM0090 §30.6.4.
+    fn assert_disabled(&self) {
+        assert!(!self.uart_cr1.ue().read_bit(), "The uart must be disabled");
     }
 }
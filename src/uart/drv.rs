@@ -1,19 +1,31 @@
-use crate::{UartMap, setup::*, diverged::UartDiverged, pins::*, rx::UartRxDrv, tx::UartTxDrv};
-use core::marker::PhantomData;
+use crate::{UartMap, setup::*, diverged::UartDiverged, pins::*, rx::{UartRxDoubleBuf, UartRxDrv}, tx::UartTxDrv};
 use alloc::sync::Arc;
-use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_cortexm::{reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::{
     dma::ch::DmaChMap,
     uart::traits::*,
 };
-use drone_stm32f4_dma_drv::{DmaChCfg, DmaStChToken};
+use drone_stm32f4_dma_drv::{DmaChCfg, DmaRequest, DmaRequestToken, DmaStChToken};
 use drone_stm32f4_rcc_drv::{clktree::*, ConfiguredClk};
 
 /// Uart driver.
 pub struct UartDrv<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> {
     pub(crate) uart: Arc<UartDiverged<Uart>>,
     pub(crate) uart_int: UartInt,
-    clk: PhantomData<Clk>,
+    clk: ConfiguredClk<Clk>,
+}
+
+/// A handle for changing the baud rate and the frame format between the sessions of the receiver and transmitter.
+pub struct UartReconfig<Uart: UartMap> {
+    uart: Arc<UartDiverged<Uart>>,
+    f_pclk: u32,
+}
+
+/// Uart reconfiguration error.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReconfigError {
+    /// The receiver or the transmitter is running, i.e. an rx guard, a tx guard, or a double buffered receiver is alive.
+    Busy,
 }
 
 impl<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> UartDrv<Uart, UartInt, Clk> {
@@ -29,26 +41,35 @@ impl<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> UartDrv<Uart, UartInt, Cl
             stop_bits,
             parity,
             oversampling,
+            flow_control,
         } = setup;
         assert!(data_bits == 8 || data_bits == 9);
         assert!(oversampling == 8 || oversampling == 16);
         let mut drv = Self {
             uart: Arc::new(uart.into()),
             uart_int,
-            clk: PhantomData,
+            clk,
         };
-        drv.init_uart(clk, baud_rate, data_bits, parity, stop_bits, oversampling);
+        drv.init_uart(baud_rate, data_bits, parity, stop_bits, oversampling, flow_control);
         drv
     }
 
+    /// Get a handle for runtime reconfiguration, which is kept when the driver is turned into its rx and tx drivers.
+    pub fn reconfig(&self) -> UartReconfig<Uart> {
+        UartReconfig {
+            uart: self.uart.clone(),
+            f_pclk: self.clk.freq(),
+        }
+    }
+
     fn init_uart(
         &mut self,
-        clk: ConfiguredClk<Clk>,
         baud_rate: BaudRate,
         data_bits: u32,
         parity: Parity,
         stop_bits: StopBits,
         oversampling: u32,
+        flow_control: FlowControl,
     ) {
         // Enable uart clock.
         self.uart.rcc_busenr_uarten.set_bit();
@@ -62,56 +83,38 @@ impl<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> UartDrv<Uart, UartInt, Cl
                 r.m().set(v);
             }
 
-            // Parity.
-            if parity != Parity::None {
-                // Enable parity.
-                r.pce().set(v);
-                if parity == Parity::Odd {
-                    // Parity selection: odd.
-                    r.ps().set(v);
-                }
-            }
-
             // Oversampling.
             if oversampling == 8 {
                 r.over8().set(v);
             }
         });
-        self.uart.uart_cr2.store_reg(|r, v| {
-            // Stop bits.
-            r.stop().write(
-                v,
-                match stop_bits {
-                    StopBits::One => 0,
-                    StopBits::Half => 1,
-                    StopBits::Two => 2,
-                    StopBits::OneHalf => 3,
-                },
-            );
-        });
-        self.uart.uart_brr.store_reg(|r, v| {
-            // Baud rate.
-            let (div_man, div_frac) = uart_brr(clk, baud_rate, oversampling);
-            r.div_mantissa().write(v, div_man);
-            r.div_fraction().write(v, div_frac);
-        });
 
-        self.uart.uart_cr1.modify_reg(|r, v| {
-            // Enable parity error interrupt.
-            r.peie().set(v);
-            // Enable ORE or RXNE interrupt.
-            r.rxneie().set(v);
-            // Enable uart after being fully configured.
-            r.ue().set(v);
-        });
+        // Parity, stop bits, and baud rate.
+        self.uart.configure(self.clk.freq(), baud_rate, parity, stop_bits);
 
-        // Attach uart error handler.
-        let sr = self.uart.uart_sr;
-        self.uart_int.add_fn(move || {
-            let val = sr.load_val();
-            handle_uart_err::<Uart>(&val, sr);
-            fib::Yielded::<(), !>(())
+        self.uart.uart_cr3.store_reg(|r, v| {
+            // Hardware flow control.
+            if flow_control.rts() {
+                r.rtse().set(v);
+            }
+            if flow_control.cts() {
+                r.ctse().set(v);
+            }
         });
+
+        // Enable uart after being fully configured.
+        self.uart.uart_cr1.ue().set_bit_band();
+    }
+}
+
+impl<Uart: UartMap> UartReconfig<Uart> {
+    /// Change the baud rate, the parity, and the stop bits.
+    ///
+    /// The receiver and the transmitter must be stopped, i.e. their guards dropped, and are started again afterwards.
+    /// Returns [`ReconfigError::Busy`] without changing the configuration if either is running,
+    /// and starting either while the uart is reconfigured panics.
+    pub fn reconfigure(&self, baud_rate: BaudRate, parity: Parity, stop_bits: StopBits) -> Result<(), ReconfigError> {
+        self.uart.reconfigure(self.f_pclk, baud_rate, parity, stop_bits)
     }
 }
 
@@ -128,7 +131,16 @@ pub trait IntoRxDrv<
         self,
         rx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
         rx_pins: &UartPins<Uart, Defined, Tx>,
-    ) -> UartRxDrv<Uart, UartInt, DmaCh>;
+    ) -> UartRxDrv<Uart, UartInt, DmaCh, DmaInt>;
+
+    /// Let the driver run in RX only for a configured dma channel,
+    /// receiving into two buffers using dma double buffer mode.
+    fn into_rx_double_buffered<DmaInt: IntToken, Tx>(
+        self,
+        rx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        rx_pins: &UartPins<Uart, Defined, Tx>,
+        bufs: [Box<[u8]>; 2],
+    ) -> UartRxDoubleBuf<Uart, DmaCh, DmaInt>;
 }
 
 pub trait IntoTxDrv<
@@ -165,129 +177,89 @@ pub trait IntoTrxDrv<
         pins: &UartPins<Uart, Defined, Defined>,
     ) -> (
         UartTxDrv<Uart, UartInt, TxDmaCh, TxDmaInt>,
-        UartRxDrv<Uart, UartInt, RxDmaCh>);
+        UartRxDrv<Uart, UartInt, RxDmaCh, RxDmaInt>);
 }
 
-#[macro_export]
-macro_rules! rx_drv_init {
-    ($uart:ident; $ch:ident, $stch:ident) => {
-        impl<
-                UartInt: drone_cortexm::thr::IntToken,
-                Clk: drone_stm32f4_rcc_drv::clktree::PClkToken,
-            >
-            crate::drv::IntoRxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$ch,
-                $stch,
-                Clk,
-            > for crate::drv::UartDrv<drone_stm32_map::periph::uart::$uart, UartInt, Clk>
-        {
-            fn into_rx<DmaRxInt: drone_cortexm::thr::IntToken, Tx>(
-                self,
-                rx_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    drone_stm32_map::periph::dma::ch::$ch,
-                    $stch,
-                    DmaRxInt,
-                >,
-                _rx_pins: &crate::pins::UartPins<drone_stm32_map::periph::uart::$uart, Defined, Tx>,
-            ) -> crate::rx::UartRxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$ch,
-            > {
-                crate::rx::UartRxDrv::init(self.uart, self.uart_int, rx_cfg)
-            }
-        }
-    };
+/// The dma requests of a uart peripheral.
+pub trait UartDmaRequests: UartMap {
+    /// The receive dma request.
+    type Rx: DmaRequestToken;
+    /// The transmit dma request.
+    type Tx: DmaRequestToken;
 }
 
-#[macro_export]
-macro_rules! tx_drv_init {
-    ($uart:ident; $ch:ident, $stch:ident) => {
-        impl<
-                UartInt: drone_cortexm::thr::IntToken,
-                Clk: drone_stm32f4_rcc_drv::clktree::PClkToken,
-            >
-            crate::drv::IntoTxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$ch,
-                $stch,
-                Clk,
-            > for crate::drv::UartDrv<drone_stm32_map::periph::uart::$uart, UartInt, Clk>
-        {
-            fn into_tx<DmaInt: drone_cortexm::thr::IntToken, Rx>(
-                self,
-                tx_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    drone_stm32_map::periph::dma::ch::$ch,
-                    $stch,
-                    DmaInt,
-                >,
-                _tx_pins: &crate::pins::UartPins<drone_stm32_map::periph::uart::$uart, Rx, Defined>,
-            ) -> crate::tx::UartTxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$ch,
-                DmaInt,
-            > {
-                crate::tx::UartTxDrv::init(self.uart, self.uart_int, tx_cfg)
-            }
-        }
-    };
+impl<
+        Uart: UartDmaRequests,
+        UartInt: IntToken,
+        DmaCh: DmaRequest<Uart::Rx, DmaStCh>,
+        DmaStCh: DmaStChToken,
+        Clk: PClkToken,
+    > IntoRxDrv<Uart, UartInt, DmaCh, DmaStCh, Clk> for UartDrv<Uart, UartInt, Clk>
+{
+    fn into_rx<DmaInt: IntToken, Tx>(
+        self,
+        rx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        _rx_pins: &UartPins<Uart, Defined, Tx>,
+    ) -> UartRxDrv<Uart, UartInt, DmaCh, DmaInt> {
+        UartRxDrv::init(self.uart, self.uart_int, rx_cfg, self.clk.freq())
+    }
+
+    fn into_rx_double_buffered<DmaInt: IntToken, Tx>(
+        self,
+        rx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        _rx_pins: &UartPins<Uart, Defined, Tx>,
+        bufs: [Box<[u8]>; 2],
+    ) -> UartRxDoubleBuf<Uart, DmaCh, DmaInt> {
+        UartRxDoubleBuf::start(self.uart, rx_cfg, bufs)
+    }
 }
 
-#[macro_export]
-macro_rules! trx_drv_init {
-    ($uart:ident; $tx_ch:ident, $tx_stch:ident; $rx_ch:ident, $rx_stch:ident) => {
-        impl<
-                UartInt: drone_cortexm::thr::IntToken,
-                Clk: drone_stm32f4_rcc_drv::clktree::PClkToken,
-            >
-            crate::drv::IntoTrxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$tx_ch,
-                $tx_stch,
-                drone_stm32_map::periph::dma::ch::$rx_ch,
-                $rx_stch,
-                Clk,
-            > for crate::drv::UartDrv<drone_stm32_map::periph::uart::$uart, UartInt, Clk>
-        {
-            fn into_trx<TxDmaInt: drone_cortexm::thr::IntToken, RxDmaInt: drone_cortexm::thr::IntToken>(
-                self,
-                tx_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    drone_stm32_map::periph::dma::ch::$tx_ch,
-                    $tx_stch,
-                    TxDmaInt,
-                >,
-                rx_cfg: drone_stm32f4_dma_drv::DmaChCfg<
-                    drone_stm32_map::periph::dma::ch::$rx_ch,
-                    $rx_stch,
-                    RxDmaInt,
-                >,
-                _pins: &crate::pins::UartPins<drone_stm32_map::periph::uart::$uart, Defined, Defined>,
-            ) -> (crate::tx::UartTxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$tx_ch,
-                TxDmaInt,
-            >, crate::rx::UartRxDrv<
-                drone_stm32_map::periph::uart::$uart,
-                UartInt,
-                drone_stm32_map::periph::dma::ch::$rx_ch,
-            >) {
-                let tx = crate::tx::UartTxDrv::init(self.uart.clone(), self.uart_int, tx_cfg);
-                let rx = crate::rx::UartRxDrv::init(self.uart, self.uart_int, rx_cfg);
+impl<
+        Uart: UartDmaRequests,
+        UartInt: IntToken,
+        DmaCh: DmaRequest<Uart::Tx, DmaStCh>,
+        DmaStCh: DmaStChToken,
+        Clk: PClkToken,
+    > IntoTxDrv<Uart, UartInt, DmaCh, DmaStCh, Clk> for UartDrv<Uart, UartInt, Clk>
+{
+    fn into_tx<DmaInt: IntToken, Rx>(
+        self,
+        tx_cfg: DmaChCfg<DmaCh, DmaStCh, DmaInt>,
+        _tx_pins: &UartPins<Uart, Rx, Defined>,
+    ) -> UartTxDrv<Uart, UartInt, DmaCh, DmaInt> {
+        UartTxDrv::init(self.uart, self.uart_int, tx_cfg)
+    }
+}
 
-                (tx, rx)
-            }
-        }
-    };
+impl<
+        Uart: UartDmaRequests,
+        UartInt: IntToken,
+        TxDmaCh: DmaRequest<Uart::Tx, TxDmaStCh>,
+        TxDmaStCh: DmaStChToken,
+        RxDmaCh: DmaRequest<Uart::Rx, RxDmaStCh>,
+        RxDmaStCh: DmaStChToken,
+        Clk: PClkToken,
+    > IntoTrxDrv<Uart, UartInt, TxDmaCh, TxDmaStCh, RxDmaCh, RxDmaStCh, Clk>
+    for UartDrv<Uart, UartInt, Clk>
+{
+    fn into_trx<TxDmaInt: IntToken, RxDmaInt: IntToken>(
+        self,
+        tx_cfg: DmaChCfg<TxDmaCh, TxDmaStCh, TxDmaInt>,
+        rx_cfg: DmaChCfg<RxDmaCh, RxDmaStCh, RxDmaInt>,
+        _pins: &UartPins<Uart, Defined, Defined>,
+    ) -> (
+        UartTxDrv<Uart, UartInt, TxDmaCh, TxDmaInt>,
+        UartRxDrv<Uart, UartInt, RxDmaCh, RxDmaInt>,
+    ) {
+        let tx = UartTxDrv::init(self.uart.clone(), self.uart_int, tx_cfg);
+        let rx = UartRxDrv::init(self.uart, self.uart_int, rx_cfg, self.clk.freq());
+
+        (tx, rx)
+    }
 }
 
-fn uart_brr<Clk: PClkToken>(
-    clk: ConfiguredClk<Clk>,
+pub(crate) fn uart_brr(
+    f_pclk: u32,
     baud_rate: BaudRate,
     oversampling: u32,
 ) -> (u32, u32) {
@@ -306,7 +278,6 @@ fn uart_brr<Clk: PClkToken>(
             //                  2 * (2 - over8) * baud_rate
             //
             // Note that 25 * f_pclk fits safely in a u32 as max f_pclk = 90_000_000.
-            let f_pclk = clk.freq();
             let over8 = (oversampling == 8) as u32;
             let div100 = (25 * f_pclk) / (2 * (2 - over8) * baud_rate);
             let div_man = div100 / 100; // The mantissa part is: (100 * USARTDIV) / 100
@@ -325,20 +296,32 @@ fn uart_brr<Clk: PClkToken>(
     }
 }
 
-fn handle_uart_err<Uart: UartMap>(val: &Uart::UartSrVal, sr: Uart::CUartSr) {
-    if sr.rxne().read(&val) {
-        panic!("Read data register not empty");
+/// Check that `baud_rate` can be generated from `f_pclk`, i.e. that the uart divider is within 1 and the maximum mantissa.
+pub(crate) fn baud_rate_in_range(f_pclk: u32, baud_rate: u32, oversampling: u32) -> bool {
+    // Check the upper bound before computing the divider, which then cannot overflow.
+    if baud_rate == 0 || baud_rate > f_pclk / oversampling {
+        return false;
     }
-    if sr.ore().read(&val) {
-        panic!("Overrun error");
-    }
-    if sr.nf().read(&val) {
-        panic!("Noice");
-    }
-    if sr.fe().read(&val) {
-        panic!("Framing error");
-    }
-    if sr.pe().read(&val) {
-        panic!("Parity error");
+    let (div_man, _) = uart_brr(f_pclk, BaudRate::Nominal(baud_rate), oversampling);
+    div_man <= 0xfff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baud_rate_range() {
+        assert!(baud_rate_in_range(84_000_000, 115_200, 16));
+        assert!(baud_rate_in_range(84_000_000, 5_250_000, 16));
+        assert!(baud_rate_in_range(84_000_000, 10_500_000, 8));
+        assert!(baud_rate_in_range(84_000_000, 1_282, 16));
+
+        // Too slow, the mantissa does not fit in 12 bits.
+        assert!(!baud_rate_in_range(84_000_000, 1_200, 16));
+        // Too fast, the mantissa is 0.
+        assert!(!baud_rate_in_range(84_000_000, 5_250_001, 16));
+        assert!(!baud_rate_in_range(84_000_000, 84_000_000, 16));
+        assert!(!baud_rate_in_range(84_000_000, 0, 16));
     }
 }
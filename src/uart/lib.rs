@@ -12,17 +12,17 @@ mod setup;
 
 extern crate alloc;
 
-pub use self::drv::UartDrv;
+pub use self::drv::{ReconfigError, UartDmaRequests, UartDrv, UartReconfig};
 pub use self::prelude::*;
-pub use self::setup::{UartSetup, BaudRate, Parity, StopBits};
+pub use self::setup::{UartSetup, BaudRate, FlowControl, Parity, StopBits, UartFlowControlMap};
 pub use drone_stm32_map::periph::uart::UartMap;
-pub use self::pins::UartPins;
+pub use self::pins::{UartFlowPins, UartFlowPinsDefined, UartPins};
 pub use self::tx::UartTxDrv;
-pub use self::rx::UartRxDrv;
+pub use self::rx::{RxError, UartRxDoubleBuf, UartRxDrv};
 
 pub mod prelude {
     pub use crate::drv::{IntoRxDrv, IntoTxDrv, IntoTrxDrv};
-    pub use crate::setup::UartSetupInit;
+    pub use crate::setup::{UartSetupFlowControl, UartSetupInit};
     pub use crate::pins::traits::*;
 }
 
@@ -1,21 +1,20 @@
-use crate::{uart_setup_init, rx_drv_init, tx_drv_init, trx_drv_init, pins::{*, traits::*}};
+use crate::{uart_setup_init, drv::UartDmaRequests, pins::{*, traits::*}};
 use drone_stm32_map::periph::gpio::pin::*;
-use drone_stm32_map::periph::uart::Uart5;
-use drone_stm32f4_dma_drv::DmaStCh5;
+use drone_stm32_map::periph::uart::Uart7;
+use drone_stm32f4_dma_drv::request::{Uart7Rx, Uart7Tx};
 use drone_stm32f4_gpio_drv::pin_impl;
 use drone_stm32f4_gpio_drv::prelude::*;
 use drone_stm32f4_rcc_drv::clktree::PClk1;
 
 uart_setup_init!(Uart7, PClk1);
 
-rx_drv_init!(Uart7; Dma1Ch3, DmaStCh5);
-
-tx_drv_init!(Uart7; Dma1Ch1, DmaStCh5);
-
-trx_drv_init!(Uart7; Dma1Ch1, DmaStCh5; Dma1Ch3, DmaStCh5);
+impl UartDmaRequests for Uart7 {
+    type Rx = Uart7Rx;
+    type Tx = Uart7Tx;
+}
 
 pin_impl!(RxPinExt for UartPins<Uart7, ...>.rx, GpioE7, AlternateMode<PinAf8>; Undefined, Tx -> Defined, Tx);
 pin_impl!(RxPinExt for UartPins<Uart7, ...>.rx, GpioF6, AlternateMode<PinAf8>; Undefined, Tx -> Defined, Tx);
 
-pin_impl!(TxPinExt for UartPins<Uart7, ...>.tx, GpioE8, AlternateMode<PinAf8>; Tx, Undefined -> Tx, Defined);
-pin_impl!(TxPinExt for UartPins<Uart7, ...>.tx, GpioF7, AlternateMode<PinAf8>; Tx, Undefined -> Tx, Defined);
+pin_impl!(TxPinExt for UartPins<Uart7, ...>.tx, GpioE8, AlternateMode<PinAf8>; Rx, Undefined -> Rx, Defined);
+pin_impl!(TxPinExt for UartPins<Uart7, ...>.tx, GpioF7, AlternateMode<PinAf8>; Rx, Undefined -> Rx, Defined);
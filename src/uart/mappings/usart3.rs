@@ -1,25 +1,30 @@
-use crate::{uart_setup_init, rx_drv_init, tx_drv_init, trx_drv_init, pins::{*, traits::*}};
+use crate::{uart_setup_init, drv::UartDmaRequests, pins::{*, traits::*}, setup::UartFlowControlMap};
 use drone_stm32_map::periph::gpio::pin::*;
 use drone_stm32_map::periph::uart::Usart3;
-use drone_stm32f4_dma_drv::{DmaStCh4, DmaStCh7};
+use drone_stm32f4_dma_drv::request::{Usart3Rx, Usart3Tx};
 use drone_stm32f4_gpio_drv::pin_impl;
 use drone_stm32f4_gpio_drv::prelude::*;
 use drone_stm32f4_rcc_drv::clktree::PClk1;
 
 uart_setup_init!(Usart3, PClk1);
 
-rx_drv_init!(Usart3; Dma1Ch1, DmaStCh4);
+impl UartDmaRequests for Usart3 {
+    type Rx = Usart3Rx;
+    type Tx = Usart3Tx;
+}
 
-tx_drv_init!(Usart3; Dma1Ch3, DmaStCh4);
-tx_drv_init!(Usart3; Dma1Ch4, DmaStCh7);
-
-trx_drv_init!(Usart3; Dma1Ch3, DmaStCh4; Dma1Ch1, DmaStCh4);
-trx_drv_init!(Usart3; Dma1Ch4, DmaStCh7; Dma1Ch1, DmaStCh4);
+impl UartFlowControlMap for Usart3 {}
 
 pin_impl!(RxPinExt for UartPins<Usart3, ...>.rx, GpioB11, AlternateMode<PinAf7>; Undefined, Tx -> Defined, Tx);
 pin_impl!(RxPinExt for UartPins<Usart3, ...>.rx, GpioC11, AlternateMode<PinAf7>; Undefined, Tx -> Defined, Tx);
 pin_impl!(RxPinExt for UartPins<Usart3, ...>.rx, GpioD9, AlternateMode<PinAf7>; Undefined, Tx -> Defined, Tx);
 
-pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioB10, AlternateMode<PinAf7>; Tx, Undefined -> Tx, Defined);
-pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioC10, AlternateMode<PinAf7>; Tx, Undefined -> Tx, Defined);
-pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioD8, AlternateMode<PinAf7>; Tx, Undefined -> Tx, Defined);
+pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioB10, AlternateMode<PinAf7>; Rx, Undefined -> Rx, Defined);
+pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioC10, AlternateMode<PinAf7>; Rx, Undefined -> Rx, Defined);
+pin_impl!(TxPinExt for UartPins<Usart3, ...>.tx, GpioD8, AlternateMode<PinAf7>; Rx, Undefined -> Rx, Defined);
+
+pin_impl!(RtsPinExt for UartFlowPins<Usart3, ...>.rts, GpioB14, AlternateMode<PinAf7>; Undefined, Cts -> Defined, Cts);
+pin_impl!(RtsPinExt for UartFlowPins<Usart3, ...>.rts, GpioD12, AlternateMode<PinAf7>; Undefined, Cts -> Defined, Cts);
+
+pin_impl!(CtsPinExt for UartFlowPins<Usart3, ...>.cts, GpioB13, AlternateMode<PinAf7>; Rts, Undefined -> Rts, Defined);
+pin_impl!(CtsPinExt for UartFlowPins<Usart3, ...>.cts, GpioD11, AlternateMode<PinAf7>; Rts, Undefined -> Rts, Defined);
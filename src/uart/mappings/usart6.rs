@@ -1,23 +1,19 @@
-use crate::{uart_setup_init, rx_drv_init, tx_drv_init, trx_drv_init, pins::{*, traits::*}};
+use crate::{uart_setup_init, drv::UartDmaRequests, pins::{*, traits::*}, setup::UartFlowControlMap};
 use drone_stm32_map::periph::gpio::pin::*;
 use drone_stm32_map::periph::uart::Usart6;
-use drone_stm32f4_dma_drv::DmaStCh5;
+use drone_stm32f4_dma_drv::request::{Usart6Rx, Usart6Tx};
 use drone_stm32f4_gpio_drv::pin_impl;
 use drone_stm32f4_gpio_drv::prelude::*;
 use drone_stm32f4_rcc_drv::clktree::PClk2;
 
 uart_setup_init!(Usart6, PClk2);
 
-rx_drv_init!(Usart6; Dma2Ch1, DmaStCh5);
-rx_drv_init!(Usart6; Dma2Ch2, DmaStCh5);
+impl UartDmaRequests for Usart6 {
+    type Rx = Usart6Rx;
+    type Tx = Usart6Tx;
+}
 
-tx_drv_init!(Usart6; Dma2Ch6, DmaStCh5);
-tx_drv_init!(Usart6; Dma2Ch7, DmaStCh5);
-
-trx_drv_init!(Usart6; Dma2Ch6, DmaStCh5; Dma2Ch1, DmaStCh5);
-trx_drv_init!(Usart6; Dma2Ch6, DmaStCh5; Dma2Ch2, DmaStCh5);
-trx_drv_init!(Usart6; Dma2Ch7, DmaStCh5; Dma2Ch1, DmaStCh5);
-trx_drv_init!(Usart6; Dma2Ch7, DmaStCh5; Dma2Ch2, DmaStCh5);
+impl UartFlowControlMap for Usart6 {}
 
 pin_impl!(RxPinExt for UartPins<Usart6, ...>.rx, GpioC7, AlternateMode<PinAf8>; Undefined, Tx -> Defined, Tx);
 #[cfg(any(
@@ -32,7 +28,53 @@ pin_impl!(RxPinExt for UartPins<Usart6, ...>.rx, GpioC7, AlternateMode<PinAf8>;
 ))]
 pin_impl!(RxPinExt for UartPins<Usart6, ...>.rx, GpioG9, AlternateMode<PinAf8>; Undefined, Tx -> Defined, Tx);
 
-pin_impl!(TxPinExt for UartPins<Usart6, ...>.tx, GpioC6, AlternateMode<PinAf8>; Tx, Undefined -> Tx, Defined);
+pin_impl!(TxPinExt for UartPins<Usart6, ...>.tx, GpioC6, AlternateMode<PinAf8>; Rx, Undefined -> Rx, Defined);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f412",
+    stm32_mcu = "stm32f413",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f446",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(TxPinExt for UartPins<Usart6, ...>.tx, GpioG14, AlternateMode<PinAf8>; Rx, Undefined -> Rx, Defined);
+
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f412",
+    stm32_mcu = "stm32f413",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f446",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(RtsPinExt for UartFlowPins<Usart6, ...>.rts, GpioG8, AlternateMode<PinAf8>; Undefined, Cts -> Defined, Cts);
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f412",
+    stm32_mcu = "stm32f413",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f446",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(RtsPinExt for UartFlowPins<Usart6, ...>.rts, GpioG12, AlternateMode<PinAf8>; Undefined, Cts -> Defined, Cts);
+
+#[cfg(any(
+    stm32_mcu = "stm32f405",
+    stm32_mcu = "stm32f407",
+    stm32_mcu = "stm32f412",
+    stm32_mcu = "stm32f413",
+    stm32_mcu = "stm32f427",
+    stm32_mcu = "stm32f429",
+    stm32_mcu = "stm32f446",
+    stm32_mcu = "stm32f469",
+))]
+pin_impl!(CtsPinExt for UartFlowPins<Usart6, ...>.cts, GpioG13, AlternateMode<PinAf8>; Rts, Undefined -> Rts, Defined);
 #[cfg(any(
     stm32_mcu = "stm32f405",
     stm32_mcu = "stm32f407",
@@ -43,4 +85,4 @@ pin_impl!(TxPinExt for UartPins<Usart6, ...>.tx, GpioC6, AlternateMode<PinAf8>;
     stm32_mcu = "stm32f446",
     stm32_mcu = "stm32f469",
 ))]
-pin_impl!(TxPinExt for UartPins<Usart6, ...>.tx, GpioG14, AlternateMode<PinAf8>; Tx, Undefined -> Tx, Defined);
+pin_impl!(CtsPinExt for UartFlowPins<Usart6, ...>.cts, GpioG15, AlternateMode<PinAf8>; Rts, Undefined -> Rts, Defined);
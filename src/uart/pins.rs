@@ -1,3 +1,4 @@
+use crate::{setup::UartFlowControlMap, FlowControl};
 use core::marker::PhantomData;
 use drone_stm32_map::periph::uart::UartMap;
 
@@ -26,10 +27,53 @@ impl<Uart: UartMap> Default for UartPins<Uart, Undefined, Undefined> {
     }
 }
 
+/// The rts/cts pins of a usart peripheral, passed to the setup to enable hardware flow control.
+pub struct UartFlowPins<Uart: UartFlowControlMap, Rts, Cts> {
+    uart: PhantomData<Uart>,
+    rts: PhantomData<Rts>,
+    cts: PhantomData<Cts>,
+}
+
+impl<Uart: UartFlowControlMap, Rts, Cts> UartFlowPins<Uart, Rts, Cts> {
+    pub fn new() -> Self {
+        Self {
+            uart: PhantomData,
+            rts: PhantomData,
+            cts: PhantomData,
+        }
+    }
+}
+
+impl<Uart: UartFlowControlMap> Default for UartFlowPins<Uart, Undefined, Undefined> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Flow pins with at least one of rts or cts defined.
+pub trait UartFlowPinsDefined {
+    /// The flow control for the defined pins.
+    const FLOW_CONTROL: FlowControl;
+}
+
+impl<Uart: UartFlowControlMap> UartFlowPinsDefined for UartFlowPins<Uart, Defined, Undefined> {
+    const FLOW_CONTROL: FlowControl = FlowControl::Rts;
+}
+
+impl<Uart: UartFlowControlMap> UartFlowPinsDefined for UartFlowPins<Uart, Undefined, Defined> {
+    const FLOW_CONTROL: FlowControl = FlowControl::Cts;
+}
+
+impl<Uart: UartFlowControlMap> UartFlowPinsDefined for UartFlowPins<Uart, Defined, Defined> {
+    const FLOW_CONTROL: FlowControl = FlowControl::RtsCts;
+}
+
 pub mod traits {
     use super::*;
     use drone_stm32f4_gpio_drv::pin_ext;
 
     pin_ext!(RxPinExt<Uart: UartMap, ..., Rx, Tx>.rx -> UartPins<Uart, Defined, Tx>);
     pin_ext!(TxPinExt<Uart: UartMap, ..., Rx, Tx>.tx -> UartPins<Uart, Rx, Defined>);
+    pin_ext!(RtsPinExt<Uart: UartFlowControlMap, ..., Rts, Cts>.rts -> UartFlowPins<Uart, Defined, Cts>);
+    pin_ext!(CtsPinExt<Uart: UartFlowControlMap, ..., Rts, Cts>.cts -> UartFlowPins<Uart, Rts, Defined>);
 }
@@ -1,78 +1,340 @@
-use crate::diverged::{DmaChDiverged, UartDiverged};
+use crate::{diverged::UartDiverged, drv::baud_rate_in_range, BaudRate};
 use alloc::sync::Arc;
-use core::ops::Range;
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use drone_core::fib::FiberStreamRing;
 use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::{
     dma::ch::{traits::*, DmaChMap},
     uart::{traits::*, UartMap},
 };
-use drone_stm32f4_dma_drv::{DmaChCfg, DmaStChToken};
+use drone_stm32f4_dma_drv::{
+    config::DataSize, DmaBufFull, DmaChCfg, DmaChDiverged, DmaChNum, DmaDoubleBufRx, DmaError, DmaStChToken, DoubleBufError,
+};
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
 
-pub struct UartRxDrv<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap> {
+pub struct UartRxDrv<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap, DmaRxInt: IntToken> {
     pub(crate) uart: Arc<UartDiverged<Uart>>,
     pub(crate) uart_int: UartInt,
     pub(crate) dma: DmaChDiverged<DmaRx>,
+    pub(crate) dma_int: DmaRxInt,
+    /// The peripheral clock frequency.
+    pub(crate) f_pclk: u32,
 }
 
-pub struct RxGuard<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap> {
-    drv: &'sess UartRxDrv<Uart, UartInt, DmaRx>,
+pub struct RxGuard<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap, DmaRxInt: IntToken> {
+    drv: &'sess UartRxDrv<Uart, UartInt, DmaRx, DmaRxInt>,
     ring_buf: Box<[u8]>,
     first: usize,
     last_read_wrapped: bool,
+    idle_wakeup: bool,
+    /// Whether rts flow control is enabled, in which case the dma stream stops at the high-water mark.
+    rts: bool,
+    /// The part of the ring buffer that the dma stream receives into.
+    window: Arc<RxWindow>,
+    /// The byte substituted for bytes received with a line error, if not reported as errors.
+    err_substitute: Option<u8>,
+    /// The next line error and its position in the ring buffer.
+    line_err: Option<(LineError, usize)>,
+    dma_err: FiberStreamRing<DmaError>,
+    uart_err: FiberStreamRing<(LineError, usize)>,
+    uart_err_queue: Arc<LineErrQueue>,
 }
+/// Double buffered uart receiver.
+pub struct UartRxDoubleBuf<Uart: UartMap, DmaRx: DmaChMap, DmaRxInt: IntToken> {
+    uart: Arc<UartDiverged<Uart>>,
+    dma: DmaDoubleBufRx<DmaRx, DmaRxInt, u8>,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum RxError {
     PossibleOverflow,
     Overflow,
+    /// The dma stream failed, and the reception was restarted with an empty ring buffer.
+    Dma(DmaError),
+    /// The next byte was received without a valid stop bit, and is skipped by `read()`.
+    Framing,
+    /// The next byte was received with noise, and is skipped by `read()`.
+    Noise,
+    /// The next byte was received with a parity error, and is skipped by `read()`.
+    Parity,
+    /// A byte was received before the previous byte was read by the dma controller, and is lost before the next byte.
+    HardwareOverrun,
+    /// More line errors were detected than could be queued between reads, and the excess errors are not reported.
+    LineErrOverflow,
+}
+
+/// Uart line error.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum LineError {
+    Framing,
+    Noise,
+    Parity,
+    HardwareOverrun,
+}
+
+impl From<LineError> for RxError {
+    fn from(err: LineError) -> Self {
+        match err {
+            LineError::Framing => RxError::Framing,
+            LineError::Noise => RxError::Noise,
+            LineError::Parity => RxError::Parity,
+            LineError::HardwareOverrun => RxError::HardwareOverrun,
+        }
+    }
+}
+
+/// The part of the ring buffer that the dma stream receives into.
+///
+/// The window is the entire ring buffer in circular mode, or the free part below the high-water mark with rts flow control.
+struct RxWindow {
+    /// The start of the window.
+    start: AtomicUsize,
+    /// The end of the window, or `usize::MAX` while a new window is set up.
+    end: AtomicUsize,
+}
+
+impl RxWindow {
+    fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Get the position in the ring buffer that the dma stream writes next.
+    fn pos<DmaRx: DmaChMap>(&self, dma_cndtr: DmaRx::CDmaCndtr, psize: usize, ring_len: usize) -> usize {
+        let end = self.end.load(Ordering::Acquire);
+        let remaining = dma_cndtr.ndt().read_bits() as usize * psize;
+        if end == usize::MAX || end != self.end.load(Ordering::Acquire) {
+            // A new window is set up, and no bytes are yet received into it.
+            self.start.load(Ordering::Acquire)
+        } else {
+            (end - remaining) % ring_len
+        }
+    }
 }
 
-impl<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
-    UartRxDrv<Uart, UartInt, DmaRx>
+/// The number of line errors that are buffered between reads.
+const LINE_ERR_CAPACITY: usize = 8;
+
+/// The fill level of the line error queue, so that errors not fitting in the queue are reported.
+struct LineErrQueue {
+    /// The number of queued line errors.
+    len: AtomicUsize,
+    /// Whether a line error was dropped as the queue was full.
+    overflow: AtomicBool,
+}
+
+impl<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap, DmaRxInt: IntToken>
+    UartRxDrv<Uart, UartInt, DmaRx, DmaRxInt>
 {
-    pub(crate) fn init<DmaRxStCh: DmaStChToken, DmaRxInt: IntToken>(
+    pub(crate) fn init<DmaRxStCh: DmaStChToken>(
         uart: Arc<UartDiverged<Uart>>,
         uart_int: UartInt,
         rx_cfg: DmaChCfg<DmaRx, DmaRxStCh, DmaRxInt>,
-    ) -> Self {
+        f_pclk: u32,
+    ) -> Self
+    where
+        DmaRx: DmaChNum,
+    {
         let DmaChCfg {
             dma_ch,
             dma_int,
             dma_pl,
+            dma_transfer,
             ..
         } = rx_cfg;
+        // The ring buffer position is derived from NDTR,
+        // which in fifo mode also counts bytes still held in the fifo.
+        assert!(
+            dma_transfer.fifo_threshold().is_none() && dma_transfer.psize() == DataSize::Byte,
+            "The uart receiver requires byte transfers in direct mode"
+        );
         let rx = Self {
             uart,
             uart_int,
             dma: dma_ch.into(),
+            dma_int,
+            f_pclk,
         };
         rx.dma
-            .init_dma_rx(rx.uart.uart_dr.as_mut_ptr() as u32, DmaRxStCh::NUM, dma_pl);
-        rx.dma.panic_on_err(dma_int);
+            .init_dma_rx(rx.uart.uart_dr.as_mut_ptr() as u32, DmaRxStCh::NUM, dma_pl, dma_transfer);
+        rx.dma.dma_ccr.modify_reg(|r, v| {
+            r.tcie().clear(v); // transfer complete interrupt disable
+            r.circ().set(v); // circular mode
+        });
         rx
     }
 
     /// Enable rx operation for the uart peripheral and return a guard that disables the receiver when dropped.
     /// Bytes are received into `ring_buf` and `read()` calls must be made in a sufficent pace to keep up with the reception.
-    /// `read()' calls must always keep the ring buffer less than half full for the driver to correctly detect if overflows have occured.
-    pub fn start(&mut self, ring_buf: Box<[u8]>) -> RxGuard<Uart, UartInt, DmaRx> {
+    /// `read()' calls must always keep the ring buffer less than half full for the driver to correctly detect if overflows have occurred.
+    ///
+    /// If rts flow control is enabled then the dma stream stops when the ring buffer becomes half full,
+    /// after which the uart deasserts rts as its data register is no longer read.
+    /// `read()` restarts the stream when it has made room, and overflows can then not occur.
+    /// The stream also stops at the end of the ring buffer, so rts is briefly deasserted there until the next `read()`.
+    pub fn start(&mut self, ring_buf: Box<[u8]>) -> RxGuard<Uart, UartInt, DmaRx, DmaRxInt> {
+        let rts = self.uart.uart_cr3.rtse().read_bit();
+        assert!(!rts || ring_buf.len() >= 2);
+        self.uart.start_session();
+        let window = Arc::new(RxWindow::new());
+
+        // Listen for dma errors while the receiver is running.
+        let flags = self.dma.err_flags();
+        let dma_isr_teif = self.dma.dma_isr_teif;
+        let dma_err = self.dma_int.add_saturating_stream(
+            1,
+            fib::new_fn(move || {
+                // Load _entire_ interrupt status register.
+                // The value is not masked to TEIF.
+                let val = dma_isr_teif.load_val();
+                fib::Yielded(flags.take(&val))
+            }),
+        );
+
+        // Listen for line errors while the receiver is running.
+        let uart_sr = self.uart.uart_sr;
+        let uart_dr = self.uart.uart_dr;
+        let dma_cndtr = self.dma.dma_cndtr;
+        let ring_len = ring_buf.len();
+        let psize = self.dma.psize_bytes();
+        let err_window = window.clone();
+        let uart_err_queue = Arc::new(LineErrQueue {
+            len: AtomicUsize::new(0),
+            overflow: AtomicBool::new(false),
+        });
+        let queue = uart_err_queue.clone();
+        let uart_err = self.uart_int.add_saturating_stream(
+            LINE_ERR_CAPACITY,
+            fib::new_fn(move || {
+                let val = uart_sr.load_val();
+                let err = if uart_sr.fe().read(&val) {
+                    LineError::Framing
+                } else if uart_sr.pe().read(&val) {
+                    LineError::Parity
+                } else if uart_sr.nf().read(&val) {
+                    LineError::Noise
+                } else if uart_sr.ore().read(&val) {
+                    LineError::HardwareOverrun
+                } else {
+                    return fib::Yielded(None);
+                };
+
+                // The flags are cleared by reading SR followed by DR.
+                // DR is left for the dma controller if it holds a received byte, which then clears the flags.
+                let rxne = uart_sr.rxne().read(&val);
+                if !rxne {
+                    uart_dr.load_val();
+                }
+
+                // The errored byte is the byte in DR if it is not yet read by the dma controller,
+                // and is otherwise assumed to be the last byte written to the ring buffer.
+                // The latter is only exact if the interrupt is handled before the next byte is received.
+                // An overrun byte is lost after the errored byte, so the error is reported before the byte after it.
+                let end = err_window.pos::<DmaRx>(dma_cndtr, psize, ring_len);
+                let errored = if rxne { end } else { (end + ring_len - 1) % ring_len };
+                let pos = if err == LineError::HardwareOverrun {
+                    (errored + 1) % ring_len
+                } else {
+                    errored
+                };
+
+                // The stream ring drops errors when full, so count the queued errors to report any that are dropped.
+                if queue.len.load(Ordering::Acquire) >= LINE_ERR_CAPACITY {
+                    queue.overflow.store(true, Ordering::Release);
+                    return fib::Yielded(None);
+                }
+                queue.len.fetch_add(1, Ordering::AcqRel);
+                fib::Yielded(Some((err, pos)))
+            }),
+        );
+
         let mut rx = RxGuard {
             drv: self,
             ring_buf,
             first: 0,
             last_read_wrapped: false,
+            idle_wakeup: false,
+            rts,
+            window,
+            err_substitute: None,
+            line_err: None,
+            dma_err,
+            uart_err,
+            uart_err_queue,
         };
         rx.start();
         rx
     }
+
+    /// Measure the baud rate from the start bit of the next received character, and set the baud rate accordingly.
+    ///
+    /// `edges` are the timestamps of the edges on the rx line in ticks of a counter running at `counter_freq`,
+    /// e.g. the captures of a timer input capture channel on both edges,
+    /// or the timestamps of an exti edge stream sampled from the dwt cycle counter.
+    /// The line must be idle when the stream starts, and the least significant bit of the character must be 1,
+    /// such that the first two edges enclose the start bit, e.g. `0x55` or `0x7f`.
+    /// The measured character is not received, and the transmitter must be stopped for the baud rate to be changed.
+    ///
+    /// Returns the measured baud rate, or `None` if the stream ended before the start bit was measured,
+    /// if the measured baud rate cannot be generated from the peripheral clock,
+    /// or if the transmitter is running, in which case the baud rate is unchanged.
+    pub async fn auto_baud<Edges: Stream<Item = u32> + Unpin>(
+        &mut self,
+        mut edges: Edges,
+        counter_freq: u32,
+    ) -> Option<u32> {
+        let start = edges.next().await?;
+        let end = edges.next().await?;
+        let ticks = end.wrapping_sub(start);
+        if ticks == 0 {
+            return None;
+        }
+        let baud_rate = ((u64::from(counter_freq) + u64::from(ticks / 2)) / u64::from(ticks)) as u32;
+        let oversampling = if self.uart.uart_cr1.over8().read_bit() { 8 } else { 16 };
+        if !baud_rate_in_range(self.f_pclk, baud_rate, oversampling) {
+            return None;
+        }
+        self.uart.reconfigure_baud_rate(self.f_pclk, BaudRate::Nominal(baud_rate)).ok()?;
+        Some(baud_rate)
+    }
 }
 
-impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
-    RxGuard<'sess, Uart, UartInt, DmaRx>
+impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap, DmaRxInt: IntToken>
+    RxGuard<'sess, Uart, UartInt, DmaRx, DmaRxInt>
 {
     /// Read from the rx ring buffer into `buf`.
     /// Wait for any receiption if no bytes are readily awailable in the ring buffer.
+    ///
+    /// If the dma stream has failed then the reception is restarted with an empty ring buffer,
+    /// and [`RxError::Dma`] is returned.
+    ///
+    /// Line errors are returned when reaching the errored byte, i.e. after all bytes received before it have been read.
+    /// The errored byte is then skipped, unless a substitute is set with `set_err_substitute()`.
+    /// The position of an errored byte is determined when the error interrupt is handled,
+    /// and is the last byte written to the ring buffer unless the byte is still in the data register.
+    /// It is therefore only exact if the interrupt is handled before the next byte is received.
+    ///
+    /// [`RxError::LineErrOverflow`] is returned if more line errors were detected between reads than could be queued.
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, RxError> {
         let drv = self.drv;
+
+        if let Some(Some(err)) = self.dma_err.next().now_or_never() {
+            self.restart();
+            return Err(RxError::Dma(err));
+        }
+
+        if self.uart_err_queue.overflow.swap(false, Ordering::AcqRel) {
+            return Err(RxError::LineErrOverflow);
+        }
+
         // RX Buffer layout:
         //
         // Without wraparound:                             With wraparound:
@@ -93,8 +355,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
         // The transfer completed interrupt flag (TCIF) is asserted when this happens,
         // which is used to detect overflows in the ring buffer.
 
-        let mut ndtr = drv.dma.dma_cndtr.ndt().read_bits() as usize;
-        let mut end = self.ring_buf.len() - ndtr;
+        let mut end = self.window.pos::<DmaRx>(drv.dma.dma_cndtr, drv.dma.psize_bytes(), self.ring_buf.len());
 
         if self.first == end {
             // There currently no bytes readily available in the buffer.
@@ -102,29 +363,43 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
             // Return a buffer overflow error if TCIF is asserted
             // as the DMA controller in that case has wrapped.
             // This is the special case where n*ring_buf.len(), n > 0,1,2,..., bytes have been written since last read.
-            if drv.dma.dma_isr_tcif.read_bit() {
+            // With rts flow control the stream stops at the high-water mark, and TCIF is instead set at the end of each window.
+            if !self.rts && drv.dma.dma_isr_tcif.read_bit() {
                 // Clear transfer completed interrupt flag.
                 drv.dma.dma_ifcr_ctcif.set_bit();
 
                 return Err(RxError::Overflow);
             }
 
+            // Restart the stream if it has stopped at the high-water mark or at the end of the ring buffer.
+            self.update_rts();
+
             // Wait for any number of bytes to arrive in the rx ring buffer.
-            self.any_rx_activity(ndtr).await;
+            let activity = if self.idle_wakeup {
+                self.line_idle().await
+            } else {
+                self.any_rx_activity().await
+            };
+            if let Err(err) = activity {
+                self.restart();
+                return Err(RxError::Dma(err));
+            }
 
             // Update the ring buffer values to new values after some bytes have been received.
-            ndtr = drv.dma.dma_cndtr.ndt().read_bits() as usize;
-            end = self.ring_buf.len() - ndtr;
+            end = self.window.pos::<DmaRx>(drv.dma.dma_cndtr, drv.dma.psize_bytes(), self.ring_buf.len());
         }
 
         // There are at this time bytes readily available in the ring buffer.
 
+        // Limit the available bytes to those before the next line error.
+        let end = self.limit_to_line_err(end)?;
+
         if self.first < end {
             // The available portion in the ring buffer _does not_ wrap.
 
             // Return a buffer overflow error if TCIF is asserted
             // as the DMA controller in that case has wrapped.
-            if drv.dma.dma_isr_tcif.read_bit() {
+            if !self.rts && drv.dma.dma_isr_tcif.read_bit() {
                 // Clear transfer completed interrupt flag.
                 drv.dma.dma_ifcr_ctcif.set_bit();
                 return Err(RxError::Overflow);
@@ -133,6 +408,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
 
             let cnt = self.copy_to(buf, self.first..end);
             self.first = (self.first + cnt) % self.ring_buf.len();
+            self.update_rts();
 
             Ok(cnt)
         } else {
@@ -144,7 +420,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
 
                 // Return a buffer overflow error if TCIF is asserted
                 // as the DMA controller in that case has wrapped.
-                if drv.dma.dma_isr_tcif.read_bit() {
+                if !self.rts && drv.dma.dma_isr_tcif.read_bit() {
                     // Clear transfer completed interrupt flag.
                     drv.dma.dma_ifcr_ctcif.set_bit();
                     return Err(RxError::Overflow);
@@ -153,6 +429,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
 
                 let cnt = self.copy_to(buf, self.first..self.ring_buf.len());
                 self.first = (self.first + cnt) % self.ring_buf.len();
+                self.update_rts();
 
                 Ok(cnt)
             } else {
@@ -161,7 +438,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
 
                 // Clear transfer completed interrupt flag.
                 drv.dma.dma_ifcr_ctcif.set_bit();
-                if self.last_read_wrapped {
+                if !self.rts && self.last_read_wrapped {
                     return Err(RxError::PossibleOverflow);
                 }
                 self.last_read_wrapped = true;
@@ -169,12 +446,91 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
                 let cnt_tail = self.copy_to(buf, self.first..self.ring_buf.len());
                 let cnt_head = self.copy_to(&mut buf[cnt_tail..], 0..end);
                 self.first = cnt_head;
+                self.update_rts();
 
                 Ok(cnt_tail + cnt_head)
             }
         }
     }
 
+    /// Read a frame from the rx ring buffer into `buf`,
+    /// i.e. wait until some bytes are received and the line has become idle for one frame time.
+    ///
+    /// Bytes that do not fit in `buf` are returned by the next read.
+    /// If the dma stream has failed then the reception is restarted with an empty ring buffer,
+    /// and [`RxError::Dma`] is returned.
+    pub async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, RxError> {
+        if let Some(Some(err)) = self.dma_err.next().now_or_never() {
+            self.restart();
+            return Err(RxError::Dma(err));
+        }
+
+        if let Err(err) = self.line_idle().await {
+            self.restart();
+            return Err(RxError::Dma(err));
+        }
+
+        self.read(buf).await
+    }
+
+    /// Let `read()` wait for an idle line instead of any reception when no bytes are readily available.
+    /// This takes a single interrupt per received burst instead of one per received byte.
+    pub fn set_idle_wakeup(&mut self, enabled: bool) {
+        self.idle_wakeup = enabled;
+    }
+
+    /// Substitute bytes received with a line error with `substitute` instead of returning framing, noise, and parity errors,
+    /// or return the errors again if `None`.
+    pub fn set_err_substitute(&mut self, substitute: Option<u8>) {
+        self.err_substitute = substitute;
+    }
+
+    /// Get the end of the available bytes in the ring buffer that are before the next line error,
+    /// or the line error if it is for the next byte.
+    fn limit_to_line_err(&mut self, end: usize) -> Result<usize, RxError> {
+        let ring_len = self.ring_buf.len();
+        loop {
+            if self.line_err.is_none() {
+                if let Some(Some(err)) = self.uart_err.next().now_or_never() {
+                    self.uart_err_queue.len.fetch_sub(1, Ordering::AcqRel);
+                    self.line_err = Some(err);
+                }
+            }
+            let (err, pos) = match self.line_err {
+                Some(line_err) => line_err,
+                None => return Ok(end),
+            };
+
+            let available = (end + ring_len - self.first) % ring_len;
+            let offset = (pos + ring_len - self.first) % ring_len;
+            if offset >= available {
+                // The errored byte is not yet available.
+                return Ok(end);
+            }
+            if offset > 0 {
+                // Read the bytes before the errored byte.
+                return Ok(pos);
+            }
+
+            // The errored byte is the next byte.
+            self.line_err = None;
+            match (err, self.err_substitute) {
+                (LineError::HardwareOverrun, _) => {
+                    // The next byte is valid, and was received after the lost byte.
+                    return Err(err.into());
+                }
+                (_, Some(substitute)) => {
+                    self.ring_buf[pos] = substitute;
+                }
+                (_, None) => {
+                    self.first = (pos + 1) % ring_len;
+                    self.update_rts();
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
     /// Copy from the rx ring buffer at `data_range` into `buf`.
     fn copy_to(&mut self, buf: &mut [u8], data_range: Range<usize>) -> usize {
         // Limit the number of bytes that can be copied.
@@ -187,24 +543,91 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
         cnt
     }
 
+    /// Restart the dma stream if it has stopped at the high-water mark or at the end of the ring buffer,
+    /// and `read()` has made room below the high-water mark.
+    fn update_rts(&mut self) {
+        let drv = self.drv;
+        if !self.rts || drv.dma.dma_ccr.en().read_bit() {
+            return;
+        }
+        let ring_len = self.ring_buf.len();
+        let start = self.window.pos::<DmaRx>(drv.dma.dma_cndtr, drv.dma.psize_bytes(), ring_len);
+        let used = (start + ring_len - self.first) % ring_len;
+        let room = ring_len / 2 - used;
+        if room > 0 {
+            // The stream does not wrap in normal mode.
+            self.setup_window(start, core::cmp::min(room, ring_len - start));
+        }
+    }
+
+    /// Setup and enable the dma stream to receive `len` bytes into the ring buffer from `start`.
+    fn setup_window(&self, start: usize, len: usize) {
+        self.window.start.store(start, Ordering::Release);
+        self.window.end.store(usize::MAX, Ordering::Release);
+        unsafe {
+            self.drv.dma.setup_stream(&self.ring_buf[start..start + len]);
+        }
+        self.window.end.store(start + len, Ordering::Release);
+    }
+
+    /// Setup and enable the dma stream to receive from the beginning of the ring buffer.
+    fn setup_stream(&self) {
+        // With rts flow control the stream stops at the high-water mark,
+        // after which the uart deasserts rts as its data register is no longer read.
+        let len = if self.rts { self.ring_buf.len() / 2 } else { self.ring_buf.len() };
+        self.setup_window(0, len);
+    }
+
     /// Start the uart and dma according to AN4031 §4.3.
     fn start(&mut self) {
         let drv = self.drv;
 
         // 1-2. Configure the dma stream and enable it.
-        unsafe {
-            drv.dma.setup_stream(self.ring_buf.as_ref());
-        }
+        // The stream runs in circular mode, or in single windows up to the high-water mark with rts.
+        let rts = self.rts;
+        drv.dma.dma_ccr.modify_reg(|r, v| {
+            if rts {
+                r.circ().clear(v);
+            } else {
+                r.circ().set(v);
+            }
+        });
+        self.setup_stream();
 
         // 3a. Configure uart to receive on DMA channel.
-        drv.uart.uart_cr3.modify_reg(|r, v| {
-            r.dmar().set(v);
-        });
+        drv.uart.uart_cr3.dmar().set_bit_band();
+        // Enable error interrupt (ORE, NF, FE) when receiving on the dma channel.
+        drv.uart.uart_cr3.eie().set_bit_band();
 
         // 3b. Enable receiver peripheral.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.re().set(v);
-        });
+        drv.uart.uart_cr1.re().set_bit_band();
+        // Enable parity error interrupt.
+        drv.uart.uart_cr1.peie().set_bit_band();
+    }
+
+    /// Restart the dma stream after an error, discarding the ring buffer contents.
+    fn restart(&mut self) {
+        let drv = self.drv;
+
+        // Disable the stream and clear its interrupt flags.
+        drv.dma.reset();
+        self.clear();
+
+        // Re-enable the stream.
+        self.setup_stream();
+    }
+
+    /// Discard the ring buffer contents.
+    fn clear(&mut self) {
+        self.first = 0;
+        self.last_read_wrapped = false;
+
+        // Discard line errors for the discarded bytes.
+        self.line_err = None;
+        while let Some(Some(_)) = self.uart_err.next().now_or_never() {
+            self.uart_err_queue.len.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.uart_err_queue.overflow.store(false, Ordering::Release);
     }
 
     /// Stop the uart and dma according to AN4031 §4.1.
@@ -222,23 +645,28 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
         }
 
         // 3a. Disable receiver.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.re().clear(v);
-        });
+        drv.uart.uart_cr1.re().clear_bit_band();
+        drv.uart.uart_cr1.peie().clear_bit_band();
 
         // 3b. Stop receive on DMA channel.
-        drv.uart.uart_cr3.modify_reg(|r, v| {
-            r.dmar().clear(v);
-        });
+        drv.uart.uart_cr3.dmar().clear_bit_band();
+        drv.uart.uart_cr3.eie().clear_bit_band();
     }
 
-    async fn any_rx_activity(&mut self, old_ndtr: usize) {
+    /// Wait for any byte to be received after the read position.
+    async fn any_rx_activity(&mut self) -> Result<(), DmaError> {
         let drv = self.drv;
+        let uart_cr1 = drv.uart.uart_cr1;
         let dma_cndtr = drv.dma.dma_cndtr;
+        let ring_len = self.ring_buf.len();
+        let psize = drv.dma.psize_bytes();
+        let window = self.window.clone();
+        let first = self.first;
         let any_rx = drv.uart_int.add_future(fib::new_fn(move || {
             // Note that we cannot clear the RXNE flag as it is automatically cleared by the DMA controller.
-            let new_ndtr = dma_cndtr.ndt().read_bits() as usize;
-            if new_ndtr != old_ndtr {
+            if window.pos::<DmaRx>(dma_cndtr, psize, ring_len) != first {
+                // Stop listen for activity, as RXNE stays set if the stream has stopped at the high-water mark.
+                uart_cr1.rxneie().clear_bit_band();
                 fib::Complete(())
             } else {
                 fib::Yielded(())
@@ -246,27 +674,128 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap>
         }));
 
         // Listen for any rx activity.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.rxneie().set(v);
-        });
-
-        let new_ndtr = drv.dma.dma_cndtr.ndt().read_bits() as usize;
-        if new_ndtr == old_ndtr {
-            // Wait for actitivy.
-            any_rx.await;
-        }
+        drv.uart.uart_cr1.rxneie().set_bit_band();
+
+        let end = self.window.pos::<DmaRx>(drv.dma.dma_cndtr, drv.dma.psize_bytes(), self.ring_buf.len());
+        let result = if end == self.first {
+            // Wait for actitivy, or for the dma stream to fail.
+            match future::select(any_rx, self.dma_err.next()).await {
+                Either::Right((Some(err), _)) => Err(err),
+                _ => Ok(()),
+            }
+        } else {
+            Ok(())
+        };
 
         // Stop listen for activity.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.rxneie().clear(v);
-        });
+        drv.uart.uart_cr1.rxneie().clear_bit_band();
+
+        result
+    }
+
+    /// Wait for the line to become idle after bytes not yet read have been received.
+    async fn line_idle(&mut self) -> Result<(), DmaError> {
+        let drv = self.drv;
+        let uart_sr = drv.uart.uart_sr;
+        let uart_dr = drv.uart.uart_dr;
+        let dma_cndtr = drv.dma.dma_cndtr;
+        let ring_len = self.ring_buf.len();
+        let psize = drv.dma.psize_bytes();
+        let window = self.window.clone();
+        let first = self.first;
+        let idle = drv.uart_int.add_future(fib::new_fn(move || {
+            let val = uart_sr.load_val();
+            if uart_sr.idle().read(&val) {
+                // The IDLE flag is cleared by reading SR followed by DR.
+                // DR is left for the dma controller if a new byte is already received,
+                // as the byte would otherwise be lost, and the read by the dma controller then clears the flag.
+                if !uart_sr.rxne().read(&val) {
+                    uart_dr.load_val();
+                }
+
+                // The flag may be stale from a burst that was already read.
+                let end = window.pos::<DmaRx>(dma_cndtr, psize, ring_len);
+                if end != first {
+                    return fib::Complete(());
+                }
+            }
+            fib::Yielded(())
+        }));
+
+        // Listen for idle line.
+        // The interrupt is triggered immediately if the IDLE flag is already set.
+        drv.uart.uart_cr1.idleie().set_bit_band();
+
+        // Wait for idle line, or for the dma stream to fail.
+        let result = match future::select(idle, self.dma_err.next()).await {
+            Either::Right((Some(err), _)) => Err(err),
+            _ => Ok(()),
+        };
+
+        // Stop listen for idle line.
+        drv.uart.uart_cr1.idleie().clear_bit_band();
+
+        result
     }
 }
 
-impl<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap> Drop for RxGuard<'_, Uart, UartInt, DmaRx> {
+impl<Uart: UartMap, UartInt: IntToken, DmaRx: DmaChMap, DmaRxInt: IntToken> Drop
+    for RxGuard<'_, Uart, UartInt, DmaRx, DmaRxInt>
+{
     /// Stop the receiver.
     fn drop(&mut self) {
         self.stop();
+        self.drv.uart.end_session();
+    }
+}
+
+impl<Uart: UartMap, DmaRx: DmaChMap, DmaRxInt: IntToken> UartRxDoubleBuf<Uart, DmaRx, DmaRxInt> {
+    /// Start the uart and dma in double buffer mode according to AN4031 §4.3.
+    pub(crate) fn start<DmaRxStCh: DmaStChToken>(
+        uart: Arc<UartDiverged<Uart>>,
+        rx_cfg: DmaChCfg<DmaRx, DmaRxStCh, DmaRxInt>,
+        bufs: [Box<[u8]>; 2],
+    ) -> Self
+    where
+        DmaRx: DmaChNum,
+    {
+        uart.start_session();
+
+        // 1-2. Configure the dma stream and enable it.
+        let dma = unsafe { DmaDoubleBufRx::start(rx_cfg, uart.uart_dr.as_mut_ptr() as u32, bufs) };
+
+        // 3a. Configure uart to receive on DMA channel.
+        uart.uart_cr3.dmar().set_bit_band();
+
+        // 3b. Enable receiver peripheral.
+        uart.uart_cr1.re().set_bit_band();
+
+        Self { uart, dma }
+    }
+
+    /// Wait for the next buffer to become full.
+    /// The buffer is handed back to the dma controller when the returned value is dropped,
+    /// which must happen before the other buffer becomes full, otherwise [`DoubleBufError::Overrun`] is reported.
+    pub async fn next(&mut self) -> Result<DmaBufFull<'_, DmaRx, DmaRxInt, u8>, DoubleBufError> {
+        self.dma.next().await
+    }
+}
+
+impl<Uart: UartMap, DmaRx: DmaChMap, DmaRxInt: IntToken> Drop
+    for UartRxDoubleBuf<Uart, DmaRx, DmaRxInt>
+{
+    /// Stop the receiver according to AN4031 §4.1.
+    fn drop(&mut self) {
+        // 1-2. Disable dma stream and wait until it is disabled.
+        self.dma.abort();
+
+        // 3a. Disable receiver.
+        self.uart.uart_cr1.re().clear_bit_band();
+
+        // 3b. Stop receive on DMA channel.
+        self.uart.uart_cr3.dmar().clear_bit_band();
+
+        self.uart.end_session();
     }
 }
 
@@ -2,6 +2,7 @@ use drone_cortexm::thr::IntToken;
 use drone_stm32f4_rcc_drv::{ConfiguredClk, clktree::PClkToken};
 use drone_stm32_map::periph::uart::UartPeriph;
 pub use crate::UartMap;
+use crate::pins::{UartFlowPins, UartFlowPinsDefined};
 
 /// Uart setup.
 pub struct UartSetup<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> {
@@ -21,6 +22,9 @@ pub struct UartSetup<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> {
     pub stop_bits: StopBits,
     /// Oversampling mode.
     pub oversampling: u32,
+    /// Hardware flow control, only supported by the usart peripherals.
+    /// Set with [`UartSetupFlowControl::flow_control`], which checks the rts/cts pins.
+    pub(crate) flow_control: FlowControl,
 }
 
 pub trait UartSetupInit<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> {
@@ -32,6 +36,29 @@ pub trait UartSetupInit<Uart: UartMap, UartInt: IntToken, Clk: PClkToken> {
     ) -> UartSetup<Uart, UartInt, Clk>;
 }
 
+pub trait UartSetupFlowControl<Uart: UartFlowControlMap> {
+    /// Enable hardware flow control for the configured rts and/or cts pins.
+    /// Only the usart peripherals support flow control, the uart peripherals do not have rts/cts signals.
+    fn flow_control<Rts, Cts>(self, pins: &UartFlowPins<Uart, Rts, Cts>) -> Self
+    where
+        UartFlowPins<Uart, Rts, Cts>: UartFlowPinsDefined;
+}
+
+/// A usart peripheral with rts/cts signals.
+pub trait UartFlowControlMap: UartMap {}
+
+impl<Uart: UartFlowControlMap, UartInt: IntToken, Clk: PClkToken> UartSetupFlowControl<Uart>
+    for UartSetup<Uart, UartInt, Clk>
+{
+    fn flow_control<Rts, Cts>(mut self, _pins: &UartFlowPins<Uart, Rts, Cts>) -> Self
+    where
+        UartFlowPins<Uart, Rts, Cts>: UartFlowPinsDefined,
+    {
+        self.flow_control = UartFlowPins::<Uart, Rts, Cts>::FLOW_CONTROL;
+        self
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum BaudRate {
     Nominal(u32),
@@ -59,6 +86,29 @@ pub enum StopBits {
     Two,
 }
 
+/// Uart hardware flow control.
+#[derive(Copy, Clone, PartialEq)]
+pub enum FlowControl {
+    /// No flow control.
+    None,
+    /// RTS flow control, where the receiver requests data only while it has room for it.
+    Rts,
+    /// CTS flow control, where the transmitter only sends while CTS is asserted.
+    Cts,
+    /// Both RTS and CTS flow control.
+    RtsCts,
+}
+
+impl FlowControl {
+    pub(crate) fn rts(self) -> bool {
+        self == FlowControl::Rts || self == FlowControl::RtsCts
+    }
+
+    pub(crate) fn cts(self) -> bool {
+        self == FlowControl::Cts || self == FlowControl::RtsCts
+    }
+}
+
 #[macro_export]
 macro_rules! uart_setup_init {
     ($uart:ident, $pclk:ident) => {
@@ -81,6 +131,7 @@ macro_rules! uart_setup_init {
                     parity: crate::Parity::None,
                     stop_bits: crate::StopBits::One,
                     oversampling: 16,
+                    flow_control: crate::FlowControl::None,
                 }
             }
         }
@@ -1,11 +1,11 @@
-use crate::diverged::{DmaChDiverged, UartDiverged};
+use crate::diverged::UartDiverged;
 use alloc::sync::Arc;
 use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::{
     dma::ch::DmaChMap,
     uart::{traits::*, UartMap},
 };
-use drone_stm32f4_dma_drv::{DmaChCfg, DmaStChToken};
+use drone_stm32f4_dma_drv::{config::DataSize, DmaChCfg, DmaChDiverged, DmaChNum, DmaError, DmaStChToken};
 
 pub struct UartTxDrv<Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToken> {
     pub(crate) uart: Arc<UartDiverged<Uart>>,
@@ -26,13 +26,21 @@ impl<Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToken>
         uart: Arc<UartDiverged<Uart>>,
         uart_int: UartInt,
         tx_cfg: DmaChCfg<DmaTx, DmaTxStCh, DmaTxInt>,
-    ) -> Self {
+    ) -> Self
+    where
+        DmaTx: DmaChNum,
+    {
         let DmaChCfg {
             dma_ch,
             dma_int,
             dma_pl,
+            dma_transfer,
             ..
         } = tx_cfg;
+        assert!(
+            dma_transfer.psize() == DataSize::Byte && dma_transfer.msize() == DataSize::Byte,
+            "The uart transmitter requires byte transfers"
+        );
         let tx = Self {
             uart,
             uart_int,
@@ -40,17 +48,16 @@ impl<Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToken>
             dma_int,
         };
         tx.dma
-            .init_dma_tx(tx.uart.uart_dr.as_mut_ptr() as u32, DmaTxStCh::NUM, dma_pl);
-        tx.dma.panic_on_err(dma_int);
+            .init_dma_tx(tx.uart.uart_dr.as_mut_ptr() as u32, DmaTxStCh::NUM, dma_pl, dma_transfer);
         tx
     }
 
     /// Enable tx operation for the uart peripheral and return a guard that disables the transmitter when dropped.
     pub fn start(&mut self) -> TxGuard<Uart, UartInt, DmaTx, DmaTxInt> {
+        self.uart.start_session();
+
         // Enable transmitter.
-        self.uart.uart_cr1.modify_reg(|r, v| {
-            r.te().set(v);
-        });
+        self.uart.uart_cr1.te().set_bit_band();
 
         TxGuard {
             drv: self,
@@ -66,17 +73,16 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToke
     ///
     /// The write future completes when the DMA transfer has completed,
     /// at which time the peripheral is ready for another invokation of write().
-    pub async fn write(&mut self, buf: &[u8]) {
+    /// A failed DMA transfer is reported as an error, and the peripheral is then ready for another write().
+    pub async fn write(&mut self, buf: &[u8]) -> Result<(), DmaError> {
         if buf.is_empty() {
-            return;
+            return Ok(());
         }
 
-        unsafe {
-            self.write_unsafe(buf).await;
-        }
+        unsafe { self.write_unsafe(buf).await }
     }
 
-    async unsafe fn write_unsafe(&mut self, buf: &[u8]) {
+    async unsafe fn write_unsafe(&mut self, buf: &[u8]) -> Result<(), DmaError> {
         let drv = self.drv;
         // PE (Parity error),
         // FE (Framing error),
@@ -94,17 +100,7 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToke
 
         // Start listen for DMA transfer to complete.
         // The transfer completes just after the second last byte is being sent on the wire.
-        let dma_isr_tcif = drv.dma.dma_isr_tcif;
-        let dma_ifcr_ctcif = drv.dma.dma_ifcr_ctcif;
-        let dma_tc = drv.dma_int.add_future(fib::new_fn(move || {
-            if dma_isr_tcif.read_bit() {
-                // Clear transfer completed interrupt flag.
-                dma_ifcr_ctcif.set_bit();
-                fib::Complete(())
-            } else {
-                fib::Yielded(())
-            }
-        }));
+        let dma_tc = drv.dma.transfer_future(drv.dma_int);
 
         // The uart transmission complete flag (TC) is cleared
         // by the sequence: Read status register (SR) and write data register (DR).
@@ -115,22 +111,26 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToke
         drv.dma.dma_ifcr_cfeif.set_bit();
 
         // Start transfer on DMA channel.
-        drv.uart.uart_cr3.modify_reg(|r, v| {
-            r.dmat().set(v);
-        });
+        drv.uart.uart_cr3.dmat().set_bit_band();
 
         self.busy = true;
 
         // Wait for DMA transfer to complete.
-        dma_tc.await;
+        let result = dma_tc.await;
+
+        if result.is_err() {
+            // The fiber completed with the error, so no fiber is left to miss the transfer complete flag
+            // set when the stream is disabled. Reset the stream so that the next transfer can proceed.
+            drv.dma.reset();
+        }
 
         // The peripheral automatically disables the DMA stream on completion without error,
         // but it does not clear the DMAT flag in CR3.
 
         // Stop transfer on DMA channel.
-        drv.uart.uart_cr3.modify_reg(|r, v| {
-            r.dmat().clear(v);
-        });
+        drv.uart.uart_cr3.dmat().clear_bit_band();
+
+        result
     }
 
     /// Wait for the uart peripheral to actually complete the transfer.
@@ -145,11 +145,13 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToke
         // 1) transmit buffer empty (TXE) is asserted, and
         // 2) transmission complete (TC) is asserted.
         let uart_sr = drv.uart.uart_sr;
+        let uart_cr1 = drv.uart.uart_cr1;
         let uart_tc = drv.uart_int.add_future(fib::new_fn(move || {
             let sr_val = uart_sr.load_val();
             if uart_sr.txe().read(&sr_val) && uart_sr.tc().read(&sr_val) {
-                // The TXE flag is automatically cleared.
-                uart_sr.tc().clear_bit();
+                // TC is left set while the transmitter is idle, and is cleared by the next write.
+                // Disable the interrupt so that it does not fire again.
+                uart_cr1.tcie().clear_bit_band();
                 fib::Complete(())
             } else {
                 fib::Yielded(())
@@ -158,18 +160,11 @@ impl<'sess, Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToke
 
         // Enable transmission complete interrupt.
         // This may fire immediately if the transmission is already completed.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.tcie().set(v);
-        });
+        drv.uart.uart_cr1.tcie().set_bit_band();
 
         // Wait for transfer to complete.
         uart_tc.await;
 
-        // Disable transmission complete interrupt.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.tcie().clear(v);
-        });
-
         // Wait for another call to write() before we need to wait in flush().
         self.busy = false;
     }
@@ -200,8 +195,8 @@ impl<Uart: UartMap, UartInt: IntToken, DmaTx: DmaChMap, DmaTxInt: IntToken> Drop
         }
 
         // Disable transmitter.
-        drv.uart.uart_cr1.modify_reg(|r, v| {
-            r.te().clear(v);
-        });
+        drv.uart.uart_cr1.te().clear_bit_band();
+
+        drv.uart.end_session();
     }
 }